@@ -0,0 +1,295 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{ball::{PaddleHit, Rally}, game_state::AppState, history::{opponent, opponents_of_player1, Opponent}, profile::Profiles, rules::{AiDifficulty, GameMode, GameRules}, score::MatchResult, Player};
+
+const LONG_RALLY: u32 = 50;
+// Has to stay below the fastest of rules::MAX_SPEED_OPTIONS, no ball goes faster than the speed limit.
+const FAST_RETURN: f32 = 2000.0;
+// How long each unlock stays on screen, in seconds.
+const TOAST_SECONDS: f32 = 3.0;
+
+pub struct AchievementsPlugin;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Achievement {
+    FirstWin,
+    Shutout,
+    LongRally,
+    BeatHardAi,
+    FastReturn,
+    LastOneStanding,
+}
+
+impl Achievement {
+    pub const ALL: [Achievement; 6] = [
+        Achievement::FirstWin,
+        Achievement::Shutout,
+        Achievement::LongRally,
+        Achievement::BeatHardAi,
+        Achievement::FastReturn,
+        Achievement::LastOneStanding,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Achievement::FirstWin => "First Win",
+            Achievement::Shutout => "Shutout",
+            Achievement::LongRally => "Marathon",
+            Achievement::BeatHardAi => "Giant Slayer",
+            Achievement::FastReturn => "Speed Demon",
+            Achievement::LastOneStanding => "Last One Standing",
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            Achievement::FirstWin => "Win a match".to_string(),
+            Achievement::Shutout => "Win without giving away a point".to_string(),
+            Achievement::LongRally => format!("Keep a rally going for {} hits", LONG_RALLY),
+            Achievement::BeatHardAi => "Beat the Hard AI".to_string(),
+            Achievement::FastReturn => format!("Return a ball faster than {:.0} px/s", FAST_RETURN),
+            Achievement::LastOneStanding => "Win a four player match".to_string(),
+        }
+    }
+}
+
+/// Sent the moment the active profile unlocks an achievement.
+pub struct AchievementUnlocked(pub Achievement);
+
+// Unlocks waiting to be shown, one at a time, with how long the current one has left.
+#[derive(Default)]
+struct Toasts {
+    queue: VecDeque<Achievement>,
+    remaining: f32,
+}
+
+struct ToastText;
+struct AchievementsText;
+
+impl Plugin for AchievementsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<Toasts>()
+            .add_event::<AchievementUnlocked>()
+            .add_startup_system(setup.system())
+            .add_system(rally_achievements.system())
+            .add_system(match_achievements.system())
+            .add_system(show_toasts.system())
+            .add_system(update_achievements_text.system())
+            .add_system_set(SystemSet::on_update(AppState::Start).with_system(open_achievements.system()))
+            .add_system_set(SystemSet::on_update(AppState::Achievements).with_system(close_achievements.system()));
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    windows: Res<Windows>,
+) {
+    let window = windows.get_primary().unwrap();
+
+    commands
+    .spawn_bundle(TextBundle {
+        style: Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                bottom: Val::Px(40.0),
+                left: Val::Px(window.width() / 2.0 - 160.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::default(),
+        ..Default::default()
+    })
+    .insert(ToastText);
+
+    commands
+    .spawn_bundle(TextBundle {
+        style: Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(60.0),
+                left: Val::Px(window.width() / 2.0 - 220.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::default(),
+        ..Default::default()
+    })
+    .insert(AchievementsText);
+}
+
+// Achievements belong to the active profile, so like the history they are only earned on the
+// left paddle with a human playing it.
+fn earning(rules: &GameRules) -> bool {
+    rules.controller(Player::Player1).is_human()
+}
+
+fn unlock(
+    achievement: Achievement,
+    profiles: &mut Profiles,
+    unlocked: &mut EventWriter<AchievementUnlocked>,
+) {
+    // Checking first keeps Profiles from being marked as changed, and saved, on every hit.
+    if profiles.active().achievements.contains(&achievement) {
+        return;
+    }
+    profiles.active_mut().achievements.push(achievement);
+    unlocked.send(AchievementUnlocked(achievement));
+}
+
+// A long rally counts whoever was hitting, it takes two to keep one going.
+fn rally_achievements(
+    mut paddle_hits: EventReader<PaddleHit>,
+    rally: Res<Rally>,
+    rules: Res<GameRules>,
+    mut profiles: ResMut<Profiles>,
+    mut unlocked: EventWriter<AchievementUnlocked>,
+) {
+    for hit in paddle_hits.iter() {
+        if !earning(&rules) {
+            continue;
+        }
+        if hit.player == Player::Player1 && hit.speed > FAST_RETURN {
+            unlock(Achievement::FastReturn, &mut profiles, &mut unlocked);
+        }
+        if rally.0 >= LONG_RALLY {
+            unlock(Achievement::LongRally, &mut profiles, &mut unlocked);
+        }
+    }
+}
+
+fn match_achievements(
+    mut match_results: EventReader<MatchResult>,
+    rules: Res<GameRules>,
+    mut profiles: ResMut<Profiles>,
+    mut unlocked: EventWriter<AchievementUnlocked>,
+) {
+    for result in match_results.iter() {
+        if !earning(&rules) || result.winner != Player::Player1 {
+            continue;
+        }
+
+        let opponents = opponents_of_player1(result);
+        let conceded = opponents.iter().any(|player| match player {
+            Player::Player1 => false,
+            Player::Player2 => result.player2 > 0,
+            Player::Player3 => result.player3 > 0,
+            Player::Player4 => result.player4 > 0,
+        });
+        let beat_hard_ai = opponents
+            .iter()
+            .any(|player| opponent(*player, result, &rules) == Opponent::Ai(AiDifficulty::Hard));
+
+        unlock(Achievement::FirstWin, &mut profiles, &mut unlocked);
+        // In elimination the scores are lives, so a shutout only means something in the other modes.
+        if !conceded && !rules.is_elimination() {
+            unlock(Achievement::Shutout, &mut profiles, &mut unlocked);
+        }
+        if beat_hard_ai {
+            unlock(Achievement::BeatHardAi, &mut profiles, &mut unlocked);
+        }
+        if result.mode == GameMode::FourPlayer {
+            unlock(Achievement::LastOneStanding, &mut profiles, &mut unlocked);
+        }
+    }
+}
+
+fn show_toasts(
+    time: Res<Time>,
+    mut unlocked: EventReader<AchievementUnlocked>,
+    mut toasts: ResMut<Toasts>,
+    asset_server: Res<AssetServer>,
+    mut query: Query<&mut Text, With<ToastText>>,
+) {
+    let was_empty = toasts.queue.is_empty();
+    toasts.queue.extend(unlocked.iter().map(|event| event.0));
+    if was_empty {
+        toasts.remaining = TOAST_SECONDS;
+    }
+
+    // Move on to the next unlock once the current one has been up long enough.
+    if !toasts.queue.is_empty() {
+        toasts.remaining -= time.delta_seconds();
+        if toasts.remaining <= 0.0 {
+            toasts.queue.pop_front();
+            toasts.remaining = TOAST_SECONDS;
+        }
+    }
+
+    if let Ok(mut text) = query.single_mut() {
+        text.sections.clear();
+        if let Some(achievement) = toasts.queue.front() {
+            text.sections.push(TextSection {
+                value: format!("Achievement unlocked: {}", achievement.name()),
+                style: TextStyle {
+                    font: asset_server.load("fonts/font.ttf"),
+                    font_size: 20.0,
+                    color: Color::YELLOW,
+                },
+            });
+        }
+    }
+}
+
+fn open_achievements(
+    keys: Res<Input<KeyCode>>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if keys.just_pressed(KeyCode::A) {
+        app_state.set(AppState::Achievements).unwrap();
+    }
+}
+
+fn close_achievements(
+    keys: Res<Input<KeyCode>>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if keys.just_pressed(KeyCode::Back) {
+        app_state.set(AppState::Start).unwrap();
+    }
+}
+
+fn update_achievements_text(
+    app_state: Res<State<AppState>>,
+    profiles: Res<Profiles>,
+    asset_server: Res<AssetServer>,
+    mut query: Query<&mut Text, With<AchievementsText>>,
+) {
+    if let Ok(mut text) = query.single_mut() {
+        if *app_state.current() != AppState::Achievements {
+            text.sections.clear();
+            return;
+        }
+
+        let profile = profiles.active();
+        let font = asset_server.load("fonts/font.ttf");
+        let style = |color: Color| TextStyle {
+            font: font.clone(),
+            font_size: 16.0,
+            color,
+        };
+
+        text.sections = vec![TextSection {
+            value: format!("{}: {} of {} achievements\n\n", profile.name, profile.achievements.len(), Achievement::ALL.len()),
+            style: style(Color::WHITE),
+        }];
+        text.sections.extend(Achievement::ALL.iter().map(|achievement| {
+            let unlocked = profile.achievements.contains(achievement);
+            TextSection {
+                value: format!("{} {} - {}\n", if unlocked { "[x]" } else { "[ ]" }, achievement.name(), achievement.description()),
+                style: style(if unlocked { Color::YELLOW } else { Color::GRAY }),
+            }
+        }));
+        text.sections.push(TextSection {
+            value: "\nBackspace to go back".to_string(),
+            style: style(Color::GRAY),
+        });
+    }
+}
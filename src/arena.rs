@@ -0,0 +1,257 @@
+use std::{fs, path::Path};
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::{Player, rules::GameRules, score::Scoreboard};
+
+const ARENA_DIRECTORY: &str = "assets/arenas";
+const WALL_THICKNESS: f32 = 200.0;
+
+pub struct ArenaPlugin;
+
+/// Anything the ball can bounce off or interact with, apart from the paddles.
+#[derive(Debug, Clone, Copy)]
+pub enum Collider {
+    Wall,
+    Block,
+    Bumper { boost: f32 },
+    Portal { exit: Vec2 },
+}
+
+// One of the four edges of the screen, with the player that defends it. An edge is a goal while
+// the game mode leaves it open and a wall otherwise.
+struct EdgeWall(Player);
+
+// Marks obstacles that belong to the current arena layout, so they can be cleared when it changes.
+struct ArenaObstacle;
+
+// Obstacles that slide back and forth between origin - offset and origin + offset.
+struct MovingObstacle {
+    origin: Vec2,
+    offset: Vec2,
+    period: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArenaDefinition {
+    pub name: String,
+    #[serde(default)]
+    pub obstacles: Vec<ObstacleDefinition>,
+}
+
+impl ArenaDefinition {
+    // Catches what the file format can't, so a bad arena is skipped instead of breaking the match.
+    fn validate(&self) -> Result<(), String> {
+        for obstacle in &self.obstacles {
+            if let Some(movement) = obstacle.movement {
+                if !movement.period.is_finite() || movement.period <= 0.0 {
+                    return Err(format!("movement period must be above zero, not {}", movement.period));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ObstacleDefinition {
+    pub kind: ObstacleKind,
+    pub position: (f32, f32),
+    pub size: (f32, f32),
+    #[serde(default)]
+    pub movement: Option<MovementDefinition>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum ObstacleKind {
+    Block,
+    Bumper { boost: f32 },
+    Portal { exit: (f32, f32) },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct MovementDefinition {
+    pub offset: (f32, f32),
+    pub period: f32,
+}
+
+/// Every arena that can be picked from the menu. The plain classic rectangle always comes
+/// first, followed by the layouts found in assets/arenas sorted by file name.
+pub struct Arenas(pub Vec<ArenaDefinition>);
+
+struct ArenaMaterials {
+    wall: Handle<ColorMaterial>,
+    block: Handle<ColorMaterial>,
+    bumper: Handle<ColorMaterial>,
+    portal: Handle<ColorMaterial>,
+}
+
+// Which entry of Arenas is currently spawned, if any.
+struct SpawnedArena(Option<usize>);
+
+impl Plugin for ArenaPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .insert_resource(load_arenas(Path::new(ARENA_DIRECTORY)))
+            .insert_resource(SpawnedArena(None))
+            .add_startup_system(setup.system())
+            .add_system(spawn_arena.system())
+            .add_system(update_edge_walls.system())
+            .add_system(move_obstacles.system());
+    }
+}
+
+fn load_arenas(directory: &Path) -> Arenas {
+    let mut arenas = vec![ArenaDefinition {
+        name: "Classic".to_string(),
+        obstacles: Vec::new(),
+    }];
+
+    let mut paths: Vec<_> = match fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+            .collect(),
+        Err(error) => {
+            warn!("Could not read arenas from {:?}: {}", directory, error);
+            Vec::new()
+        }
+    };
+    paths.sort();
+
+    for path in paths {
+        let arena = fs::read_to_string(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|contents| ron::de::from_str::<ArenaDefinition>(&contents).map_err(|error| error.to_string()))
+            .and_then(|arena| arena.validate().map(|_| arena));
+
+        match arena {
+            Ok(arena) => arenas.push(arena),
+            Err(error) => warn!("Skipping arena {:?}: {}", path, error),
+        }
+    }
+
+    Arenas(arenas)
+}
+
+fn setup(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    windows: Res<Windows>,
+) {
+    let window = windows.get_primary().unwrap();
+
+    let arena_materials = ArenaMaterials {
+        wall: materials.add(Color::rgb(1.0, 1.0, 1.0).into()),
+        block: materials.add(Color::rgb(0.6, 0.6, 0.6).into()),
+        bumper: materials.add(Color::rgb(1.0, 0.6, 0.1).into()),
+        portal: materials.add(Color::rgb(0.6, 0.3, 1.0).into()),
+    };
+
+    // The edge walls sit just outside the window. They are thick so a fast ball
+    // can't tunnel through them in a single frame.
+    let edges = [
+        (Player::Player1, Vec2::new(-(window.width() / 2.0 + WALL_THICKNESS / 2.0), 0.0), Vec2::new(WALL_THICKNESS, window.height() * 2.0)),
+        (Player::Player2, Vec2::new(window.width() / 2.0 + WALL_THICKNESS / 2.0, 0.0), Vec2::new(WALL_THICKNESS, window.height() * 2.0)),
+        (Player::Player3, Vec2::new(0.0, window.height() / 2.0 + WALL_THICKNESS / 2.0), Vec2::new(window.width() * 2.0, WALL_THICKNESS)),
+        (Player::Player4, Vec2::new(0.0, -(window.height() / 2.0 + WALL_THICKNESS / 2.0)), Vec2::new(window.width() * 2.0, WALL_THICKNESS)),
+    ];
+    for (player, translation, size) in edges.iter() {
+        commands.spawn_bundle(SpriteBundle {
+            material: arena_materials.wall.clone(),
+            transform: Transform::from_xyz(translation.x, translation.y, 0.0),
+            sprite: Sprite::new(*size),
+            ..Default::default()
+        })
+        .insert(EdgeWall(*player));
+    }
+
+    commands.insert_resource(arena_materials);
+}
+
+/// Whether the ball can leave through the edge this player defends. Goals are the edges the game mode
+/// leaves open, except that in elimination a player that is out of lives gets their goal closed.
+pub fn is_goal(player: Player, rules: &GameRules, scoreboard: &Scoreboard) -> bool {
+    let eliminated = rules.is_elimination() && scoreboard.score(player) == 0;
+    rules.mode.open_edges().contains(&player) && !eliminated
+}
+
+// Opens up the goals of the players that are in the match and closes the rest off with a wall.
+fn update_edge_walls(
+    mut commands: Commands,
+    rules: Res<GameRules>,
+    scoreboard: Res<Scoreboard>,
+    query: Query<(Entity, &EdgeWall, Option<&Collider>)>,
+) {
+    for (entity, edge, collider) in query.iter() {
+        let goal = is_goal(edge.0, &rules, &scoreboard);
+
+        if goal && collider.is_some() {
+            commands.entity(entity).remove::<Collider>();
+        } else if !goal && collider.is_none() {
+            commands.entity(entity).insert(Collider::Wall);
+        }
+    }
+}
+
+// Swaps the obstacles over whenever a different arena is picked in the menu.
+fn spawn_arena(
+    mut commands: Commands,
+    rules: Res<GameRules>,
+    arenas: Res<Arenas>,
+    arena_materials: Res<ArenaMaterials>,
+    mut spawned: ResMut<SpawnedArena>,
+    query: Query<Entity, With<ArenaObstacle>>,
+) {
+    if spawned.0 == Some(rules.arena) {
+        return;
+    }
+
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    if let Some(arena) = arenas.0.get(rules.arena) {
+        for obstacle in arena.obstacles.iter() {
+            let position = Vec2::new(obstacle.position.0, obstacle.position.1);
+
+            let (collider, material) = match obstacle.kind {
+                ObstacleKind::Block => (Collider::Block, arena_materials.block.clone()),
+                ObstacleKind::Bumper { boost } => (Collider::Bumper { boost }, arena_materials.bumper.clone()),
+                ObstacleKind::Portal { exit } => (
+                    Collider::Portal { exit: Vec2::new(exit.0, exit.1) },
+                    arena_materials.portal.clone(),
+                ),
+            };
+
+            let mut entity = commands.spawn_bundle(SpriteBundle {
+                material,
+                transform: Transform::from_xyz(position.x, position.y, 5.0),
+                sprite: Sprite::new(Vec2::new(obstacle.size.0, obstacle.size.1)),
+                ..Default::default()
+            });
+            entity.insert(collider).insert(ArenaObstacle);
+
+            if let Some(movement) = obstacle.movement {
+                entity.insert(MovingObstacle {
+                    origin: position,
+                    offset: Vec2::new(movement.offset.0, movement.offset.1),
+                    period: movement.period,
+                });
+            }
+        }
+    }
+
+    spawned.0 = Some(rules.arena);
+}
+
+fn move_obstacles(time: Res<Time>, mut query: Query<(&MovingObstacle, &mut Transform)>) {
+    let seconds = time.seconds_since_startup() as f32;
+
+    for (obstacle, mut transform) in query.iter_mut() {
+        let phase = (seconds / obstacle.period * std::f32::consts::TAU).sin();
+        transform.translation.x = obstacle.origin.x + obstacle.offset.x * phase;
+        transform.translation.y = obstacle.origin.y + obstacle.offset.y * phase;
+    }
+}
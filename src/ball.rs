@@ -1,26 +1,67 @@
-use bevy::{prelude::*, sprite::collide_aabb::collide};
+use bevy::{prelude::*, sprite::collide_aabb::{collide, Collision}};
 use rand::{thread_rng, Rng};
 
-use crate::{game_state::AppState, paddle::{Paddle, PADDLE_SCALE_Y, PADDLE_SCALE_X}, Player, LoadedAudio};
+use pong50::simulation::{BALL_INITIAL_Y_MAX, BALL_INITIAL_Y_MIN, BALL_SIZE};
 
-pub const BALL_INITIAL_X_MIN: f32 = 140.0;
-pub const BALL_INITIAL_X_MAX: f32 = 200.0;
-pub const BALL_INITIAL_Y_MIN: f32 = -50.0;
-pub const BALL_INITIAL_Y_MAX: f32 = 50.0;
-pub const BALL_SCALE: f32 = 15.0;
-const BOUNCE_VELOCITY_INCREASE: f32 = 1.1;
+use crate::{arena::{Collider, is_goal}, game_state::AppState, paddle::Paddle, rules::{GameRules, Movement}, score::Scoreboard, scripts::{Scripts, hook_or, script_ball, script_paddle}, Player, LoadedAudio};
+
+// A hit this far from the middle of the paddle, as a fraction of half its length, is an edge hit.
+const EDGE_HIT_FRACTION: f32 = 0.75;
+// How much a spinning ball curves. Its path turns by this fraction of the spin every second.
+const SPIN_CURVE: f32 = 0.03;
+// Spin the ball picks up for every pixel per second the paddle is moving as it hits.
+const SPIN_FROM_PADDLE: f32 = 0.02;
+// The share of its spin the ball loses every second in the air, and the share it keeps through a wall bounce.
+const SPIN_DRAG: f32 = 0.2;
+const SPIN_KEPT_ON_BOUNCE: f32 = 0.5;
+// Sideways speed, in pixels per second, a bounce turns each radian per second of spin into.
+const SPIN_KICK: f32 = 4.0;
+// With momentum, the share of the paddle's own speed the ball picks up along the paddle as it's hit.
+const PADDLE_CARRY: f32 = 0.3;
+// Characters in the speed bar on the HUD.
+const SPEED_BAR_LENGTH: usize = 10;
 
 pub struct BallPlugin;
 
 pub struct Ball {
     pub velocity: Vec2,
+    /// The last player whose paddle touched this ball, used for scoring in the four player mode.
+    pub last_hit: Option<Player>,
+    /// How fast the ball is spinning, in radians per second. Anticlockwise spin curves it to the left of
+    /// where it is heading, clockwise spin to the right.
+    pub spin: f32,
+    /// How fast the ball was served. Every paddle hit speeds it up from there, along GameRules::speed_curve.
+    pub serve_speed: f32,
+    /// Paddle hits since the ball was served.
+    pub hits: u32,
+}
+
+/// Paddle hits since the last serve, counting every ball in play.
+#[derive(Default)]
+pub struct Rally(pub u32);
+
+/// Sent every time a ball comes off a paddle.
+pub struct PaddleHit {
+    pub player: Player,
+    /// The ball caught the end of the paddle rather than the middle.
+    pub edge: bool,
+    /// How fast the ball is leaving the paddle.
+    pub speed: f32,
 }
 
+// Balls come and go during a match now, so the material is kept around for spawning them.
+pub struct BallMaterial(pub Handle<ColorMaterial>);
+
+struct SpeedText;
+
 impl Plugin for BallPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app
+            .init_resource::<Rally>()
+            .add_event::<PaddleHit>()
             .add_startup_system(setup.system())
             .add_system(ball_collision.system())
+            .add_system(update_speed_text.system())
             .add_system_set(SystemSet::on_update(AppState::Play).with_system(ball_movement.system()));
     }
 }
@@ -29,107 +70,335 @@ fn setup(
     mut commands: Commands,
     mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
-    // Ball
+    commands.insert_resource(BallMaterial(materials.add(Color::rgb(1.0, 0.5, 0.5).into())));
+
+    // How fast the fastest ball is going, next to the speed limit.
+    commands
+    .spawn_bundle(TextBundle {
+        style: Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                bottom: Val::Px(5.0),
+                left: Val::Px(15.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::default(),
+        ..Default::default()
+    })
+    .insert(SpeedText);
+}
+
+pub fn spawn_ball(
+    commands: &mut Commands,
+    material: &BallMaterial,
+    translation: Vec2,
+    velocity: Vec2,
+    spin: f32,
+) -> Entity {
     commands.spawn_bundle(SpriteBundle {
-        material: materials.add(Color::rgb(1.0, 0.5, 0.5).into()),
-        transform: Transform::from_xyz(0.0, 0.0, 1.0),
-        sprite: Sprite::new(Vec2::new(BALL_SCALE, BALL_SCALE)),
+        material: material.0.clone(),
+        transform: Transform::from_xyz(translation.x, translation.y, 1.0),
+        sprite: Sprite::new(Vec2::new(BALL_SIZE, BALL_SIZE)),
         ..Default::default()
     })
-    .insert( Ball{ velocity: Vec2::new(0.0,0.0) });
+    .insert( Ball{ velocity, last_hit: None, spin, serve_speed: velocity.length(), hits: 0 })
+    .id()
 }
 
-fn ball_movement(time: Res<Time>, mut query: Query<(&Ball, &mut Transform)>) {
-    if let Ok((ball, mut transform)) = query.single_mut() {
+// A spinning ball curves the way it spins, Magnus style, and slowly loses its spin. The sprite turns
+// with it so you can see which way it's going to go.
+fn ball_movement(time: Res<Time>, rules: Res<GameRules>, mut query: Query<(&mut Ball, &mut Transform)>) {
+    let delta_seconds = time.delta_seconds();
+    for (mut ball, mut transform) in query.iter_mut() {
+        if ball.spin != 0.0 && rules.spin > 0.0 {
+            let (sin, cos) = (ball.spin * SPIN_CURVE * rules.spin * delta_seconds).sin_cos();
+            let velocity = ball.velocity;
+            ball.velocity = Vec2::new(velocity.x * cos - velocity.y * sin, velocity.x * sin + velocity.y * cos);
+            transform.rotate(Quat::from_rotation_z(ball.spin * delta_seconds));
+            ball.spin *= (1.0 - SPIN_DRAG * delta_seconds).max(0.0);
+        }
         transform.translation.x += ball.velocity.x * time.delta_seconds();
         transform.translation.y += ball.velocity.y * time.delta_seconds();
     }
 }
 
-// I needed to use a QuerySet here because I need a mutable reference to the Transform for the Ball, and 
-// I also need an immutable reference to the Transform for the paddle. You can't have an immutable and mutable
+// I needed to use a QuerySet here because I need a mutable reference to the Transform for the Balls, and 
+// I also need an immutable reference to the Transform for the paddles and colliders. You can't have an immutable and mutable
 // reference of the same component at the same time. The problem is You also can't nest these Queries now because
-// you can only have one reference to the QuerySet at a time. So I use the first Query to ensure that we have a ball still
-// as well as saving the balls translation locally that I need to use in the second Query when I check for collision with the paddles.
-// I go throught the second Query and check for collisions. Depending on which player I collided with, I need to move the balls
-// x position to the left or right. So I also save that data locally as a boolean. Finally I use the first Query again and this 
-// time I actually apply the changes needed to the balls translation and velocity.
+// you can only have one reference to the QuerySet at a time. So I use the first Query to save every balls entity 
+// and translation locally, which I need in the other Queries when I check for collisions.
+// I go throught the paddles and the colliders and check each ball against them. Depending on which player it collided with, I need 
+// to move that ball away from the paddle, and for colliders I need to know which side got hit. So I also save that 
+// data locally per ball. Finally I use the first Query again and this time I actually apply the changes needed to each balls 
+// translation and velocity.
 fn ball_collision(
     mut q: QuerySet<(
-        Query<(&mut Transform, &mut Ball)>,
-        Query<(&Paddle, &Transform)>,
+        Query<(Entity, &mut Transform, &mut Ball)>,
+        Query<(&Paddle, &Transform, &Sprite)>,
+        Query<(&Collider, &Transform, &Sprite)>,
     )>,
-    windows: Res<Windows>,
     audio: Res<Audio>,
     loaded_audio: Res<LoadedAudio>,
+    app_state: Res<State<AppState>>,
+    mut rally: ResMut<Rally>,
+    mut paddle_hits: EventWriter<PaddleHit>,
+    scripts: Res<Scripts>,
+    rules: Res<GameRules>,
+    scoreboard: Res<Scoreboard>,
+    windows: Res<Windows>,
 ) {
-
-    let mut ball_translation = Vec3::new(0.0, 0.0, 0.0);
-    let mut found_ball = false;
-
-    if let Ok((ball_transform, _)) = q.q0_mut().single_mut() {
-        found_ball = true;
-        ball_translation = ball_transform.translation;
+    // Online matches are run by the rollback session or the server instead.
+    if app_state.current().is_networked() {
+        return;
     }
+    let window = windows.get_primary().unwrap();
+    let inside = Vec2::new(window.width(), window.height()) / 2.0 - Vec2::new(BALL_SIZE, BALL_SIZE) / 2.0;
+
+    let balls: Vec<(Entity, Vec3)> = q
+        .q0_mut()
+        .iter_mut()
+        .map(|(entity, ball_transform, _)| (entity, ball_transform.translation))
+        .collect();
 
-    // I make sure we found the ball so I don't check collisions against a ball that doesn't exist.
-    if found_ball {
-        let mut collided_player_1 = false;
-        let mut collided_player_2 = false;
+    for (entity, ball_translation) in balls {
+        let mut collided_player = None;
+        let mut edge = false;
+        let mut paddle_velocity = 0.0;
+        // The paddle that got hit, for the match script's bounce hook.
+        let mut hit_paddle = None;
 
-        for (paddle, paddle_transform) in q.q1().iter() {
+        for (paddle, paddle_transform, paddle_sprite) in q.q1().iter() {
             let collides = collide(
                 ball_translation, 
-                Vec2::new(BALL_SCALE, BALL_SCALE),
+                Vec2::new(BALL_SIZE, BALL_SIZE),
                 paddle_transform.translation,
-                Vec2::new(PADDLE_SCALE_X, PADDLE_SCALE_Y));
+                paddle_sprite.size);
             
-            match collides {
-                Some(collision) => {
-                    match collision {
-                        _ => {
-                            match paddle.player {
-                                Player::Player1 => {
-                                    collided_player_1 = true;
-                                }
-                                Player::Player2 => {
-                                    collided_player_2 = true;
-                                }
+            if collides.is_some() {
+                collided_player = Some(paddle.player);
+                paddle_velocity = paddle.velocity;
+                hit_paddle = Some(script_paddle(paddle.player, paddle_transform.translation.truncate(), paddle_sprite.size));
+                let (offset, half_length) = if paddle.player.is_horizontal() {
+                    (ball_translation.x - paddle_transform.translation.x, paddle_sprite.size.x / 2.0)
+                } else {
+                    (ball_translation.y - paddle_transform.translation.y, paddle_sprite.size.y / 2.0)
+                };
+                edge = offset.abs() > half_length * EDGE_HIT_FRACTION;
+            }
+        }
+
+        let mut collider_hits: Vec<(Collider, Collision)> = Vec::new();
+        for (collider, collider_transform, collider_sprite) in q.q2().iter() {
+            if let Some(collision) = collide(
+                ball_translation,
+                Vec2::new(BALL_SIZE, BALL_SIZE),
+                collider_transform.translation,
+                collider_sprite.size)
+            {
+                collider_hits.push((*collider, collision));
+            }
+        }
+
+        if let Ok((_, mut ball_transform, mut ball)) = q.q0_mut().get_mut(entity) {
+            for (collider, collision) in collider_hits {
+                match collider {
+                    Collider::Portal { exit } => {
+                        ball_transform.translation.x = exit.x;
+                        ball_transform.translation.y = exit.y;
+                    }
+                    _ => {
+                        // Only bounce if the ball is still heading into the collider. Otherwise it would flip
+                        // back and forth every frame while it is overlapping.
+                        if bounce(&mut ball.velocity, &collision) {
+                            spin_kick(&mut ball, &collision, rules.spin);
+                            if let Collider::Bumper { boost } = collider {
+                                ball.velocity *= boost;
                             }
+                            // Spin and bumpers both add speed, neither gets to take the ball past the limit.
+                            ball.velocity = limit_speed(ball.velocity, rules.max_speed);
+                            audio.play(loaded_audio.wall_hit.clone());
                         }
                     }
                 }
-                None => (),
             }
-        }
 
-        if let Ok((mut ball_transform, mut ball)) = q.q0_mut().single_mut() {
-            let window = windows.get_primary().unwrap();
-
-            if ball_transform.translation.y > window.height() / 2.0 - BALL_SCALE / 2.0 {
-                audio.play(loaded_audio.wall_hit.clone());
-                ball.velocity.y = -ball.velocity.y;
-            }
-            if ball_transform.translation.y < -window.height() / 2.0 + BALL_SCALE / 2.0 {
-                audio.play(loaded_audio.wall_hit.clone());
-                ball.velocity.y = -ball.velocity.y;
+            // collide can't see a ball that went all the way into a wall in one long frame, so any ball
+            // that ended up past an edge that isn't a goal is put back and bounced here as well.
+            for player in Player::ALL.iter().copied().filter(|player| !is_goal(*player, &rules, &scoreboard)) {
+                let translation = &mut ball_transform.translation;
+                let collision = match player {
+                    Player::Player1 if translation.x < -inside.x => {
+                        translation.x = -inside.x;
+                        Collision::Right
+                    }
+                    Player::Player2 if translation.x > inside.x => {
+                        translation.x = inside.x;
+                        Collision::Left
+                    }
+                    Player::Player3 if translation.y > inside.y => {
+                        translation.y = inside.y;
+                        Collision::Bottom
+                    }
+                    Player::Player4 if translation.y < -inside.y => {
+                        translation.y = -inside.y;
+                        Collision::Top
+                    }
+                    _ => continue,
+                };
+                if bounce(&mut ball.velocity, &collision) {
+                    spin_kick(&mut ball, &collision, rules.spin);
+                    ball.velocity = limit_speed(ball.velocity, rules.max_speed);
+                    audio.play(loaded_audio.wall_hit.clone());
+                }
             }
 
-            if collided_player_1 {
-                ball_transform.translation.x += BALL_SCALE / 2.0;
-                ball.velocity.x = BOUNCE_VELOCITY_INCREASE * -ball.velocity.x;
-            }
-            if collided_player_2 {
-                ball_transform.translation.x -= BALL_SCALE / 2.0;
-                ball.velocity.x = BOUNCE_VELOCITY_INCREASE * -ball.velocity.x;
+            match collided_player {
+                Some(Player::Player1) => {
+                    ball_transform.translation.x += BALL_SIZE / 2.0;
+                    ball.velocity.x = -ball.velocity.x;
+                }
+                Some(Player::Player2) => {
+                    ball_transform.translation.x -= BALL_SIZE / 2.0;
+                    ball.velocity.x = -ball.velocity.x;
+                }
+                Some(Player::Player3) => {
+                    ball_transform.translation.y -= BALL_SIZE / 2.0;
+                    ball.velocity.y = -ball.velocity.y;
+                }
+                Some(Player::Player4) => {
+                    ball_transform.translation.y += BALL_SIZE / 2.0;
+                    ball.velocity.y = -ball.velocity.y;
+                }
+                None => (),
             }
-            if collided_player_1 || collided_player_2 {
-                // randomize ball y velocity
+            if let Some(player) = collided_player {
+                // randomize the balls velocity along the paddle
                 let mut rng = thread_rng();
-                let ball_y = rng.gen_range(BALL_INITIAL_Y_MIN..BALL_INITIAL_Y_MAX);
-                ball.velocity.y = if ball.velocity.y > 0.0 { ball_y } else { -ball_y };
+                let random = rng.gen_range(BALL_INITIAL_Y_MIN..BALL_INITIAL_Y_MAX);
+                if player.is_horizontal() {
+                    ball.velocity.x = if ball.velocity.x > 0.0 { random } else { -random };
+                } else {
+                    ball.velocity.y = if ball.velocity.y > 0.0 { random } else { -random };
+                }
+                // A paddle with momentum swings the ball along with it. The speed curve below still decides
+                // how fast it goes, this only changes the direction.
+                if rules.movement == Movement::Momentum {
+                    let carry = paddle_velocity * PADDLE_CARRY;
+                    if player.is_horizontal() {
+                        ball.velocity.x += carry;
+                    } else {
+                        ball.velocity.y += carry;
+                    }
+                }
+                // This is the only place a ball speeds up over a rally. It starts over with every serve,
+                // since that's a new ball.
+                ball.hits += 1;
+                // The carry can cancel out everything else, and a ball with no direction would turn into NaN.
+                // Send it straight out from the paddle instead.
+                let direction = ball.velocity.try_normalize().unwrap_or(match player {
+                    Player::Player1 => Vec2::X,
+                    Player::Player2 => -Vec2::X,
+                    Player::Player3 => -Vec2::Y,
+                    Player::Player4 => Vec2::Y,
+                });
+                ball.velocity = direction * rules.rally_speed(ball.serve_speed, ball.hits);
+                // The match script gets the last word on where the ball goes, as long as it keeps to the speed limit.
+                if let (Some(script), Some(paddle)) = (scripts.active(&rules), &hit_paddle) {
+                    let leaving = script_ball(ball_transform.translation.truncate(), ball.velocity, ball.last_hit);
+                    let velocity: Vec2 = hook_or(script.bounce(&leaving, paddle), ball.velocity.into()).into();
+                    ball.velocity = limit_speed(velocity, rules.max_speed);
+                }
+                // Whatever spin the ball came in with, it leaves with the spin the paddle's own movement
+                // put on it. Brushing up the side of the ball turns it one way, brushing down the other.
+                let leaving = if player.is_horizontal() {
+                    Vec2::new(0.0, ball.velocity.y.signum())
+                } else {
+                    Vec2::new(ball.velocity.x.signum(), 0.0)
+                };
+                let paddle_velocity = if player.is_horizontal() {
+                    Vec2::new(paddle_velocity, 0.0)
+                } else {
+                    Vec2::new(0.0, paddle_velocity)
+                };
+                ball.spin = -leaving.perp_dot(paddle_velocity) * SPIN_FROM_PADDLE * rules.spin;
+                ball.last_hit = Some(player);
+                rally.0 += 1;
+                paddle_hits.send(PaddleHit { player, edge, speed: ball.velocity.length() });
                 audio.play(loaded_audio.paddle_hit.clone());
             }
         }
     }
 }
+
+// Slows the velocity down to max_speed, if it was going any faster.
+fn limit_speed(velocity: Vec2, max_speed: f32) -> Vec2 {
+    let speed = velocity.length();
+    if speed > max_speed {
+        velocity * (max_speed / speed)
+    } else {
+        velocity
+    }
+}
+
+// A spinning ball grips whatever it bounces off, which turns some of the spin into speed along the
+// surface: topspin kicks forward off a wall, backspin holds the ball back. The collision says which side
+// of the collider the ball is on, and so which way it is now leaving.
+fn spin_kick(ball: &mut Ball, collision: &Collision, strength: f32) {
+    let normal = match collision {
+        Collision::Left => Vec2::new(-1.0, 0.0),
+        Collision::Right => Vec2::new(1.0, 0.0),
+        Collision::Top => Vec2::new(0.0, 1.0),
+        Collision::Bottom => Vec2::new(0.0, -1.0),
+    };
+    ball.velocity -= Vec2::new(normal.y, -normal.x) * ball.spin * SPIN_KICK * strength;
+    ball.spin *= SPIN_KEPT_ON_BOUNCE;
+}
+
+// Sends the ball away from the side of the collider it hit. The collision tells us which side
+// of the collider the ball is on. Returns false if the ball was already moving away.
+fn bounce(velocity: &mut Vec2, collision: &Collision) -> bool {
+    match collision {
+        Collision::Left if velocity.x > 0.0 => velocity.x = -velocity.x,
+        Collision::Right if velocity.x < 0.0 => velocity.x = -velocity.x,
+        Collision::Bottom if velocity.y > 0.0 => velocity.y = -velocity.y,
+        Collision::Top if velocity.y < 0.0 => velocity.y = -velocity.y,
+        _ => return false,
+    }
+    true
+}
+
+// Shown while a match is being played here. The bar fills up as the fastest ball gets closer to the speed limit.
+fn update_speed_text(
+    app_state: Res<State<AppState>>,
+    rules: Res<GameRules>,
+    balls: Query<&Ball>,
+    asset_server: Res<AssetServer>,
+    mut query: Query<&mut Text, With<SpeedText>>,
+) {
+    if let Ok(mut text) = query.single_mut() {
+        if !matches!(app_state.current(), AppState::Serve | AppState::Play) {
+            text.sections.clear();
+            return;
+        }
+
+        let speed = balls.iter().map(|ball| ball.velocity.length()).fold(0.0, f32::max);
+        let filled = ((speed / rules.max_speed).min(1.0) * SPEED_BAR_LENGTH as f32).round() as usize;
+        text.sections = vec![TextSection {
+            value: format!(
+                "Speed [{}{}] {:.0} / {:.0}",
+                "=".repeat(filled),
+                "-".repeat(SPEED_BAR_LENGTH - filled),
+                speed,
+                rules.max_speed,
+            ),
+            style: TextStyle {
+                font: asset_server.load("fonts/font.ttf"),
+                font_size: 15.0,
+                color: if filled == SPEED_BAR_LENGTH { Color::ORANGE_RED } else { Color::WHITE },
+            },
+        }];
+    }
+}
@@ -0,0 +1,37 @@
+use pong50::gym::{chase_input, evaluate, serve_json, EnvConfig, PongEnv};
+
+// With --benchmark N the game's own AI plays N episodes against the opponent, as a baseline for
+// trained agents. Otherwise the environment is served as JSON lines over stdin and stdout.
+fn main() {
+    let mut args: Vec<String> = std::env::args().collect();
+    let benchmark = match args.iter().position(|arg| arg == "--benchmark") {
+        Some(index) if index + 1 < args.len() => {
+            let episodes = args.remove(index + 1);
+            args.remove(index);
+            Some(episodes.parse::<u32>().unwrap_or_else(|_| {
+                eprintln!("--benchmark needs a number of episodes");
+                std::process::exit(1);
+            }))
+        }
+        _ => None,
+    };
+
+    let config = EnvConfig::from_args(args.into_iter()).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        eprintln!("usage: pong50-gym [--seed 0] [--side left|right] [--opponent chase|idle] [--slowdown 0.0] [--victory 2] [--max-steps 18000] [--benchmark episodes]");
+        std::process::exit(1);
+    });
+    let mut env = PongEnv::new(config);
+
+    if let Some(episodes) = benchmark {
+        let evaluation = evaluate(&mut env, episodes, |observation| chase_input(observation.paddle, observation.ball_position[1]));
+        println!("{:?}", evaluation);
+        return;
+    }
+
+    let stdin = std::io::stdin();
+    if let Err(error) = serve_json(&mut env, stdin.lock(), std::io::stdout()) {
+        eprintln!("Gym stopped: {}", error);
+        std::process::exit(1);
+    }
+}
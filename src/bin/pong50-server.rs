@@ -0,0 +1,17 @@
+use bevy::{app::App, log::{info, LogPlugin}};
+use pong50::server::{DedicatedServer, ServerConfig};
+
+fn main() {
+    let config = ServerConfig::from_args(std::env::args()).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        eprintln!("usage: pong50-server [--bind 0.0.0.0:7100] [--seed 0]");
+        std::process::exit(1);
+    });
+
+    // The server logs through bevy like the game does. Without a window there is nothing else to the App.
+    App::build().add_plugin(LogPlugin);
+
+    let mut server = DedicatedServer::bind(&config).expect("could not open the server sockets");
+    info!("Pong50 server listening on {}", server.local_addr().map_or(config.bind, |address| address));
+    server.run();
+}
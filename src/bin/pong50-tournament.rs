@@ -0,0 +1,45 @@
+use std::fs;
+
+use pong50::tournament::{run, TournamentConfig};
+
+// Runs the whole tournament, prints the standings and writes them out if asked to.
+fn main() {
+    let config = TournamentConfig::from_args(std::env::args()).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        eprintln!("usage: pong50-tournament [--format round-robin|bracket] [--matches 10] [--seed 0] [--victory 2] [--max-ticks 36000] [--bot-timeout 10] [--csv standings.csv] [--json results.json] entrant entrant...");
+        eprintln!("entrants: idle, chase, chase:0.3, predictive, script:assets/scripts/goalkeeper.rhai, bot:./my-bot, bot:tcp:127.0.0.1:7200");
+        std::process::exit(1);
+    });
+
+    let report = run(&config).unwrap_or_else(|error| {
+        eprintln!("Tournament stopped: {}", error);
+        std::process::exit(1);
+    });
+
+    if !report.rounds.is_empty() {
+        for (round, pairings) in report.rounds.iter().enumerate() {
+            println!("Round {}", round + 1);
+            for pairing in pairings {
+                match &pairing.second {
+                    Some(second) => println!("  {} {} - {} {}, {} goes through", pairing.first, pairing.wins[0], pairing.wins[1], second, pairing.winner),
+                    None => println!("  {} has a bye", pairing.first),
+                }
+            }
+        }
+        println!();
+    }
+    print!("{}", report.table());
+    if let Some(champion) = &report.champion {
+        println!("\n{} wins the tournament", champion);
+    }
+
+    let outputs = [(&config.csv, Ok(report.csv())), (&config.json, report.json())];
+    for (path, contents) in outputs.iter() {
+        if let Some(path) = path {
+            if let Err(error) = contents.clone().and_then(|contents| fs::write(path, contents).map_err(|error| error.to_string())) {
+                eprintln!("Could not write {:?}: {}", path, error);
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// Lets a program outside the game play a paddle. Every tick the game writes one JSON line describing
+// the match to the bot and waits a short while for a JSON line back saying how to move. A bot that is
+// too slow, or has gone away, simply doesn't move that tick, so a broken bot can't hold up the match.
+//
+// A bot is either a program we start and talk to over its stdin and stdout, or something already
+// listening on a TCP port.
+
+use std::{io::{self, BufRead, BufReader, Write}, net::{Shutdown, SocketAddr, TcpStream}, process::{Child, Command, Stdio}, sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError}, thread, time::{Duration, Instant}};
+
+use serde::{Deserialize, Serialize};
+
+/// How long we wait for a bot to answer before its paddle stands still for the tick.
+pub const DEFAULT_BOT_TIMEOUT: Duration = Duration::from_millis(10);
+// Observations waiting to be written to a bot. A bot that falls this far behind isn't reading, so
+// further observations are dropped until it catches up.
+const MAX_QUEUED_OBSERVATIONS: usize = 4;
+
+/// Where to find a bot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BotEndpoint {
+    /// A program and its arguments.
+    Process(Vec<String>),
+    Tcp(SocketAddr),
+}
+
+/// The bots asked for on the command line, indexed like players: left, right, top and bottom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BotConfig {
+    pub bots: [Option<BotEndpoint>; 4],
+    pub timeout: Duration,
+}
+
+impl BotConfig {
+    /// `--bot left=./my-bot --bot "right=python3 bot.py" --bot top=tcp:127.0.0.1:7200 --bot-timeout 10`,
+    /// with the timeout in milliseconds. Anything else on the command line is left for someone else.
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<BotConfig, String> {
+        let mut config = BotConfig { bots: [None, None, None, None], timeout: DEFAULT_BOT_TIMEOUT };
+
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+            match arg.as_str() {
+                "--bot" => {
+                    let value = value("--bot")?;
+                    let (side, endpoint) = value.split_once('=').ok_or_else(|| format!("--bot wants side=bot, not {}", value))?;
+                    let index = match side {
+                        "left" => 0,
+                        "right" => 1,
+                        "top" => 2,
+                        "bottom" => 3,
+                        other => return Err(format!("--bot side is left, right, top or bottom, not {}", other)),
+                    };
+                    config.bots[index] = Some(match endpoint.strip_prefix("tcp:") {
+                        Some(address) => BotEndpoint::Tcp(address.parse().map_err(|_| format!("could not understand {:?}", address))?),
+                        None => {
+                            let command: Vec<String> = endpoint.split_whitespace().map(str::to_string).collect();
+                            if command.is_empty() {
+                                return Err(format!("--bot {} needs a program to run", side));
+                            }
+                            BotEndpoint::Process(command)
+                        }
+                    });
+                }
+                "--bot-timeout" => {
+                    let milliseconds = value("--bot-timeout")?;
+                    let milliseconds: u64 = milliseconds.parse().map_err(|_| format!("could not understand {:?}", milliseconds))?;
+                    config.timeout = Duration::from_millis(milliseconds);
+                }
+                _ => (),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BotBall {
+    pub position: [f32; 2],
+    pub velocity: [f32; 2],
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BotPaddle {
+    /// 0 to 3, for left, right, top and bottom.
+    pub player: usize,
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+}
+
+/// One line to the bot. Positions are in pixels with 0, 0 in the middle of the arena and y going up.
+#[derive(Debug, Clone, Serialize)]
+pub struct BotObservation {
+    /// Filled in by the BotLink. The bot should send it back with its move.
+    pub tick: u64,
+    /// Which of the paddles is the bot's.
+    pub you: usize,
+    pub arena: [f32; 2],
+    pub balls: Vec<BotBall>,
+    pub paddles: Vec<BotPaddle>,
+    /// Indexed like the paddles. In elimination these are lives.
+    pub scores: [u32; 4],
+}
+
+/// One line from the bot: `{"tick": 12, "move": 1}`. The move goes from -1 to 1, positive is up for
+/// the side paddles and right for the top and bottom ones. Without a tick it counts for the latest one.
+#[derive(Debug, Clone, Deserialize)]
+struct BotMove {
+    tick: Option<u64>,
+    #[serde(rename = "move")]
+    intent: f32,
+}
+
+/// A running bot.
+pub struct BotLink {
+    observations: SyncSender<String>,
+    moves: Receiver<BotMove>,
+    child: Option<Child>,
+    // Kept to shut the connection down when we are done, which also frees a writer stuck on it.
+    stream: Option<TcpStream>,
+    tick: u64,
+    timeout: Duration,
+    connected: bool,
+}
+
+impl BotLink {
+    pub fn open(endpoint: &BotEndpoint, timeout: Duration) -> io::Result<Self> {
+        let (mut writer, reader, child, stream): (Box<dyn Write + Send>, Box<dyn io::Read + Send>, _, _) = match endpoint {
+            BotEndpoint::Process(command) => {
+                let mut child = Command::new(&command[0])
+                    .args(&command[1..])
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::inherit())
+                    .spawn()?;
+                let stdin = child.stdin.take().ok_or_else(|| io::Error::other("bot has no stdin"))?;
+                let stdout = child.stdout.take().ok_or_else(|| io::Error::other("bot has no stdout"))?;
+                (Box::new(stdin), Box::new(stdout), Some(child), None)
+            }
+            BotEndpoint::Tcp(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_nodelay(true)?;
+                (Box::new(stream.try_clone()?), Box::new(stream.try_clone()?), None, Some(stream))
+            }
+        };
+
+        // Reading blocks, so it gets a thread of its own. It stops when the bot closes its end.
+        let (sender, moves) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                if let Ok(bot_move) = serde_json::from_str::<BotMove>(&line) {
+                    if sender.send(bot_move).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        // So does writing, a bot that stops reading would otherwise hold up the frame once its pipe or
+        // socket is full. The writer stops when it can't write any more or the link is dropped.
+        let (observations, queued) = mpsc::sync_channel::<String>(MAX_QUEUED_OBSERVATIONS);
+        thread::spawn(move || {
+            for line in queued {
+                if writeln!(writer, "{}", line).and_then(|_| writer.flush()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(BotLink { observations, moves, child, stream, tick: 0, timeout, connected: true })
+    }
+
+    /// Sends the bot the match and waits for its move. 0, standing still, if it doesn't answer in time.
+    pub fn exchange(&mut self, mut observation: BotObservation) -> f32 {
+        if !self.connected {
+            return 0.0;
+        }
+
+        self.tick += 1;
+        observation.tick = self.tick;
+        let line = match serde_json::to_string(&observation) {
+            Ok(line) => line,
+            Err(_) => return 0.0,
+        };
+        match self.observations.try_send(line) {
+            Ok(()) => (),
+            // Still busy with earlier ticks, it can't be waiting on this one.
+            Err(TrySendError::Full(_)) => return 0.0,
+            Err(TrySendError::Disconnected(_)) => {
+                self.connected = false;
+                return 0.0;
+            }
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.moves.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                // Late answers to earlier ticks are no use any more.
+                Ok(bot_move) if bot_move.tick.is_some_and(|tick| tick < self.tick) => continue,
+                Ok(bot_move) if bot_move.intent.is_finite() => return bot_move.intent.clamp(-1.0, 1.0),
+                Ok(_) => return 0.0,
+                Err(RecvTimeoutError::Timeout) => return 0.0,
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.connected = false;
+                    return 0.0;
+                }
+            }
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+}
+
+impl Drop for BotLink {
+    fn drop(&mut self) {
+        if let Some(stream) = &self.stream {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
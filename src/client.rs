@@ -0,0 +1,202 @@
+use std::{collections::VecDeque, io::{self, Write}, net::{SocketAddr, TcpStream, UdpSocket}, time::{Duration, Instant}};
+
+use crate::{protocol::{ControlMessage, InputPacket, LineReader, LobbyState, Snapshot}, simulation::{MatchRules, PaddleInput, TICK_SECONDS}};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// We draw the match this far behind the newest snapshot, so there is almost always a snapshot on
+// either side of the time we draw and a lost packet or two doesn't make things jump.
+const INTERPOLATION_DELAY: f32 = 0.1;
+const MAX_SNAPSHOTS: usize = 32;
+// The ball moving further than this between two snapshots means it was served again, not that it flew there.
+const MAX_INTERPOLATED_JUMP: f32 = 200.0;
+
+/// A connection to a dedicated server.
+pub struct ServerConnection {
+    stream: TcpStream,
+    reader: LineReader,
+    socket: UdpSocket,
+    token: u32,
+    sequence: u32,
+    lobby: LobbyState,
+    in_match: bool,
+    /// Oldest first, with the time each one arrived.
+    snapshots: VecDeque<(Instant, Snapshot)>,
+    connected: bool,
+}
+
+impl ServerConnection {
+    /// Joins the servers lobby under a name, waiting a few seconds for it to answer.
+    pub fn connect(address: SocketAddr, name: &str) -> io::Result<Self> {
+        let deadline = Instant::now() + CONNECT_TIMEOUT;
+        let mut stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+        stream.set_nodelay(true)?;
+        stream.write_all(ControlMessage::Join { name: name.to_string() }.to_line().as_bytes())?;
+
+        let mut reader = LineReader::default();
+        // The lobby, and maybe the match, can come in right behind the welcome.
+        let (token, rest) = loop {
+            // A read that times out looks like there was nothing to read, so the deadline is kept here.
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "the server didn't answer"));
+            }
+            stream.set_read_timeout(Some(left))?;
+            let mut messages: Vec<ControlMessage> = reader.read_lines(&mut stream)?.iter().filter_map(|line| ControlMessage::parse(line)).collect();
+            if let Some(index) = messages.iter().position(|message| matches!(message, ControlMessage::Welcome { .. })) {
+                let rest = messages.split_off(index + 1);
+                if let Some(ControlMessage::Welcome { token }) = messages.pop() {
+                    break (token, rest);
+                }
+            }
+        };
+        stream.set_read_timeout(None)?;
+        stream.set_nonblocking(true)?;
+
+        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0)))?;
+        socket.connect(address)?;
+        socket.set_nonblocking(true)?;
+
+        let mut connection = ServerConnection {
+            stream,
+            reader,
+            socket,
+            token,
+            sequence: 0,
+            lobby: LobbyState::default(),
+            in_match: false,
+            snapshots: VecDeque::new(),
+            connected: true,
+        };
+        for message in rest {
+            connection.handle_message(message);
+        }
+        // The server finds out where to send snapshots from our first input.
+        connection.send_input(0)?;
+        Ok(connection)
+    }
+
+    /// 0 for Player1 on the left, 1 for Player2 on the right, or None while we haven't picked a side.
+    pub fn player(&self) -> Option<usize> {
+        self.lobby.seat_of(self.token)
+    }
+
+    pub fn lobby(&self) -> &LobbyState {
+        &self.lobby
+    }
+
+    /// True from the moment the server starts a match until it sends us back to the lobby.
+    pub fn in_match(&self) -> bool {
+        self.in_match
+    }
+
+    pub fn choose_side(&mut self, side: Option<usize>) -> io::Result<()> {
+        self.send_control(ControlMessage::Side(side))
+    }
+
+    pub fn propose_rules(&mut self, rules: MatchRules) -> io::Result<()> {
+        self.send_control(ControlMessage::Rules(rules))
+    }
+
+    pub fn set_ready(&mut self, ready: bool) -> io::Result<()> {
+        self.send_control(ControlMessage::Ready(ready))
+    }
+
+    fn send_control(&mut self, message: ControlMessage) -> io::Result<()> {
+        self.stream.write_all(message.to_line().as_bytes())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    pub fn send_input(&mut self, input: PaddleInput) -> io::Result<()> {
+        self.sequence += 1;
+        let packet = InputPacket { token: self.token, sequence: self.sequence, input };
+        match self.socket.send(&packet.encode()) {
+            Err(error) if error.kind() != io::ErrorKind::WouldBlock => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// Picks up any snapshots that arrived, and notices when the server closes the connection.
+    pub fn poll(&mut self) -> io::Result<()> {
+        if self.connected {
+            match self.reader.read_lines(&mut self.stream) {
+                Ok(lines) => {
+                    for message in lines.iter().filter_map(|line| ControlMessage::parse(line)) {
+                        self.handle_message(message);
+                    }
+                }
+                Err(_) => self.connected = false,
+            }
+        }
+
+        let mut buffer = [0u8; 64];
+        loop {
+            match self.socket.recv(&mut buffer) {
+                Ok(length) => {
+                    let snapshot = match Snapshot::decode(&buffer[..length]) {
+                        Some(snapshot) => snapshot,
+                        None => continue,
+                    };
+                    // UDP can reorder packets, and an old snapshot is no use to us.
+                    if self.snapshots.back().is_some_and(|(_, latest)| latest.tick >= snapshot.tick) {
+                        continue;
+                    }
+                    self.snapshots.push_back((Instant::now(), snapshot));
+                    if self.snapshots.len() > MAX_SNAPSHOTS {
+                        self.snapshots.pop_front();
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => return Ok(()),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    fn handle_message(&mut self, message: ControlMessage) {
+        match message {
+            ControlMessage::Lobby(lobby) => {
+                self.lobby = lobby;
+                self.in_match = false;
+            }
+            // Whatever we had is from before the match.
+            ControlMessage::Start => {
+                self.in_match = true;
+                self.snapshots.clear();
+            }
+            _ => (),
+        }
+    }
+
+    /// The match as it should be drawn right now, blended between the two snapshots around the time we draw.
+    pub fn interpolated(&self, now: Instant) -> Option<Snapshot> {
+        let (received, latest) = self.snapshots.back()?;
+        let tick_time = |snapshot: &Snapshot| snapshot.tick as f32 * TICK_SECONDS;
+        let server_time = tick_time(latest) + now.saturating_duration_since(*received).as_secs_f32();
+        let render_time = server_time - INTERPOLATION_DELAY;
+
+        let index = match self.snapshots.iter().position(|(_, snapshot)| tick_time(snapshot) > render_time) {
+            Some(0) => return Some(self.snapshots[0].1.clone()),
+            Some(index) => index,
+            None => return Some(latest.clone()),
+        };
+        let earlier = &self.snapshots[index - 1].1;
+        let later = &self.snapshots[index].1;
+
+        let mut snapshot = later.clone();
+        let jump = (later.ball_position[0] - earlier.ball_position[0]).abs() + (later.ball_position[1] - earlier.ball_position[1]).abs();
+        if earlier.phase != later.phase || jump > MAX_INTERPOLATED_JUMP {
+            return Some(snapshot);
+        }
+
+        let t = (render_time - tick_time(earlier)) / (tick_time(later) - tick_time(earlier));
+        let lerp = |from: f32, to: f32| from + (to - from) * t;
+        for axis in 0..2 {
+            snapshot.ball_position[axis] = lerp(earlier.ball_position[axis], later.ball_position[axis]);
+            snapshot.paddles[axis] = lerp(earlier.paddles[axis], later.paddles[axis]);
+        }
+        Some(snapshot)
+    }
+}
@@ -0,0 +1,535 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use rand::{thread_rng, Rng};
+use pong50::{bot::{BotBall, BotConfig, BotLink, BotObservation, BotPaddle}, scripting::Script, simulation::BALL_SIZE};
+
+use crate::{profile::ControlScheme, rules::Controller, score::Scoreboard, scripts::{script_ball, script_paddle}, Player};
+
+// Stick movement smaller than this is treated as the stick resting in the middle.
+const STICK_DEAD_ZONE: f32 = 0.2;
+
+// The adaptive AI starts in the middle and moves its difficulty after every point. At 0.0 it takes
+// the longest to react and can miss by more than a paddle length, at 1.0 it is as quick and as
+// accurate as the predictive AI.
+const ADAPTIVE_START: f32 = 0.5;
+const ADAPTIVE_MARGIN_STEP: f32 = 0.08;
+const ADAPTIVE_RALLY_STEP: f32 = 0.04;
+// Rallies with this many hits or fewer count as short.
+const ADAPTIVE_SHORT_RALLY: u32 = 2;
+const ADAPTIVE_MIN_REACTION: f32 = 0.0;
+const ADAPTIVE_MAX_REACTION: f32 = 0.4;
+// As a fraction of the paddle length.
+const ADAPTIVE_MAX_AIM_ERROR: f32 = 0.75;
+
+/// A ball, as controllers see it.
+#[derive(Debug, Clone, Copy)]
+pub struct BallView {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub last_hit: Option<Player>,
+}
+
+/// A paddle, as controllers see it.
+#[derive(Debug, Clone, Copy)]
+pub struct PaddleView {
+    pub player: Player,
+    pub position: Vec2,
+    pub size: Vec2,
+}
+
+impl PaddleView {
+    /// Splits a position or velocity into how far along this paddle's axis it is and how far across it.
+    /// The top and bottom paddles see the world turned on its side, so the same logic works for every paddle.
+    pub fn turn(&self, vector: Vec2) -> (f32, f32) {
+        if self.player.is_horizontal() {
+            (vector.x, vector.y)
+        } else {
+            (vector.y, vector.x)
+        }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.turn(self.size).0
+    }
+}
+
+/// Everything a controller gets to look at when it decides what its paddle does. None of it can be changed from here.
+pub struct ControllerView<'a> {
+    /// The paddle being controlled.
+    pub paddle: PaddleView,
+    /// Every paddle in the match, this one included.
+    pub paddles: &'a [PaddleView],
+    pub balls: &'a [BallView],
+    /// Width and height of the arena, which is centered on 0, 0.
+    pub arena: Vec2,
+    pub scoreboard: &'a Scoreboard,
+    /// Paddle hits since the last serve.
+    pub rally: u32,
+    pub keys: &'a Input<KeyCode>,
+    pub gamepad_buttons: &'a Input<GamepadButton>,
+    pub gamepad_axes: &'a Axis<GamepadAxis>,
+    pub delta_seconds: f32,
+}
+
+/// Decides where a paddle goes. A new kind of player only has to implement this and be put on a
+/// paddle with PaddleControl, the movement system takes care of speed and staying on the screen.
+pub trait PaddleController: Send + Sync {
+    /// How hard to move along the paddle's axis this frame, from -1.0 to 1.0. Positive is up for
+    /// the side paddles and right for the top and bottom ones.
+    fn intent(&mut self, view: &ControllerView) -> f32;
+
+    /// Whether to dash this frame, see paddle::PaddleDash. Only people dash, the AIs get by without.
+    fn dash(&mut self, _view: &ControllerView) -> bool {
+        false
+    }
+
+    /// How hard an AI that adjusts itself during the match is playing right now, from 0.0 to 1.0,
+    /// so it can be kept in the match stats. Everything else plays the same all match.
+    fn difficulty(&self) -> Option<f32> {
+        None
+    }
+}
+
+/// Drives the paddle it is on. Paddles without one stand still.
+pub struct PaddleControl(pub Box<dyn PaddleController>);
+
+/// The bots given on the command line.
+pub struct Bots(pub BotConfig);
+
+impl PaddleControl {
+    /// One of the controllers that can be picked in the menu. Gamepads are handed out in player
+    /// order, so `gamepad` is how many players before this one already have one. `script` is the match script, if any, and `recording` what a replay plays back.
+    pub fn builtin(controller: Controller, player: Player, controls: ControlScheme, gamepad: usize, bots: &Bots, script: Option<&Arc<Script>>, recording: &Recording) -> Self {
+        PaddleControl(match controller {
+            Controller::Human => Box::new(KeyboardController::for_player(player, controls)),
+            Controller::Gamepad => Box::new(GamepadController { gamepad: Gamepad(gamepad) }),
+            Controller::Ai => Box::new(ChaseAi),
+            Controller::PredictiveAi => Box::new(PredictiveAi),
+            Controller::AdaptiveAi => Box::new(AdaptiveAi::default()),
+            Controller::Bot => Box::new(BotController::start(player, &bots.0)),
+            Controller::Script => match script {
+                Some(script) if script.has_hook("intent") => Box::new(ScriptedAi { script: Some(script.clone()) }),
+                _ => Box::new(ChaseAi),
+            },
+            Controller::Replay => Box::new(ReplayController::new(recording.latest(player).to_vec())),
+        })
+    }
+}
+
+// Turns how far a paddle is from where it wants to be into an intent, leaving it alone when it is
+// close enough so it doesn't jitter.
+fn towards(position: f32, target: f32, dead_zone: f32) -> f32 {
+    if position + dead_zone < target {
+        1.0
+    } else if position - dead_zone > target {
+        -1.0
+    } else {
+        0.0
+    }
+}
+
+/// Two keys, one for each direction, and one more to dash.
+pub struct KeyboardController {
+    pub positive: KeyCode,
+    pub negative: KeyCode,
+    pub dash: KeyCode,
+}
+
+impl KeyboardController {
+    /// The left paddle belongs to the active profile and gets the keys it prefers, the right paddle
+    /// gets the others.
+    pub fn for_player(player: Player, controls: ControlScheme) -> Self {
+        let ws = (KeyCode::W, KeyCode::S, KeyCode::LShift);
+        let arrows = (KeyCode::Up, KeyCode::Down, KeyCode::RShift);
+        let (positive, negative, dash) = match player {
+            Player::Player1 => if controls == ControlScheme::WS { ws } else { arrows },
+            Player::Player2 => if controls == ControlScheme::WS { arrows } else { ws },
+            Player::Player3 => (KeyCode::L, KeyCode::J, KeyCode::K),
+            Player::Player4 => (KeyCode::Right, KeyCode::Left, KeyCode::RControl),
+        };
+        KeyboardController { positive, negative, dash }
+    }
+}
+
+impl PaddleController for KeyboardController {
+    fn intent(&mut self, view: &ControllerView) -> f32 {
+        if view.keys.pressed(self.positive) {
+            1.0
+        } else if view.keys.pressed(self.negative) {
+            -1.0
+        } else {
+            0.0
+        }
+    }
+
+    fn dash(&mut self, view: &ControllerView) -> bool {
+        view.keys.just_pressed(self.dash)
+    }
+}
+
+/// The d-pad, or the left stick for finer control. The bottom face button dashes.
+pub struct GamepadController {
+    pub gamepad: Gamepad,
+}
+
+impl PaddleController for GamepadController {
+    fn intent(&mut self, view: &ControllerView) -> f32 {
+        let (positive, negative, axis) = if view.paddle.player.is_horizontal() {
+            (GamepadButtonType::DPadRight, GamepadButtonType::DPadLeft, GamepadAxisType::LeftStickX)
+        } else {
+            (GamepadButtonType::DPadUp, GamepadButtonType::DPadDown, GamepadAxisType::LeftStickY)
+        };
+
+        if view.gamepad_buttons.pressed(GamepadButton(self.gamepad, positive)) {
+            return 1.0;
+        }
+        if view.gamepad_buttons.pressed(GamepadButton(self.gamepad, negative)) {
+            return -1.0;
+        }
+        match view.gamepad_axes.get(GamepadAxis(self.gamepad, axis)) {
+            Some(value) if value.abs() > STICK_DEAD_ZONE => value.clamp(-1.0, 1.0),
+            _ => 0.0,
+        }
+    }
+
+    fn dash(&mut self, view: &ControllerView) -> bool {
+        view.gamepad_buttons.just_pressed(GamepadButton(self.gamepad, GamepadButtonType::South))
+    }
+}
+
+// The ball the AI should care about: the one heading towards the paddle that will get there first.
+// If nothing is heading our way it is just the closest ball.
+fn most_threatening_ball<'a>(paddle: &PaddleView, balls: &'a [BallView]) -> Option<&'a BallView> {
+    let (_, depth) = paddle.turn(paddle.position);
+
+    let incoming = balls
+        .iter()
+        .filter_map(|ball| {
+            let (_, across) = paddle.turn(ball.position);
+            let (_, speed) = paddle.turn(ball.velocity);
+            if speed != 0.0 && (depth - across).signum() == speed.signum() {
+                Some(((depth - across) / speed, ball))
+            } else {
+                None
+            }
+        })
+        .fold(None, |closest: Option<(f32, &BallView)>, (time, ball)| match closest {
+            Some((closest_time, _)) if closest_time <= time => closest,
+            _ => Some((time, ball)),
+        });
+
+    if let Some((_, ball)) = incoming {
+        return Some(ball);
+    }
+
+    balls
+        .iter()
+        .map(|ball| ((depth - paddle.turn(ball.position).1).abs(), ball))
+        .fold(None, |closest: Option<(f32, &BallView)>, (distance, ball)| match closest {
+            Some((closest_distance, _)) if closest_distance <= distance => closest,
+            _ => Some((distance, ball)),
+        })
+        .map(|(_, ball)| ball)
+}
+
+/// The original AI. It follows the most threatening ball around, and waits in the middle when there is none.
+pub struct ChaseAi;
+
+impl PaddleController for ChaseAi {
+    fn intent(&mut self, view: &ControllerView) -> f32 {
+        let (position, _) = view.paddle.turn(view.paddle.position);
+        let target = most_threatening_ball(&view.paddle, view.balls).map_or(0.0, |ball| view.paddle.turn(ball.position).0);
+        towards(position, target, view.paddle.length() / 4.0)
+    }
+}
+
+/// Works out where the most threatening ball will reach the paddle, bounces off the walls included,
+/// and goes straight there instead of following the ball around. It goes back to the middle while
+/// the ball is heading away.
+pub struct PredictiveAi;
+
+impl PaddleController for PredictiveAi {
+    fn intent(&mut self, view: &ControllerView) -> f32 {
+        let (position, _) = view.paddle.turn(view.paddle.position);
+        let target = predicted_target(view).unwrap_or(0.0);
+        towards(position, target, view.paddle.length() / 8.0)
+    }
+}
+
+// Where along its axis the most threatening ball will reach the paddle, or None while it is heading away.
+fn predicted_target(view: &ControllerView) -> Option<f32> {
+    let paddle = &view.paddle;
+    let (_, depth) = paddle.turn(paddle.position);
+    let (extent, _) = paddle.turn(view.arena);
+
+    let ball = most_threatening_ball(paddle, view.balls)?;
+    let (along, across) = paddle.turn(ball.position);
+    let (along_speed, across_speed) = paddle.turn(ball.velocity);
+    let time = if across_speed != 0.0 { (depth - across) / across_speed } else { -1.0 };
+    if time > 0.0 {
+        Some(reflect(along + along_speed * time, extent / 2.0 - BALL_SIZE / 2.0))
+    } else {
+        None
+    }
+}
+
+/// Plays like the predictive AI, held back by a reaction time and an aiming error that both shrink as
+/// its difficulty goes from 0.0 up to 1.0. After every point it looks at the score and how long the
+/// rally was, and moves its difficulty to keep the match close.
+pub struct AdaptiveAi {
+    difficulty: f32,
+    // The scores when it last looked, to notice points being scored. None until it has looked once,
+    // since handicaps can hand out points before the match starts.
+    scores: Option<[u32; 4]>,
+    // The longest the rally got since the last point. The rally is already reset by the time we notice the point.
+    rally: u32,
+    // Whether a ball was heading our way last frame, how much longer it waits before reacting to it,
+    // and how far off it is aiming this time.
+    incoming: bool,
+    waiting: f32,
+    aim_error: f32,
+}
+
+impl Default for AdaptiveAi {
+    fn default() -> Self {
+        AdaptiveAi {
+            difficulty: ADAPTIVE_START,
+            scores: None,
+            rally: 0,
+            incoming: false,
+            waiting: 0.0,
+            aim_error: 0.0,
+        }
+    }
+}
+
+impl AdaptiveAi {
+    // Leading makes it easier and trailing harder, by a step for every point in the margin. On top of
+    // that a short rally says one side is struggling: it eases off if the point went its way and tries
+    // harder if it didn't. Scores are lives in elimination, but more is still better there.
+    fn adapt(&mut self, player: Player, paddles: &[PaddleView], before: [u32; 4], scores: [u32; 4]) {
+        let margin = |scores: [u32; 4]| {
+            let best_other = paddles
+                .iter()
+                .filter(|paddle| paddle.player != player)
+                .map(|paddle| scores[paddle.player.index()])
+                .max()
+                .unwrap_or(0);
+            scores[player.index()] as f32 - best_other as f32
+        };
+
+        let margin_now = margin(scores);
+        let mut change = -margin_now * ADAPTIVE_MARGIN_STEP;
+        if self.rally <= ADAPTIVE_SHORT_RALLY {
+            change += if margin_now > margin(before) { -ADAPTIVE_RALLY_STEP } else { ADAPTIVE_RALLY_STEP };
+        }
+        self.difficulty = (self.difficulty + change).clamp(0.0, 1.0);
+    }
+}
+
+impl PaddleController for AdaptiveAi {
+    fn intent(&mut self, view: &ControllerView) -> f32 {
+        let scores = view.scoreboard.all();
+        self.rally = self.rally.max(view.rally);
+        match self.scores {
+            Some(before) if before != scores => {
+                self.adapt(view.paddle.player, view.paddles, before, scores);
+                self.rally = 0;
+            }
+            _ => (),
+        }
+        self.scores = Some(scores);
+
+        let (position, _) = view.paddle.turn(view.paddle.position);
+        let target = predicted_target(view);
+
+        // A new ball coming in: take a moment to react, and pick how far off to aim.
+        if target.is_some() && !self.incoming {
+            let sloppiness = 1.0 - self.difficulty;
+            self.waiting = ADAPTIVE_MIN_REACTION + (ADAPTIVE_MAX_REACTION - ADAPTIVE_MIN_REACTION) * sloppiness;
+            self.aim_error = thread_rng().gen_range(-1.0..1.0) * ADAPTIVE_MAX_AIM_ERROR * sloppiness * view.paddle.length();
+        }
+        self.incoming = target.is_some();
+
+        if self.waiting > 0.0 {
+            self.waiting -= view.delta_seconds;
+            return 0.0;
+        }
+        let target = target.map_or(0.0, |target| target + self.aim_error);
+        towards(position, target, view.paddle.length() / 8.0)
+    }
+
+    fn difficulty(&self) -> Option<f32> {
+        Some(self.difficulty)
+    }
+}
+
+// Folds a position that went past the walls at -limit and limit back inside, the way bouncing would.
+fn reflect(position: f32, limit: f32) -> f32 {
+    let span = 2.0 * limit;
+    let folded = (position + limit).rem_euclid(2.0 * span);
+    let inside = if folded > span { 2.0 * span - folded } else { folded };
+    inside - limit
+}
+
+/// Plays back a list of intents, one per frame, and stands still once it runs out. See Recording
+/// for where the intents come from.
+pub struct ReplayController {
+    intents: Vec<f32>,
+    next: usize,
+}
+
+impl ReplayController {
+    pub fn new(intents: Vec<f32>) -> Self {
+        ReplayController { intents, next: 0 }
+    }
+}
+
+impl PaddleController for ReplayController {
+    fn intent(&mut self, _view: &ControllerView) -> f32 {
+        let intent = self.intents.get(self.next).copied().unwrap_or(0.0);
+        self.next += 1;
+        intent
+    }
+}
+
+/// What every paddle was told to do, frame by frame, in the match being played and in the one before
+/// it. Controller::Replay plays the latest one back. It is kept per frame, so the replay only moves
+/// exactly like the original when the game runs at the same frame rate.
+#[derive(Default)]
+pub struct Recording {
+    playing: [Vec<f32>; 4],
+    last: [Vec<f32>; 4],
+}
+
+impl Recording {
+    pub fn record(&mut self, player: Player, intent: f32) {
+        self.playing[player.index()].push(intent);
+    }
+
+    /// The intents of the most recent match that got going, which is the one in progress unless
+    /// nothing has been recorded for it yet.
+    pub fn latest(&self, player: Player) -> &[f32] {
+        if self.playing.iter().any(|intents| !intents.is_empty()) {
+            &self.playing[player.index()]
+        } else {
+            &self.last[player.index()]
+        }
+    }
+
+    /// Keeps the match that was just played, if there was one, and starts recording a new one.
+    pub fn start_match(&mut self) {
+        if self.playing.iter().any(|intents| !intents.is_empty()) {
+            self.last = std::mem::take(&mut self.playing);
+        }
+    }
+}
+
+/// Moves however a player on the other end of a connection does. Whoever holds the NetworkIntent sets
+/// it, see online::online_update, and the paddle keeps doing that until it is set again.
+pub struct NetworkController {
+    intent: Arc<Mutex<f32>>,
+}
+
+/// The other end of a NetworkController.
+#[derive(Clone)]
+pub struct NetworkIntent(Arc<Mutex<f32>>);
+
+impl NetworkIntent {
+    pub fn set(&self, intent: f32) {
+        if let Ok(mut current) = self.0.lock() {
+            *current = intent.clamp(-1.0, 1.0);
+        }
+    }
+}
+
+impl NetworkController {
+    pub fn new() -> (Self, NetworkIntent) {
+        let intent = Arc::new(Mutex::new(0.0));
+        (NetworkController { intent: intent.clone() }, NetworkIntent(intent))
+    }
+}
+
+impl PaddleController for NetworkController {
+    fn intent(&mut self, _view: &ControllerView) -> f32 {
+        self.intent.lock().map_or(0.0, |intent| *intent)
+    }
+}
+
+/// A program outside the game, see pong50::bot. Every call sends it the match and waits for its move.
+/// A bot that couldn't be started just stands still.
+pub struct BotController {
+    // The link is only ever used through &mut self, the Mutex is there to make it Sync.
+    link: Option<Mutex<BotLink>>,
+}
+
+impl BotController {
+    pub fn start(player: Player, config: &BotConfig) -> Self {
+        let link = config.bots[player.index()].as_ref().and_then(|endpoint| match BotLink::open(endpoint, config.timeout) {
+            Ok(link) => Some(Mutex::new(link)),
+            Err(error) => {
+                error!("Could not start the bot for {:?}: {}", player, error);
+                None
+            }
+        });
+        BotController { link }
+    }
+}
+
+impl PaddleController for BotController {
+    fn intent(&mut self, view: &ControllerView) -> f32 {
+        let link = match self.link.as_mut().and_then(|link| link.get_mut().ok()) {
+            Some(link) => link,
+            None => return 0.0,
+        };
+
+        let scoreboard = view.scoreboard;
+        link.exchange(BotObservation {
+            tick: 0,
+            you: view.paddle.player.index(),
+            arena: [view.arena.x, view.arena.y],
+            balls: view
+                .balls
+                .iter()
+                .map(|ball| BotBall { position: ball.position.into(), velocity: ball.velocity.into() })
+                .collect(),
+            paddles: view
+                .paddles
+                .iter()
+                .map(|paddle| BotPaddle { player: paddle.player.index(), position: paddle.position.into(), size: paddle.size.into() })
+                .collect(),
+            scores: scoreboard.all(),
+        })
+    }
+}
+
+/// Moves however the intent hook of the match script says, see pong50::scripting. A script that fails
+/// is given up on for the rest of the match and its paddle stands still.
+pub struct ScriptedAi {
+    script: Option<Arc<Script>>,
+}
+
+impl PaddleController for ScriptedAi {
+    fn intent(&mut self, view: &ControllerView) -> f32 {
+        let script = match &self.script {
+            Some(script) => script,
+            None => return 0.0,
+        };
+
+        let paddle = |paddle: &PaddleView| script_paddle(paddle.player, paddle.position, paddle.size);
+        let balls: Vec<_> = view.balls.iter().map(|ball| script_ball(ball.position, ball.velocity, ball.last_hit)).collect();
+        let paddles: Vec<_> = view.paddles.iter().map(paddle).collect();
+
+        match script.intent(&paddle(&view.paddle), &balls, &paddles, view.scoreboard.all()) {
+            Some(Ok(intent)) if intent.is_finite() => intent,
+            Some(Ok(_)) => 0.0,
+            Some(Err(error)) => {
+                error!("Script AI for {:?} stopped, {}", view.paddle.player, error);
+                self.script = None;
+                0.0
+            }
+            None => 0.0,
+        }
+    }
+}
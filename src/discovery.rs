@@ -0,0 +1,145 @@
+use std::{io, net::{Ipv4Addr, SocketAddr, UdpSocket}, time::{Duration, Instant}};
+
+// Finding games on the local network. Whoever is looking broadcasts a question and every server
+// that hears it answers with its name and how many seats are taken. Asking instead of listening
+// means any number of games on one machine can look at the same time, only the servers need the
+// well known port.
+
+pub const DISCOVERY_PORT: u16 = 7101;
+
+const QUERY: &str = "PONG50 FIND";
+const ANSWER: &str = "PONG50 HOST";
+const SEARCH_INTERVAL: Duration = Duration::from_secs(1);
+// A game we haven't heard from in this long has probably gone away.
+const FORGET_AFTER: Duration = Duration::from_secs(3);
+
+/// What a server says about itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameAnnouncement {
+    pub name: String,
+    /// The port clients should connect to, which isn't the one the answer came from.
+    pub port: u16,
+    pub players: usize,
+}
+
+impl GameAnnouncement {
+    pub fn encode(&self) -> String {
+        format!("{} {} {} {}", ANSWER, self.port, self.players, self.name)
+    }
+
+    pub fn decode(text: &str) -> Option<GameAnnouncement> {
+        let rest = text.strip_prefix(ANSWER)?.trim_start();
+        let mut words = rest.splitn(3, ' ');
+        Some(GameAnnouncement {
+            port: words.next()?.parse().ok()?,
+            players: words.next()?.parse().ok()?,
+            name: words.next().unwrap_or("").trim().to_string(),
+        })
+    }
+}
+
+/// The server side, answering anyone who asks.
+pub struct DiscoveryResponder {
+    socket: UdpSocket,
+}
+
+impl DiscoveryResponder {
+    pub fn bind() -> io::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], DISCOVERY_PORT)))?;
+        socket.set_nonblocking(true)?;
+        Ok(DiscoveryResponder { socket })
+    }
+
+    pub fn answer(&self, announcement: &GameAnnouncement) -> io::Result<()> {
+        let mut buffer = [0u8; 64];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((length, address)) => {
+                    if &buffer[..length] == QUERY.as_bytes() {
+                        // Someone who asked and left straight away is fine.
+                        let _ = self.socket.send_to(announcement.encode().as_bytes(), address);
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(error) if error.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredGame {
+    pub name: String,
+    pub address: SocketAddr,
+    pub players: usize,
+    last_seen: Instant,
+}
+
+/// The client side, keeping a list of the games that answered recently.
+pub struct LanBrowser {
+    socket: UdpSocket,
+    games: Vec<DiscoveredGame>,
+    last_search: Option<Instant>,
+}
+
+impl LanBrowser {
+    pub fn new() -> io::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0)))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        Ok(LanBrowser {
+            socket,
+            games: Vec::new(),
+            last_search: None,
+        })
+    }
+
+    /// Asks again every so often and collects whatever answers came in.
+    pub fn poll(&mut self, now: Instant) -> io::Result<()> {
+        if self.last_search.is_none_or(|last| now.duration_since(last) >= SEARCH_INTERVAL) {
+            self.last_search = Some(now);
+            // Without a network to broadcast on we can still find games on this machine.
+            let broadcast = SocketAddr::from((Ipv4Addr::BROADCAST, DISCOVERY_PORT));
+            let local = SocketAddr::from((Ipv4Addr::LOCALHOST, DISCOVERY_PORT));
+            if let Err(error) = self.socket.send_to(QUERY.as_bytes(), broadcast).or_else(|_| self.socket.send_to(QUERY.as_bytes(), local)) {
+                if error.kind() != io::ErrorKind::WouldBlock {
+                    return Err(error);
+                }
+            }
+        }
+
+        let mut buffer = [0u8; 256];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((length, from)) => {
+                    let announcement = match GameAnnouncement::decode(&String::from_utf8_lossy(&buffer[..length])) {
+                        Some(announcement) => announcement,
+                        None => continue,
+                    };
+                    let address = SocketAddr::new(from.ip(), announcement.port);
+                    let game = DiscoveredGame {
+                        name: announcement.name,
+                        address,
+                        players: announcement.players,
+                        last_seen: now,
+                    };
+                    match self.games.iter_mut().find(|game| game.address == address) {
+                        Some(known) => *known = game,
+                        None => self.games.push(game),
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(error) => return Err(error),
+            }
+        }
+
+        self.games.retain(|game| now.duration_since(game.last_seen) < FORGET_AFTER);
+        Ok(())
+    }
+
+    pub fn games(&self) -> &[DiscoveredGame] {
+        &self.games
+    }
+}
@@ -1,16 +1,36 @@
 use bevy::prelude::*;
 use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
 
-use crate::{ball::{Ball, BALL_INITIAL_X_MAX, BALL_INITIAL_X_MIN, BALL_INITIAL_Y_MIN, BALL_INITIAL_Y_MAX}, Server, Player, score::Scoreboard};
+use pong50::simulation::{BALL_INITIAL_X_MAX, BALL_INITIAL_X_MIN, BALL_INITIAL_Y_MAX, BALL_INITIAL_Y_MIN, BALL_SIZE};
+
+use crate::{ball::{Ball, BallMaterial, Rally, spawn_ball}, Server, Player, practice::{Practice, machine_serve}, score::Scoreboard, rules::{GameMode, GameRules}, scripts::{Scripts, hook_or}};
 
 pub struct GameStatePlugin;
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum AppState {
     Play,
     Start,
     Serve,
     Done,
+    /// Playing against someone on another machine. The rollback session or a server runs the match.
+    Online,
+    /// Finding a game on the local network and getting ready for it.
+    Lobby,
+    /// Looking back at the active profile's finished matches.
+    History,
+    /// Every profile's rating next to the AI difficulties.
+    Leaderboard,
+    /// What the active profile has unlocked so far, and what is still left.
+    Achievements,
+}
+
+impl AppState {
+    /// In these states the match, if there is one, isn't ours to run.
+    pub fn is_networked(&self) -> bool {
+        matches!(self, AppState::Online | AppState::Lobby)
+    }
 }
 
 struct GameStateText;
@@ -65,67 +85,105 @@ fn setup(
 }
 
 fn enter_start_state(
-    mut query: Query<(&mut Transform, &mut Ball)>,
+    mut commands: Commands,
+    query: Query<Entity, With<Ball>>,
     mut scoreboard: ResMut<Scoreboard>,
-    server: Res<Server>
+    mut rally: ResMut<Rally>,
+    server: Res<Server>,
+    rules: Res<GameRules>,
+    scripts: Res<Scripts>,
+    practice: Res<Practice>,
+    ball_material: Res<BallMaterial>,
+    windows: Res<Windows>,
 ) {
-    if let Ok((mut transform, mut ball)) = query.single_mut() {
-        // reset scores to 0
-        scoreboard.player1 = 0;
-        scoreboard.player2 = 0;
-
-        // reset ball position to 0.0
-        transform.translation.x = 0.0;
-        transform.translation.y = 0.0;
-
-
-        let mut serve = 1.0;
-
-        match server.0 {
-            Player::Player2 => serve = -1.0,
-            _ => (),
-        }
+    // reset scores to 0
+    *scoreboard = Scoreboard::default();
+    rally.0 = 0;
 
+    serve_balls(&mut commands, &query, &server, &rules, &scoreboard, &scripts, &practice, &ball_material, windows.get_primary().unwrap());
+}
 
-        // randomize ball velocity with relation to server
-        let mut rng = thread_rng();
-        let ball_x = serve * rng.gen_range(BALL_INITIAL_X_MIN..BALL_INITIAL_X_MAX);
-        let ball_y = rng.gen_range(BALL_INITIAL_Y_MIN..BALL_INITIAL_Y_MAX);
+fn enter_serve_state(
+    mut commands: Commands,
+    query: Query<Entity, With<Ball>>,
+    mut rally: ResMut<Rally>,
+    server: Res<Server>,
+    rules: Res<GameRules>,
+    scoreboard: Res<Scoreboard>,
+    scripts: Res<Scripts>,
+    practice: Res<Practice>,
+    ball_material: Res<BallMaterial>,
+    windows: Res<Windows>,
+) {
+    rally.0 = 0;
+    serve_balls(&mut commands, &query, &server, &rules, &scoreboard, &scripts, &practice, &ball_material, windows.get_primary().unwrap());
+}
 
-        ball.velocity.x = ball_x;
-        ball.velocity.y = ball_y;
+// Clears out whatever balls are left over and puts a fresh set in the middle of the screen, 
+// as many as the game mode asks for. They are stacked vertically so they don't overlap.
+// The match script, if there is one, can change how each ball is served. In practice the ball
+// machine serves instead.
+fn serve_balls(
+    commands: &mut Commands,
+    query: &Query<Entity, With<Ball>>,
+    server: &Server,
+    rules: &GameRules,
+    scoreboard: &Scoreboard,
+    scripts: &Scripts,
+    practice: &Practice,
+    ball_material: &BallMaterial,
+    window: &Window,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
     }
 
+    if rules.mode == GameMode::Practice {
+        let (position, velocity, spin) = machine_serve(&rules.machine, practice.played(), window.width());
+        spawn_ball(commands, ball_material, position, velocity, spin);
+        return;
+    }
 
-}
+    // A server left over from a four player match might not have a paddle anymore.
+    let server = if rules.mode.players().contains(&server.0) { server.0 } else { Player::Player1 };
 
-fn enter_serve_state(
-    mut query: Query<(&mut Transform, &mut Ball)>,
-    server: Res<Server>    
-) {
-    if let Ok((mut transform, mut ball)) = query.single_mut() {
-        // reset ball position to 0.0
-        transform.translation.x = 0.0;
-        transform.translation.y = 0.0;
+    let mut serve = 1.0;
 
-        let mut serve = 1.0;
+    match server {
+        Player::Player2 | Player::Player3 => serve = -1.0,
+        _ => (),
+    }
 
-        match server.0 {
-            Player::Player2 => serve = -1.0,
-            _ => (),
-        }
+    let ball_count = rules.mode.ball_count();
+    let script = scripts.active(rules);
+    let mut rng = thread_rng();
 
+    for index in 0..ball_count {
+        let offset = index as f32 - (ball_count - 1) as f32 / 2.0;
 
         // randomize ball velocity with relation to server
-        let mut rng = thread_rng();
         let ball_x = serve * rng.gen_range(BALL_INITIAL_X_MIN..BALL_INITIAL_X_MAX);
         let ball_y = rng.gen_range(BALL_INITIAL_Y_MIN..BALL_INITIAL_Y_MAX);
 
-        ball.velocity.x = ball_x;
-        ball.velocity.y = ball_y;
+        // The top and bottom players serve up and down the screen instead.
+        let velocity = if server.is_horizontal() {
+            Vec2::new(ball_y, ball_x)
+        } else {
+            Vec2::new(ball_x, ball_y)
+        };
+        let velocity = hook_or(
+            script.and_then(|script| script.serve(server.index(), index, velocity.into(), scoreboard.all())),
+            velocity.into(),
+        );
+
+        spawn_ball(
+            commands,
+            ball_material,
+            Vec2::new(0.0, offset * BALL_SIZE * 4.0),
+            velocity.into(),
+            0.0,
+        );
     }
-
-
 }
 
 fn change_state_using_enter_key(
@@ -146,19 +204,38 @@ fn change_state_using_enter_key(
 fn update_game_state_text(
     app_state: Res<State<AppState>>, 
     mut query: Query<&mut Text, With<GameStateText>>,
-    server: Res<Server>
+    scoreboard: Res<Scoreboard>,
+    rules: Res<GameRules>,
 ) {
     match app_state.current() {
         AppState::Done => {
             if let Ok(mut text) = query.single_mut() {
-                match server.0 {
-                    Player::Player1 => {
-                        text.sections[0].value = format!("Player 2 Wins!");
+                // Make it clear when the result came with a handicap.
+                let note = if rules.any_handicap() {
+                    " (handicap)"
+                } else {
+                    ""
+                };
+                // Nobody wins playing alone, it's just a score to beat.
+                match rules.mode {
+                    GameMode::Practice => {
+                        text.sections[0].value = format!("{} Points!", scoreboard.player1);
+                        return;
                     }
-                    Player::Player2 => {
-                        text.sections[0].value = format!("Player 1 Wins!");
+                    GameMode::Squash => {
+                        text.sections[0].value = format!("{} Returns!", scoreboard.player1);
+                        return;
                     }
+                    _ => (),
                 }
+                let winner = match scoreboard.winner {
+                    Some(Player::Player1) => "Player 1",
+                    Some(Player::Player2) => "Player 2",
+                    Some(Player::Player3) => "Player 3",
+                    Some(Player::Player4) => "Player 4",
+                    None => "Nobody",
+                };
+                text.sections[0].value = format!("{} Wins!{}", winner, note);
             }
         }
         _ => {
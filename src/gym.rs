@@ -0,0 +1,292 @@
+// A reinforcement learning environment in the style of Gym, on top of the same simulation online play
+// uses. There is no window and no clock: every call to step runs exactly one tick, so training runs as
+// fast as the machine allows.
+
+use std::io::{self, BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::{MatchRules, PaddleInput, SimulationEvent, SimulationState, ARENA_HEIGHT, ARENA_WIDTH, PADDLE_HEIGHT};
+
+/// How the paddle the agent isn't controlling plays.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpponentPolicy {
+    /// Stands still.
+    Idle,
+    /// The same chasing the game's AI paddles do. The slowdown works like the AI slowdown handicap:
+    /// 0.3 means the paddle only moves on 70% of the ticks.
+    Chase { slowdown: f32 },
+}
+
+impl Default for OpponentPolicy {
+    fn default() -> Self {
+        OpponentPolicy::Chase { slowdown: 0.0 }
+    }
+}
+
+/// What the game's AI would do: head for the ball, but only once it is more than a quarter of
+/// a paddle away from the middle, so the paddle doesn't jitter.
+pub fn chase_input(paddle_y: f32, ball_y: f32) -> PaddleInput {
+    if paddle_y + PADDLE_HEIGHT / 4.0 < ball_y {
+        1
+    } else if paddle_y - PADDLE_HEIGHT / 4.0 > ball_y {
+        -1
+    } else {
+        0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnvConfig {
+    pub seed: u64,
+    pub rules: MatchRules,
+    /// 0 to play the left paddle, 1 for the right one.
+    pub side: usize,
+    pub opponent: OpponentPolicy,
+    /// An episode that runs this many steps without the match being decided ends anyway.
+    pub max_steps: u32,
+}
+
+impl EnvConfig {
+    /// `pong50-gym [--seed 0] [--side left|right] [--opponent chase|idle] [--slowdown 0.0] [--victory 2] [--max-steps 18000]`
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<EnvConfig, String> {
+        let mut config = EnvConfig::default();
+        let mut slowdown = 0.0;
+        let mut idle = false;
+
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+            match arg.as_str() {
+                "--seed" => config.seed = parse(&value("--seed")?)?,
+                "--side" => {
+                    config.side = match value("--side")?.as_str() {
+                        "left" => 0,
+                        "right" => 1,
+                        other => return Err(format!("--side is left or right, not {}", other)),
+                    }
+                }
+                "--opponent" => {
+                    idle = match value("--opponent")?.as_str() {
+                        "chase" => false,
+                        "idle" => true,
+                        other => return Err(format!("--opponent is chase or idle, not {}", other)),
+                    }
+                }
+                "--slowdown" => slowdown = parse(&value("--slowdown")?)?,
+                "--victory" => config.rules.victory_score = parse(&value("--victory")?)?,
+                "--max-steps" => config.max_steps = parse(&value("--max-steps")?)?,
+                other => return Err(format!("unknown argument {}", other)),
+            }
+        }
+
+        if !(0.0..1.0).contains(&slowdown) {
+            return Err(format!("--slowdown goes from 0.0 up to 1.0, not {}", slowdown));
+        }
+        config.opponent = if idle { OpponentPolicy::Idle } else { OpponentPolicy::Chase { slowdown } };
+        Ok(config)
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("could not understand {:?}", value))
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        EnvConfig {
+            seed: 0,
+            rules: MatchRules::default(),
+            side: 0,
+            opponent: OpponentPolicy::default(),
+            max_steps: 60 * 60 * 5,
+        }
+    }
+}
+
+/// The match as the agent sees it. Everything is turned around for the right paddle, so the agent
+/// always defends the left edge and a policy trained on one side works on both.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Observation {
+    pub ball_position: [f32; 2],
+    pub ball_velocity: [f32; 2],
+    /// The y position of the agent's paddle.
+    pub paddle: f32,
+    pub opponent_paddle: f32,
+    /// The agent's score first.
+    pub scores: [u32; 2],
+}
+
+impl Observation {
+    /// Positions scaled to roughly -1..1 and speeds to the arena width per second, for feeding a network.
+    pub fn to_features(&self) -> [f32; 6] {
+        [
+            self.ball_position[0] / (ARENA_WIDTH / 2.0),
+            self.ball_position[1] / (ARENA_HEIGHT / 2.0),
+            self.ball_velocity[0] / ARENA_WIDTH,
+            self.ball_velocity[1] / ARENA_WIDTH,
+            self.paddle / (ARENA_HEIGHT / 2.0),
+            self.opponent_paddle / (ARENA_HEIGHT / 2.0),
+        ]
+    }
+}
+
+pub struct PongEnv {
+    config: EnvConfig,
+    state: SimulationState,
+    steps: u32,
+    episodes: u64,
+    // How far the opponent is towards its next move, for the slowdown.
+    opponent_progress: f32,
+}
+
+impl PongEnv {
+    pub fn new(config: EnvConfig) -> Self {
+        PongEnv {
+            state: SimulationState::with_rules(config.seed, config.rules),
+            config,
+            steps: 0,
+            episodes: 0,
+            opponent_progress: 0.0,
+        }
+    }
+
+    /// Starts a new match. Every episode gets its own seed, so they don't all play out the same.
+    pub fn reset(&mut self) -> Observation {
+        self.episodes += 1;
+        self.state = SimulationState::with_rules(self.config.seed.wrapping_add(self.episodes), self.config.rules);
+        self.steps = 0;
+        self.opponent_progress = 0.0;
+        self.observation()
+    }
+
+    /// Runs one tick with the agent's paddle doing `action`: 1 is up, -1 down and 0 standing still.
+    /// The reward is 1 for every point the agent wins and -1 for every point it loses.
+    pub fn step(&mut self, action: PaddleInput) -> (Observation, f32, bool) {
+        let side = self.config.side;
+        let mut inputs = [0; 2];
+        inputs[side] = action;
+        inputs[1 - side] = self.opponent_input();
+
+        let mut reward = 0.0;
+        let mut done = false;
+        for event in self.state.step(inputs) {
+            match event {
+                SimulationEvent::Scored(scorer) => reward += if scorer == side { 1.0 } else { -1.0 },
+                SimulationEvent::MatchOver(_) => done = true,
+                _ => (),
+            }
+        }
+
+        self.steps += 1;
+        (self.observation(), reward, done || self.steps >= self.config.max_steps)
+    }
+
+    /// The simulation underneath, for anyone who wants more than the observation.
+    pub fn state(&self) -> &SimulationState {
+        &self.state
+    }
+
+    pub fn observation(&self) -> Observation {
+        let side = self.config.side;
+        let mirror = if side == 0 { 1.0 } else { -1.0 };
+        Observation {
+            ball_position: [self.state.ball_position[0] * mirror, self.state.ball_position[1]],
+            ball_velocity: [self.state.ball_velocity[0] * mirror, self.state.ball_velocity[1]],
+            paddle: self.state.paddles[side],
+            opponent_paddle: self.state.paddles[1 - side],
+            scores: [self.state.scores[side], self.state.scores[1 - side]],
+        }
+    }
+
+    fn opponent_input(&mut self) -> PaddleInput {
+        match self.config.opponent {
+            OpponentPolicy::Idle => 0,
+            OpponentPolicy::Chase { slowdown } => {
+                self.opponent_progress += 1.0 - slowdown;
+                if self.opponent_progress < 1.0 {
+                    return 0;
+                }
+                self.opponent_progress -= 1.0;
+                chase_input(self.state.paddles[1 - self.config.side], self.state.ball_position[1])
+            }
+        }
+    }
+}
+
+/// How a policy did over a number of episodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Evaluation {
+    pub wins: u32,
+    pub losses: u32,
+    /// Episodes that hit max_steps before anyone won.
+    pub unfinished: u32,
+    pub points_won: u32,
+    pub points_lost: u32,
+}
+
+/// Plays whole episodes with `policy` choosing the agent's moves, to compare it with the opponent.
+pub fn evaluate(env: &mut PongEnv, episodes: u32, mut policy: impl FnMut(&Observation) -> PaddleInput) -> Evaluation {
+    let mut evaluation = Evaluation::default();
+    for _ in 0..episodes {
+        let mut observation = env.reset();
+        loop {
+            let (next, _, done) = env.step(policy(&observation));
+            observation = next;
+            if done {
+                break;
+            }
+        }
+
+        evaluation.points_won += observation.scores[0];
+        evaluation.points_lost += observation.scores[1];
+        match env.state().winner {
+            Some(winner) if winner == env.config.side => evaluation.wins += 1,
+            Some(_) => evaluation.losses += 1,
+            None => evaluation.unfinished += 1,
+        }
+    }
+    evaluation
+}
+
+/// One line of the JSON protocol.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Request {
+    Reset,
+    Step { action: PaddleInput },
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct StepReply {
+    observation: Observation,
+    reward: f32,
+    done: bool,
+}
+
+/// Serves the environment over a line based JSON protocol, so agents written in other languages can
+/// train against it. Each request is one line, `{"command": "reset"}` or `{"command": "step", "action": 1}`,
+/// and each gets one line back: the observation after a reset, or the observation, reward and done after a step.
+/// Lines that don't parse get `{"error": ...}`.
+pub fn serve_json(env: &mut PongEnv, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let reply = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Reset) => serde_json::to_string(&env.reset()),
+            Ok(Request::Step { action }) => {
+                let (observation, reward, done) = env.step(action);
+                serde_json::to_string(&StepReply { observation, reward, done })
+            }
+            Err(error) => serde_json::to_string(&serde_json::json!({ "error": error.to_string() })),
+        }
+        .map_err(io::Error::other)?;
+
+        writeln!(output, "{}", reply)?;
+        output.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,257 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{Player, game_state::AppState, profile::Profiles, rules::{AiDifficulty, Controller, GameMode, GameRules}, score::MatchResult, storage};
+
+const HISTORY_FILE: &str = "history.ron";
+// How many matches fit on the history screen.
+const HISTORY_ROWS: usize = 14;
+
+pub struct HistoryPlugin;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Opponent {
+    Human,
+    Ai(AiDifficulty),
+    Bot,
+    /// A paddle driven by the match script.
+    Script,
+    Adaptive,
+    /// A paddle playing back the moves of an earlier match.
+    Replay,
+}
+
+impl Opponent {
+    pub fn name(&self) -> String {
+        match self {
+            Opponent::Human => "Human".to_string(),
+            Opponent::Ai(difficulty) => format!("AI ({})", difficulty.name()),
+            Opponent::Bot => "Bot".to_string(),
+            Opponent::Script => "Script AI".to_string(),
+            Opponent::Adaptive => "Adaptive AI".to_string(),
+            Opponent::Replay => "Replay".to_string(),
+        }
+    }
+}
+
+/// One finished match, from the point of view of the profile that played it on the left paddle.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatchRecord {
+    /// Seconds since the Unix epoch.
+    pub finished_at: u64,
+    pub profile: String,
+    pub mode: GameMode,
+    /// Everyone else in the match, in player order.
+    pub opponents: Vec<Opponent>,
+    pub score: u32,
+    /// In the same order as opponents.
+    pub opponent_scores: Vec<u32>,
+    pub won: bool,
+    /// How long the match took, in seconds.
+    pub duration: f32,
+}
+
+/// Every match ever recorded on this machine, oldest first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MatchHistory(pub Vec<MatchRecord>);
+
+// When the current match started, from Time::seconds_since_startup.
+struct MatchClock(f64);
+
+struct HistoryText;
+
+impl Plugin for HistoryPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .insert_resource(storage::load::<MatchHistory>(HISTORY_FILE))
+            .insert_resource(MatchClock(0.0))
+            .add_startup_system(setup.system())
+            .add_system(record_match.system())
+            .add_system(update_history_text.system())
+            .add_system_set(SystemSet::on_exit(AppState::Start).with_system(start_match_clock.system()))
+            .add_system_set(SystemSet::on_update(AppState::Start).with_system(open_history.system()))
+            .add_system_set(SystemSet::on_update(AppState::History).with_system(close_history.system()));
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    windows: Res<Windows>,
+) {
+    let window = windows.get_primary().unwrap();
+
+    commands
+    .spawn_bundle(TextBundle {
+        style: Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(60.0),
+                left: Val::Px(window.width() / 2.0 - 300.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::default(),
+        ..Default::default()
+    })
+    .insert(HistoryText);
+}
+
+fn start_match_clock(
+    time: Res<Time>,
+    mut clock: ResMut<MatchClock>,
+) {
+    clock.0 = time.seconds_since_startup();
+}
+
+// Only matches with a human on the left paddle are recorded, since that is where the active profile plays.
+fn record_match(
+    mut events: EventReader<MatchResult>,
+    rules: Res<GameRules>,
+    profiles: Res<Profiles>,
+    time: Res<Time>,
+    clock: Res<MatchClock>,
+    mut history: ResMut<MatchHistory>,
+) {
+    let mut recorded = false;
+
+    for result in events.iter() {
+        if !rules.controller(Player::Player1).is_human() {
+            continue;
+        }
+
+        let score_of = |player: Player| match player {
+            Player::Player1 => result.player1,
+            Player::Player2 => result.player2,
+            Player::Player3 => result.player3,
+            Player::Player4 => result.player4,
+        };
+        let others = opponents_of_player1(result);
+
+        history.0.push(MatchRecord {
+            finished_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+            profile: profiles.active().name.clone(),
+            mode: result.mode,
+            opponents: others.iter().map(|player| opponent(*player, result, &rules)).collect(),
+            score: score_of(Player::Player1),
+            opponent_scores: others.iter().map(|player| score_of(*player)).collect(),
+            won: result.winner == Player::Player1,
+            duration: (time.seconds_since_startup() - clock.0) as f32,
+        });
+        recorded = true;
+    }
+
+    if recorded {
+        storage::save(HISTORY_FILE, &*history);
+    }
+}
+
+/// Everyone Player1 played against in this match, in player order.
+pub fn opponents_of_player1(result: &MatchResult) -> Vec<Player> {
+    result.mode.players().iter().copied().filter(|player| *player != Player::Player1).collect()
+}
+
+/// Who was driving a paddle. AI opponents are told apart by the slowdown they played with.
+pub fn opponent(player: Player, result: &MatchResult, rules: &GameRules) -> Opponent {
+    match rules.controller(player) {
+        Controller::Bot => Opponent::Bot,
+        Controller::Script => Opponent::Script,
+        Controller::AdaptiveAi => Opponent::Adaptive,
+        Controller::Replay => Opponent::Replay,
+        controller if controller.is_ai() => Opponent::Ai(AiDifficulty::from_slowdown(result.handicaps[player.index()].ai_slowdown)),
+        _ => Opponent::Human,
+    }
+}
+
+fn open_history(
+    keys: Res<Input<KeyCode>>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if keys.just_pressed(KeyCode::H) {
+        app_state.set(AppState::History).unwrap();
+    }
+}
+
+fn close_history(
+    keys: Res<Input<KeyCode>>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if keys.just_pressed(KeyCode::Back) {
+        app_state.set(AppState::Start).unwrap();
+    }
+}
+
+fn update_history_text(
+    app_state: Res<State<AppState>>,
+    history: Res<MatchHistory>,
+    profiles: Res<Profiles>,
+    asset_server: Res<AssetServer>,
+    mut query: Query<&mut Text, With<HistoryText>>,
+) {
+    if let Ok(mut text) = query.single_mut() {
+        if *app_state.current() != AppState::History {
+            text.sections.clear();
+            return;
+        }
+
+        let name = &profiles.active().name;
+        let matches: Vec<&MatchRecord> = history.0.iter().filter(|record| &record.profile == name).collect();
+        let wins = matches.iter().filter(|record| record.won).count();
+
+        let rating = profiles.active().rating;
+        let mut lines = vec![format!("{}: won {} of {} matches, rated {:.0}\n", name, wins, matches.len(), rating)];
+        lines.extend(matches.iter().rev().take(HISTORY_ROWS).map(|record| {
+            let opponents: Vec<String> = record.opponents.iter().map(Opponent::name).collect();
+            let opponent_scores: Vec<String> = record.opponent_scores.iter().map(u32::to_string).collect();
+            format!(
+                "{}  {}  vs {}  {}-{}  {}  {}",
+                format_date(record.finished_at),
+                record.mode.name(),
+                opponents.join(", "),
+                record.score,
+                opponent_scores.join("-"),
+                if record.won { "Won" } else { "Lost" },
+                format_duration(record.duration),
+            )
+        }));
+        if matches.is_empty() {
+            lines.push("No matches yet".to_string());
+        }
+        lines.push("\nBackspace to go back".to_string());
+
+        let font = asset_server.load("fonts/font.ttf");
+        text.sections = lines
+            .into_iter()
+            .map(|line| TextSection {
+                value: format!("{}\n", line),
+                style: TextStyle {
+                    font: font.clone(),
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                },
+            })
+            .collect();
+    }
+}
+
+// Year, month and day from seconds since the epoch, in UTC. Howard Hinnant's days to civil date.
+fn format_date(seconds: u64) -> String {
+    let days = (seconds / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn format_duration(seconds: f32) -> String {
+    let seconds = seconds.max(0.0) as u32;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
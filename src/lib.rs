@@ -0,0 +1,12 @@
+// The parts of Pong50 that don't need Bevy, shared by the game and the dedicated server.
+
+pub mod simulation;
+pub mod net;
+pub mod protocol;
+pub mod server;
+pub mod client;
+pub mod discovery;
+pub mod gym;
+pub mod bot;
+pub mod scripting;
+pub mod tournament;
@@ -0,0 +1,389 @@
+use std::{io, net::SocketAddr, sync::{mpsc::{self, Receiver, TryRecvError}, Mutex}, thread, time::Instant};
+
+use bevy::prelude::*;
+use pong50::{client::ServerConnection, discovery::LanBrowser, server::{DedicatedServer, ServerConfig, DEFAULT_PORT}, simulation::{MatchRules, LOCAL_ONLY_RULES, SPEED_UP_OPTIONS, VICTORY_SCORE_OPTIONS}};
+
+use crate::{game_state::AppState, menu::cycle, online::ServerSession, profile::Profiles, rules::GameRules};
+
+pub struct LobbyPlugin;
+
+struct LobbyText;
+
+// The highlighted row, and anything that went wrong that the player should know about.
+#[derive(Default)]
+struct LobbyScreen {
+    row: usize,
+    message: String,
+}
+
+struct GameBrowser(LanBrowser);
+
+// Joining a game waits for the server to answer, so it happens on a thread of its own and the
+// connection turns up here once it is ready. The Mutex is only there to make it Sync.
+struct Joining(Mutex<Receiver<io::Result<ServerConnection>>>);
+
+// A server we started ourselves. It keeps running until the game is closed, so hosting again
+// just joins it.
+struct HostedServer(SocketAddr);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LobbyRow {
+    Side,
+    VictoryScore,
+    SpeedUp,
+    Ready,
+}
+
+const LOBBY_ROWS: [LobbyRow; 4] = [LobbyRow::Side, LobbyRow::VictoryScore, LobbyRow::SpeedUp, LobbyRow::Ready];
+
+impl Plugin for LobbyPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<LobbyScreen>()
+            .add_startup_system(setup.system())
+            .add_system(update_lobby_text.system())
+            .add_system_set(SystemSet::on_update(AppState::Start).with_system(open_lobby.system()))
+            .add_system_set(
+                SystemSet::on_update(AppState::Lobby)
+                    .with_system(browse_games.system())
+                    .with_system(lobby_navigation.system())
+            );
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    windows: Res<Windows>,
+) {
+    let window = windows.get_primary().unwrap();
+
+    // Sits where the Start menu does, they are never on screen together.
+    commands
+    .spawn_bundle(TextBundle {
+        style: Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(window.height() / 2.0 + 60.0),
+                left: Val::Px(window.width() / 2.0 - 150.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::default(),
+        ..Default::default()
+    })
+    .insert(LobbyText);
+}
+
+// L on the Start screen takes us to the LAN lobby. Networked matches are always plain Classic,
+// the lobby has its own rules.
+fn open_lobby(
+    keys: Res<Input<KeyCode>>,
+    mut rules: ResMut<GameRules>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if keys.just_pressed(KeyCode::L) {
+        *rules = GameRules::default();
+        app_state.set(AppState::Lobby).unwrap();
+    }
+}
+
+// Starts a server on this machine for other people to find.
+fn host_game() -> std::io::Result<SocketAddr> {
+    let config = ServerConfig {
+        bind: SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT)),
+        seed: rand::random(),
+        name: "Pong50".to_string(),
+    };
+    let mut server = DedicatedServer::bind(&config)?;
+    let port = server.local_addr()?.port();
+    thread::spawn(move || {
+        server.run();
+    });
+    Ok(SocketAddr::from(([127, 0, 0, 1], port)))
+}
+
+// Before we have joined anything: the first row hosts a new game and the rest are the games we found.
+fn browse_games(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    server: Option<Res<ServerSession>>,
+    browser: Option<ResMut<GameBrowser>>,
+    hosted: Option<Res<HostedServer>>,
+    joining: Option<Res<Joining>>,
+    profiles: Res<Profiles>,
+    mut screen: ResMut<LobbyScreen>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if server.is_some() {
+        return;
+    }
+
+    if let Some(joining) = joining {
+        let joined = joining.0.lock().map_err(|_| TryRecvError::Disconnected).and_then(|receiver| receiver.try_recv());
+        match joined {
+            Ok(Ok(connection)) => {
+                commands.insert_resource(ServerSession(connection));
+                screen.row = 0;
+                screen.message.clear();
+            }
+            Ok(Err(error)) => screen.message = format!("Could not join: {}", error),
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => screen.message = "Could not join the game".to_string(),
+        }
+        commands.remove_resource::<Joining>();
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Back) {
+        app_state.set(AppState::Start).unwrap();
+        return;
+    }
+
+    let mut browser = match browser {
+        Some(browser) => browser,
+        None => {
+            match LanBrowser::new() {
+                Ok(browser) => {
+                    commands.insert_resource(GameBrowser(browser));
+                }
+                Err(error) => screen.message = format!("Can't look for games: {}", error),
+            }
+            return;
+        }
+    };
+    if let Err(error) = browser.0.poll(Instant::now()) {
+        screen.message = format!("Can't look for games: {}", error);
+    }
+
+    let rows = browser.0.games().len() + 1;
+    if keys.just_pressed(KeyCode::Up) {
+        screen.row = (screen.row + rows - 1) % rows;
+    }
+    if keys.just_pressed(KeyCode::Down) {
+        screen.row = (screen.row + 1) % rows;
+    }
+    // Games come and go while we look at the list.
+    screen.row = screen.row.min(rows - 1);
+
+    if !keys.just_pressed(KeyCode::Return) {
+        return;
+    }
+
+    let address = if screen.row > 0 {
+        browser.0.games()[screen.row - 1].address
+    } else if let Some(hosted) = hosted {
+        hosted.0
+    } else {
+        match host_game() {
+            Ok(address) => {
+                commands.insert_resource(HostedServer(address));
+                address
+            }
+            Err(error) => {
+                screen.message = format!("Could not host a game: {}", error);
+                return;
+            }
+        }
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    let name = profiles.active().name.clone();
+    thread::spawn(move || {
+        let _ = sender.send(ServerConnection::connect(address, &name));
+    });
+    commands.insert_resource(Joining(Mutex::new(receiver)));
+    screen.message = format!("Joining {}...", address);
+}
+
+// Once we are in a game's lobby: pick a side, agree on the rules and ready up. The server starts
+// the match when both sides are ready.
+fn lobby_navigation(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    server: Option<ResMut<ServerSession>>,
+    mut screen: ResMut<LobbyScreen>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    let mut server = match server {
+        Some(server) => server,
+        None => return,
+    };
+    let connection = &mut server.0;
+
+    if let Err(error) = connection.poll() {
+        screen.message = format!("Lost the server: {}", error);
+    }
+
+    if !connection.is_connected() || keys.just_pressed(KeyCode::Back) {
+        if !connection.is_connected() {
+            screen.message = "The game has gone away".to_string();
+        }
+        commands.remove_resource::<ServerSession>();
+        screen.row = 0;
+        return;
+    }
+
+    if connection.in_match() {
+        app_state.set(AppState::Online).unwrap();
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::Up) {
+        screen.row = (screen.row + LOBBY_ROWS.len() - 1) % LOBBY_ROWS.len();
+    }
+    if keys.just_pressed(KeyCode::Down) {
+        screen.row = (screen.row + 1) % LOBBY_ROWS.len();
+    }
+    screen.row = screen.row.min(LOBBY_ROWS.len() - 1);
+
+    let mut step = 0;
+    if keys.just_pressed(KeyCode::Left) {
+        step = -1;
+    } else if keys.just_pressed(KeyCode::Right) {
+        step = 1;
+    }
+
+    let lobby = connection.lobby().clone();
+    let you = connection.player();
+    let row = LOBBY_ROWS[screen.row];
+
+    let result = if keys.just_pressed(KeyCode::Return) || (row == LobbyRow::Ready && step != 0) {
+        match you {
+            Some(seat) => connection.set_ready(!lobby.ready[seat]),
+            None => Ok(()),
+        }
+    } else if step == 0 {
+        Ok(())
+    } else {
+        match row {
+            LobbyRow::Side => {
+                // Skip over the side someone else is sitting on.
+                let sides: Vec<Option<usize>> = [None, Some(0), Some(1)]
+                    .iter()
+                    .copied()
+                    .filter(|side| side.is_none_or(|side| lobby.seats[side].is_none() || you == Some(side)))
+                    .collect();
+                connection.choose_side(cycle(&sides, you, step))
+            }
+            LobbyRow::VictoryScore => connection.propose_rules(MatchRules {
+                victory_score: cycle(&VICTORY_SCORE_OPTIONS, lobby.rules.victory_score, step),
+                ..lobby.rules
+            }),
+            LobbyRow::SpeedUp => connection.propose_rules(MatchRules {
+                speed_up: cycle(&SPEED_UP_OPTIONS, lobby.rules.speed_up, step),
+                ..lobby.rules
+            }),
+            LobbyRow::Ready => Ok(()),
+        }
+    };
+
+    if let Err(error) = result {
+        screen.message = format!("Lost the server: {}", error);
+    }
+}
+
+fn update_lobby_text(
+    app_state: Res<State<AppState>>,
+    screen: Res<LobbyScreen>,
+    server: Option<Res<ServerSession>>,
+    browser: Option<Res<GameBrowser>>,
+    asset_server: Res<AssetServer>,
+    mut query: Query<&mut Text, With<LobbyText>>,
+) {
+    if let Ok(mut text) = query.single_mut() {
+        if *app_state.current() != AppState::Lobby {
+            text.sections.clear();
+            return;
+        }
+
+        // Each line, and whether it is the highlighted one.
+        let mut lines: Vec<(String, bool)> = Vec::new();
+
+        let hint = match &server {
+            Some(server) => {
+                let lobby = server.0.lobby();
+                let you = server.0.player();
+
+                for (index, row) in LOBBY_ROWS.iter().enumerate() {
+                    let value = match *row {
+                        LobbyRow::Side => {
+                            let side = match you {
+                                Some(0) => "Left",
+                                Some(_) => "Right",
+                                None => "Watching",
+                            };
+                            format!("Side: < {} >", side)
+                        }
+                        LobbyRow::VictoryScore => format!("Points To Win: {}", lobby.rules.victory_score),
+                        LobbyRow::SpeedUp => format!("Speed Up: {:.0}%", (lobby.rules.speed_up - 1.0) * 100.0),
+                        LobbyRow::Ready => {
+                            let ready = you.is_some_and(|seat| lobby.ready[seat]);
+                            format!("Ready: {}", if ready { "Yes" } else { "No" })
+                        }
+                    };
+                    lines.push((value, index == screen.row));
+                }
+
+                for (seat, side) in ["Left", "Right"].iter().enumerate() {
+                    let who = match lobby.seats[seat] {
+                        Some(_) if you == Some(seat) => format!("{} (You)", lobby.names[seat]),
+                        Some(_) => lobby.names[seat].clone(),
+                        None => "Open".to_string(),
+                    };
+                    let ready = if lobby.ready[seat] { ", Ready" } else { "" };
+                    lines.push((format!("    {}: {}{}", side, who, ready), false));
+                }
+                lines.push((format!("    {}", LOCAL_ONLY_RULES), false));
+
+                "Enter to ready up, Backspace to leave"
+            }
+            None => {
+                lines.push(("Host a game".to_string(), screen.row == 0));
+                let games = browser.as_ref().map_or(&[][..], |browser| browser.0.games());
+                for (index, game) in games.iter().enumerate() {
+                    lines.push((format!("Join {} at {} ({}/2)", game.name, game.address, game.players), screen.row == index + 1));
+                }
+                if games.is_empty() {
+                    lines.push(("    Looking for games...".to_string(), false));
+                }
+
+                "Enter to join, Backspace to go back"
+            }
+        };
+
+        let font = asset_server.load("fonts/font.ttf");
+        text.sections = lines
+            .into_iter()
+            .map(|(value, selected)| TextSection {
+                value: format!("{} {}\n", if selected { ">" } else { " " }, value),
+                style: TextStyle {
+                    font: font.clone(),
+                    font_size: 18.0,
+                    color: if selected { Color::YELLOW } else { Color::WHITE },
+                },
+            })
+            .collect();
+
+        text.sections.push(TextSection {
+            value: format!("\n{}", hint),
+            style: TextStyle {
+                font: font.clone(),
+                font_size: 15.0,
+                color: Color::GRAY,
+            },
+        });
+
+        if !screen.message.is_empty() {
+            text.sections.push(TextSection {
+                value: format!("\n{}", screen.message),
+                style: TextStyle {
+                    font,
+                    font_size: 15.0,
+                    color: Color::RED,
+                },
+            });
+        }
+    }
+}
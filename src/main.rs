@@ -1,18 +1,78 @@
+// Bevy systems take everything they need as arguments and queries, so long argument lists and
+// query types are how systems look here.
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
 use bevy::{prelude::*, input::system::exit_on_esc_system, diagnostic::{FrameTimeDiagnosticsPlugin, Diagnostics}};
+use serde::{Deserialize, Serialize};
 
 mod paddle;
 mod ball;
 mod score;
 mod game_state;
+mod rules;
+mod menu;
+mod arena;
+mod online;
+mod lobby;
+mod spectator;
+mod storage;
+mod profile;
+mod history;
+mod rating;
+mod stats;
+mod achievements;
+mod save;
+mod controller;
+mod scripts;
+mod practice;
+mod squash;
 
 use paddle::PaddlePlugin;
 use game_state::GameStatePlugin;
 use score::ScorePlugin;
 use ball::BallPlugin;
+use menu::MenuPlugin;
+use arena::ArenaPlugin;
+use pong50::{bot::BotConfig, client::ServerConnection, net::{OnlineConfig, RollbackSession, default_player_name, player_name_from_args}, simulation::{ARENA_HEIGHT, ARENA_WIDTH}};
+use online::{OnlinePlugin, OnlineSession, ServerSession};
+use lobby::LobbyPlugin;
+use spectator::SpectatorPlugin;
+use profile::{ProfilePlugin, Profiles};
+use history::HistoryPlugin;
+use rating::RatingPlugin;
+use stats::StatsPlugin;
+use achievements::AchievementsPlugin;
+use save::SavePlugin;
+use scripts::ScriptsPlugin;
+use practice::PracticePlugin;
+use squash::SquashPlugin;
+use controller::Bots;
+use rules::{Controller, GameMode, GameRules};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Player {
     Player1,
     Player2,
+    Player3,
+    Player4,
+}
+
+impl Player {
+    pub const ALL: [Player; 4] = [Player::Player1, Player::Player2, Player::Player3, Player::Player4];
+
+    pub fn index(&self) -> usize {
+        match self {
+            Player::Player1 => 0,
+            Player::Player2 => 1,
+            Player::Player3 => 2,
+            Player::Player4 => 3,
+        }
+    }
+
+    /// Player1 and Player2 defend the left and right edges, Player3 and Player4 the top and bottom.
+    pub fn is_horizontal(&self) -> bool {
+        matches!(self, Player::Player3 | Player::Player4)
+    }
 }
 
 struct Server(Player);
@@ -26,11 +86,33 @@ pub struct LoadedAudio {
 struct FPSText;
 
 fn main() {
-    App::build()
+    // Online play is set up from the command line, see OnlineConfig::from_args.
+    let online_config = OnlineConfig::from_args(std::env::args()).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    });
+
+    // `--bot side=program` lets a program outside the game play that side, see pong50::bot.
+    let bot_config = BotConfig::from_args(std::env::args()).unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    });
+
+    // `--name` picks a profile, or makes a new one. Without it we carry on with whoever played last.
+    let mut profiles = Profiles::load();
+    match player_name_from_args(std::env::args()) {
+        Some(name) => profiles.select_or_create(&name),
+        None if profiles.list.is_empty() => profiles.select_or_create(&default_player_name()),
+        None => profiles.active = profiles.active.min(profiles.list.len() - 1),
+    }
+    let player_name = profiles.active().name.clone();
+
+    let mut app = App::build();
+    app
         .insert_resource(WindowDescriptor {
             title: "Pong50".to_string(),
-            width: 1080.0,
-            height: 720.0,
+            width: ARENA_WIDTH,
+            height: ARENA_HEIGHT,
             resizable: false,
             ..Default::default()
         })
@@ -38,13 +120,57 @@ fn main() {
         .add_plugins(DefaultPlugins)
         .add_plugin(PaddlePlugin)
         .add_plugin(BallPlugin)
+        .add_plugin(ArenaPlugin)
         .add_plugin(ScorePlugin)
         .add_plugin(GameStatePlugin)
+        .add_plugin(MenuPlugin)
+        .add_plugin(OnlinePlugin)
+        .add_plugin(LobbyPlugin)
+        .add_plugin(SpectatorPlugin)
+        .add_plugin(ProfilePlugin)
+        .add_plugin(HistoryPlugin)
+        .add_plugin(RatingPlugin)
+        .add_plugin(StatsPlugin)
+        .add_plugin(AchievementsPlugin)
+        .add_plugin(SavePlugin)
+        .add_plugin(ScriptsPlugin)
+        .add_plugin(PracticePlugin)
+        .add_plugin(SquashPlugin)
         .add_plugin(FrameTimeDiagnosticsPlugin)
         .add_startup_system(setup.system())
         .add_system(update_fps_text.system())
-        .add_system(exit_on_esc_system.system())
-        .run();
+        .add_system(exit_on_esc_system.system());
+
+    match online_config {
+        Some(OnlineConfig::PeerToPeer(config)) => {
+            let session = RollbackSession::new(config).expect("could not open the UDP socket for online play");
+            app.insert_resource(OnlineSession::new(session));
+        }
+        Some(OnlineConfig::Server(address)) => {
+            let connection = ServerConnection::connect(address, &player_name).unwrap_or_else(|error| {
+                eprintln!("Could not join the server at {}: {}", address, error);
+                std::process::exit(1);
+            });
+            app.insert_resource(ServerSession(connection));
+        }
+        None => (),
+    }
+    app.insert_resource(profiles);
+
+    // Bots start out playing the sides they were given. A bot on the top or bottom needs the four player mode.
+    let mut rules = GameRules::default();
+    for (index, bot) in bot_config.bots.iter().enumerate() {
+        if bot.is_some() {
+            rules.controllers[index] = Controller::Bot;
+            if index >= 2 {
+                rules.mode = GameMode::FourPlayer;
+            }
+        }
+    }
+    app.insert_resource(rules);
+    app.insert_resource(Bots(bot_config));
+
+    app.run();
 }
 
 fn setup(
@@ -0,0 +1,334 @@
+use bevy::prelude::*;
+
+use crate::{Player, arena::Arenas, controller::{Bots, Recording}, game_state::AppState, profile::{ControlScheme, Profiles, Theme}, save::ResumableMatch, scripts::Scripts, rules::{GameRules, GameMode, Scoring, Controller, Movement, SpinPattern, SpeedCurve, MACHINE_SPEED_OPTIONS, MACHINE_ANGLE_OPTIONS, SPIN_OPTIONS, MAX_SPEED_OPTIONS, POINT_BONUS_OPTIONS, PADDLE_SIZE_OPTIONS, PADDLE_SPEED_OPTIONS, AI_SLOWDOWN_OPTIONS}};
+
+pub struct MenuPlugin;
+
+// Rows shown at once. The menu scrolls to keep the cursor in view when there are more.
+const MENU_VISIBLE_ROWS: usize = 10;
+
+struct MenuText;
+
+// The highlighted row, and whose settings the per player rows are showing.
+struct MenuCursor {
+    row: usize,
+    player: Player,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MenuRow {
+    // Whose profile the match is recorded for, and their preferences.
+    Profile,
+    Controls,
+    Theme,
+    Mode,
+    Arena,
+    // The script whose hooks tweak the rules, and that Script AI paddles follow.
+    Script,
+    Spin,
+    // How the ball speeds up over a rally, and how fast it can get.
+    SpeedCurve,
+    MaxSpeed,
+    Movement,
+    Scoring,
+    // How the ball machine serves in practice.
+    MachineSpeed,
+    MachineAngle,
+    MachineSpin,
+    // Picks which player the rows below it are editing.
+    Player,
+    Control,
+    PointBonus,
+    PaddleSize,
+    PaddleSpeed,
+    AiSlowdown,
+}
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .insert_resource(GameRules::default())
+            .insert_resource(MenuCursor { row: 0, player: Player::Player1 })
+            .add_startup_system(setup.system())
+            .add_system(update_menu_text.system())
+            .add_system_set(SystemSet::on_update(AppState::Start).with_system(menu_navigation.system()));
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    windows: Res<Windows>,
+) {
+    let window = windows.get_primary().unwrap();
+
+    // The sections get filled in by update_menu_text, one per row.
+    commands
+    .spawn_bundle(TextBundle {
+        style: Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(window.height() / 2.0 + 25.0),
+                left: Val::Px(window.width() / 2.0 - 150.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::default(),
+        ..Default::default()
+    })
+    .insert(MenuText);
+}
+
+// Only the rows that mean something for the current rules are shown.
+fn menu_rows(rules: &GameRules, player: Player) -> Vec<MenuRow> {
+    let mut rows = vec![MenuRow::Profile, MenuRow::Controls, MenuRow::Theme, MenuRow::Mode, MenuRow::Arena, MenuRow::Script, MenuRow::Spin, MenuRow::SpeedCurve, MenuRow::MaxSpeed, MenuRow::Movement];
+    if rules.mode == GameMode::FourPlayer {
+        rows.push(MenuRow::Scoring);
+    }
+    if rules.mode == GameMode::Practice {
+        rows.extend_from_slice(&[MenuRow::MachineSpeed, MenuRow::MachineAngle]);
+        // With spin turned off the machine's spin wouldn't do anything.
+        if rules.spin > 0.0 {
+            rows.push(MenuRow::MachineSpin);
+        }
+    }
+    rows.extend_from_slice(&[MenuRow::Player, MenuRow::Control]);
+    // Bonus points mean nothing without someone to have a head start on.
+    if !rules.mode.is_solo() {
+        rows.push(MenuRow::PointBonus);
+    }
+    rows.extend_from_slice(&[MenuRow::PaddleSize, MenuRow::PaddleSpeed]);
+    if rules.controller(player).is_ai() {
+        rows.push(MenuRow::AiSlowdown);
+    }
+    rows
+}
+
+// Steps through a list of options, wrapping around at either end.
+pub fn cycle<T: Copy + PartialEq>(options: &[T], current: T, step: i32) -> T {
+    let index = options.iter().position(|option| *option == current).unwrap_or(0) as i32;
+    let len = options.len() as i32;
+    options[((index + step) % len + len) as usize % options.len()]
+}
+
+// Up and Down pick a row, Left and Right change the value on that row.
+fn menu_navigation(
+    keys: Res<Input<KeyCode>>,
+    mut cursor: ResMut<MenuCursor>,
+    mut rules: ResMut<GameRules>,
+    mut profiles: ResMut<Profiles>,
+    arenas: Res<Arenas>,
+    bots: Res<Bots>,
+    scripts: Res<Scripts>,
+    recording: Res<Recording>,
+) {
+    let rows = menu_rows(&rules, cursor.player);
+
+    if keys.just_pressed(KeyCode::Up) {
+        cursor.row = (cursor.row + rows.len() - 1) % rows.len();
+    }
+    if keys.just_pressed(KeyCode::Down) {
+        cursor.row = (cursor.row + 1) % rows.len();
+    }
+    cursor.row = cursor.row.min(rows.len() - 1);
+
+    let mut step = 0;
+    if keys.just_pressed(KeyCode::Left) {
+        step = -1;
+    } else if keys.just_pressed(KeyCode::Right) {
+        step = 1;
+    }
+    if step == 0 {
+        return;
+    }
+
+    let player = cursor.player;
+    match rows[cursor.row] {
+        MenuRow::Profile => {
+            let indices: Vec<usize> = (0..profiles.list.len()).collect();
+            profiles.active = cycle(&indices, profiles.active, step);
+        }
+        MenuRow::Controls => {
+            let profile = profiles.active_mut();
+            profile.controls = cycle(&ControlScheme::ALL, profile.controls, step);
+        }
+        MenuRow::Theme => {
+            let profile = profiles.active_mut();
+            profile.theme = cycle(&Theme::ALL, profile.theme, step);
+        }
+        MenuRow::Mode => {
+            rules.mode = cycle(&GameMode::ALL, rules.mode, step);
+            // Don't leave the cursor on a player that isn't playing anymore.
+            if !rules.mode.players().contains(&cursor.player) {
+                cursor.player = Player::Player1;
+            }
+        }
+        MenuRow::Arena => {
+            let indices: Vec<usize> = (0..arenas.0.len()).collect();
+            rules.arena = cycle(&indices, rules.arena, step);
+        }
+        MenuRow::Script => {
+            let mut options: Vec<Option<usize>> = vec![None];
+            options.extend((0..scripts.0.len()).map(Some));
+            rules.script = cycle(&options, rules.script, step);
+            // A script without an intent hook can't drive a paddle, so those players go back to the usual AI.
+            if !scripts.active(&rules).is_some_and(|script| script.has_hook("intent")) {
+                for controller in rules.controllers.iter_mut().filter(|controller| **controller == Controller::Script) {
+                    *controller = Controller::Ai;
+                }
+            }
+        }
+        MenuRow::Spin => rules.spin = cycle(&SPIN_OPTIONS, rules.spin, step),
+        MenuRow::SpeedCurve => rules.speed_curve = cycle(&SpeedCurve::ALL, rules.speed_curve, step),
+        MenuRow::MaxSpeed => rules.max_speed = cycle(&MAX_SPEED_OPTIONS, rules.max_speed, step),
+        MenuRow::Movement => rules.movement = cycle(&Movement::ALL, rules.movement, step),
+        MenuRow::Scoring => rules.scoring = cycle(&Scoring::ALL, rules.scoring, step),
+        MenuRow::MachineSpeed => rules.machine.speed = cycle(&MACHINE_SPEED_OPTIONS, rules.machine.speed, step),
+        MenuRow::MachineAngle => rules.machine.angle = cycle(&MACHINE_ANGLE_OPTIONS, rules.machine.angle, step),
+        MenuRow::MachineSpin => rules.machine.spin = cycle(&SpinPattern::ALL, rules.machine.spin, step),
+        MenuRow::Player => cursor.player = cycle(rules.mode.players(), cursor.player, step),
+        MenuRow::Control => {
+            // A bot can only be picked for players that were given one on the command line, the
+            // Script AI only when the match script knows how to move a paddle, and a replay only
+            // once this player has a match to play back.
+            let mut options = Controller::ALL.to_vec();
+            if bots.0.bots[player.index()].is_some() {
+                options.push(Controller::Bot);
+            }
+            if scripts.active(&rules).is_some_and(|script| script.has_hook("intent")) {
+                options.push(Controller::Script);
+            }
+            if !recording.latest(player).is_empty() {
+                options.push(Controller::Replay);
+            }
+            let controller = cycle(&options, rules.controller(player), step);
+            rules.controllers[player.index()] = controller;
+        }
+        MenuRow::PointBonus => {
+            let handicap = rules.handicap_mut(player);
+            handicap.point_bonus = cycle(&POINT_BONUS_OPTIONS, handicap.point_bonus, step);
+        }
+        MenuRow::PaddleSize => {
+            let handicap = rules.handicap_mut(player);
+            handicap.paddle_size = cycle(&PADDLE_SIZE_OPTIONS, handicap.paddle_size, step);
+        }
+        MenuRow::PaddleSpeed => {
+            let handicap = rules.handicap_mut(player);
+            handicap.paddle_speed = cycle(&PADDLE_SPEED_OPTIONS, handicap.paddle_speed, step);
+        }
+        MenuRow::AiSlowdown => {
+            let handicap = rules.handicap_mut(player);
+            handicap.ai_slowdown = cycle(&AI_SLOWDOWN_OPTIONS, handicap.ai_slowdown, step);
+        }
+    }
+}
+
+fn update_menu_text(
+    app_state: Res<State<AppState>>,
+    cursor: Res<MenuCursor>,
+    rules: Res<GameRules>,
+    profiles: Res<Profiles>,
+    arenas: Res<Arenas>,
+    scripts: Res<Scripts>,
+    resumable: Res<ResumableMatch>,
+    asset_server: Res<AssetServer>,
+    mut query: Query<&mut Text, With<MenuText>>,
+) {
+    if let Ok(mut text) = query.single_mut() {
+        // The menu is only shown on the Start screen.
+        if *app_state.current() != AppState::Start {
+            text.sections.clear();
+            return;
+        }
+
+        let player = cursor.player;
+        let handicap = rules.handicap(player);
+        let profile = profiles.active();
+        let font = asset_server.load("fonts/font.ttf");
+        let rows = menu_rows(&rules, player);
+        // The cursor can be past the end for a frame, until menu_navigation catches up with a change of rows.
+        let first = (cursor.row.min(rows.len() - 1) + 1).saturating_sub(MENU_VISIBLE_ROWS);
+        let last = (first + MENU_VISIBLE_ROWS).min(rows.len());
+        text.sections = rows[first..last]
+            .iter()
+            .enumerate()
+            .map(|(index, row)| {
+                let index = first + index;
+                let value = match *row {
+                    MenuRow::Profile => format!("Profile: < {} >", profile.name),
+                    MenuRow::Controls => format!("  Keys: {}", profile.controls.name()),
+                    MenuRow::Theme => format!("  Theme: {}", profile.theme.name()),
+                    MenuRow::Mode => format!("Mode: {}", rules.mode.name()),
+                    MenuRow::Arena => {
+                        let name = arenas.0.get(rules.arena).map_or("?", |arena| arena.name.as_str());
+                        format!("Arena: {}", name)
+                    }
+                    MenuRow::Script => {
+                        let name = scripts.active(&rules).map_or("None", |script| script.name.as_str());
+                        format!("Script: {}", name)
+                    }
+                    MenuRow::Spin if rules.spin == 0.0 => "Spin: Off".to_string(),
+                    MenuRow::Spin => format!("Spin: {:.0}%", rules.spin * 100.0),
+                    MenuRow::SpeedCurve => format!("Speed Up: {}", rules.speed_curve.name()),
+                    MenuRow::MaxSpeed => format!("Max Speed: {:.0} px/s", rules.max_speed),
+                    MenuRow::Movement => format!("Paddles: {}", rules.movement.name()),
+                    MenuRow::Scoring => format!("Scoring: {}", rules.scoring.name()),
+                    MenuRow::MachineSpeed => format!("  Machine Speed: {:.0} px/s", rules.machine.speed),
+                    MenuRow::MachineAngle => format!("  Machine Angle: up to {:.0} degrees", rules.machine.angle),
+                    MenuRow::MachineSpin => format!("  Machine Spin: {}", rules.machine.spin.name()),
+                    MenuRow::Player => format!("Settings For: < {} >", player_label(player)),
+                    MenuRow::Control => format!("  Control: {}", rules.controller(player).name()),
+                    MenuRow::PointBonus => format!("  Bonus Points: {}", handicap.point_bonus),
+                    MenuRow::PaddleSize => format!("  Paddle Size: {:.0}%", handicap.paddle_size * 100.0),
+                    MenuRow::PaddleSpeed => format!("  Paddle Speed: {:.0}%", handicap.paddle_speed * 100.0),
+                    MenuRow::AiSlowdown => format!("  AI Slowdown: {:.0}%", handicap.ai_slowdown * 100.0),
+                };
+                let selected = index == cursor.row;
+                TextSection {
+                    value: format!("{} {}\n", if selected { ">" } else { " " }, value),
+                    style: TextStyle {
+                        font: font.clone(),
+                        font_size: 16.0,
+                        color: if selected { Color::YELLOW } else { Color::WHITE },
+                    },
+                }
+            })
+            .collect();
+
+        // Let on that there is more to the menu than what fits.
+        let more = TextSection {
+            value: "  ...\n".to_string(),
+            style: TextStyle {
+                font: font.clone(),
+                font_size: 16.0,
+                color: Color::GRAY,
+            },
+        };
+        if first > 0 {
+            text.sections.insert(0, more.clone());
+        }
+        if last < rows.len() {
+            text.sections.push(more);
+        }
+
+        let resume = if resumable.0.is_some() { ", C to continue your saved match" } else { "" };
+        text.sections.push(TextSection {
+            value: format!("\nEnter to play{}, L for a LAN game\nH for history, R for ratings, A for achievements", resume),
+            style: TextStyle {
+                font,
+                font_size: 15.0,
+                color: Color::GRAY,
+            },
+        });
+    }
+}
+
+pub fn player_label(player: Player) -> &'static str {
+    match player {
+        Player::Player1 => "P1 (Left)",
+        Player::Player2 => "P2 (Right)",
+        Player::Player3 => "P3 (Top)",
+        Player::Player4 => "P4 (Bottom)",
+    }
+}
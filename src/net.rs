@@ -0,0 +1,496 @@
+use std::{collections::BTreeMap, io, net::{SocketAddr, UdpSocket}};
+
+use crate::{protocol::clean_name, simulation::{PaddleInput, SimulationEvent, SimulationState}};
+
+// How far ahead of the last input we got from the other peer we are willing to guess.
+// Past this we stop and wait for them to catch up.
+const MAX_PREDICTION_FRAMES: u32 = 8;
+const MAX_INPUTS_PER_PACKET: usize = 64;
+// How often the peers compare checksums of a frame they both have confirmed inputs for.
+const CHECKSUM_INTERVAL: u32 = 30;
+const PACKET_MAGIC: [u8; 2] = *b"PN";
+
+/// Everything needed to set up an online match, read from the command line.
+#[derive(Debug, Clone)]
+pub struct NetConfig {
+    pub local_addr: SocketAddr,
+    pub remote_addr: SocketAddr,
+    /// 0 for Player1 on the left, 1 for Player2 on the right.
+    pub local_player: usize,
+    /// How many frames local inputs are held back. A little delay means fewer rollbacks.
+    pub input_delay: u32,
+    /// Both peers must use the same seed so they serve the same balls.
+    pub seed: u64,
+}
+
+/// How the game was asked to play online, if at all.
+#[derive(Debug, Clone)]
+pub enum OnlineConfig {
+    /// Two peers running the simulation side by side with rollback.
+    PeerToPeer(NetConfig),
+    /// A dedicated server runs the match and we only draw it.
+    Server(SocketAddr),
+}
+
+impl OnlineConfig {
+    /// Looks for `--online` or `--connect` in the arguments. Without either we play locally and this returns `Ok(None)`.
+    ///
+    /// `pong50 --online --local 127.0.0.1:7000 --remote 127.0.0.1:7001 --player 1 [--input-delay 2] [--seed 0]`
+    ///
+    /// `pong50 --connect 127.0.0.1:7100`
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Option<OnlineConfig>, String> {
+        let mut online = false;
+        let mut server_addr = None;
+        let mut local_addr = None;
+        let mut remote_addr = None;
+        let mut local_player = 0;
+        let mut input_delay = 2;
+        let mut seed = 0;
+
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+            match arg.as_str() {
+                "--online" => online = true,
+                "--connect" => server_addr = Some(parse(&value("--connect")?)?),
+                "--local" => local_addr = Some(parse(&value("--local")?)?),
+                "--remote" => remote_addr = Some(parse(&value("--remote")?)?),
+                "--player" => {
+                    local_player = match value("--player")?.as_str() {
+                        "1" => 0,
+                        "2" => 1,
+                        other => return Err(format!("--player must be 1 or 2, not {}", other)),
+                    }
+                }
+                "--input-delay" => input_delay = parse(&value("--input-delay")?)?,
+                "--seed" => seed = parse(&value("--seed")?)?,
+                _ => (),
+            }
+        }
+
+        match (online, server_addr) {
+            (true, Some(_)) => Err("--online and --connect can't be used together".to_string()),
+            (false, Some(address)) => Ok(Some(OnlineConfig::Server(address))),
+            (false, None) => Ok(None),
+            (true, None) => Ok(Some(OnlineConfig::PeerToPeer(NetConfig {
+                local_addr: local_addr.ok_or("--online needs --local")?,
+                remote_addr: remote_addr.ok_or("--online needs --remote")?,
+                local_player,
+                input_delay,
+                seed,
+            }))),
+        }
+    }
+}
+
+/// The name given with `--name`, if there was one.
+pub fn player_name_from_args(args: impl Iterator<Item = String>) -> Option<String> {
+    let mut args = args.skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--name" {
+            return args.next().map(|name| clean_name(&name)).filter(|name| !name.is_empty());
+        }
+    }
+    None
+}
+
+/// Whoever is logged in, for when nobody told us a name.
+pub fn default_player_name() -> String {
+    let name = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).map(|name| clean_name(&name)).unwrap_or_default();
+    if name.is_empty() { "Player".to_string() } else { name }
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("could not understand {:?}", value))
+}
+
+// The only packet we send. Every packet carries all the local inputs the other peer hasn't
+// acknowledged yet, so a lost packet is simply covered by the next one.
+#[derive(Debug, Clone, PartialEq)]
+struct Packet {
+    /// How many of the receivers inputs the sender has.
+    ack: u32,
+    start_frame: u32,
+    inputs: Vec<PaddleInput>,
+    /// The latest checksum the sender worked out, with frame 0 meaning there isn't one yet.
+    checksum_frame: u32,
+    checksum: u64,
+}
+
+impl Packet {
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(23 + self.inputs.len());
+        bytes.extend_from_slice(&PACKET_MAGIC);
+        bytes.extend_from_slice(&self.ack.to_le_bytes());
+        bytes.extend_from_slice(&self.start_frame.to_le_bytes());
+        bytes.push(self.inputs.len() as u8);
+        bytes.extend(self.inputs.iter().map(|input| *input as u8));
+        bytes.extend_from_slice(&self.checksum_frame.to_le_bytes());
+        bytes.extend_from_slice(&self.checksum.to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Packet> {
+        if bytes.len() < 11 || bytes[0..2] != PACKET_MAGIC {
+            return None;
+        }
+        let count = bytes[10] as usize;
+        if bytes.len() != 11 + count + 12 {
+            return None;
+        }
+        let rest = &bytes[11 + count..];
+
+        Some(Packet {
+            ack: u32::from_le_bytes(bytes[2..6].try_into().ok()?),
+            start_frame: u32::from_le_bytes(bytes[6..10].try_into().ok()?),
+            inputs: bytes[11..11 + count].iter().map(|byte| *byte as PaddleInput).collect(),
+            checksum_frame: u32::from_le_bytes(rest[0..4].try_into().ok()?),
+            checksum: u64::from_le_bytes(rest[4..12].try_into().ok()?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionStatus {
+    Running,
+    /// We have guessed as far ahead as we are allowed to and are waiting on the other peer.
+    WaitingForRemote,
+    /// The peers worked out different checksums for the same frame.
+    Desynced { frame: u32 },
+}
+
+/// Runs the simulation for two peers over UDP, GGPO style. We never wait for the other peer's input
+/// unless we are too far ahead. Instead we guess they are still pressing whatever they pressed last,
+/// and if the real input turns out to be different we rewind to that frame and simulate forward again.
+pub struct RollbackSession {
+    socket: UdpSocket,
+    config: NetConfig,
+    state: SimulationState,
+    // The state right before each frame that could still get rolled back.
+    snapshots: BTreeMap<u32, SimulationState>,
+    // Inputs by frame. Both only ever grow at the end, the remote ones as they arrive in order.
+    local_inputs: Vec<PaddleInput>,
+    remote_inputs: Vec<PaddleInput>,
+    // The guesses we used for remote inputs that haven't arrived yet.
+    predicted_remote: BTreeMap<u32, PaddleInput>,
+    // How many of our inputs the other peer has told us it has.
+    remote_ack: u32,
+    next_checksum_frame: u32,
+    local_checksums: BTreeMap<u32, u64>,
+    remote_checksums: BTreeMap<u32, u64>,
+    last_checksum: (u32, u64),
+    desync_frame: Option<u32>,
+}
+
+impl RollbackSession {
+    pub fn new(config: NetConfig) -> io::Result<RollbackSession> {
+        let socket = UdpSocket::bind(config.local_addr)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(RollbackSession {
+            socket,
+            state: SimulationState::new(config.seed),
+            snapshots: BTreeMap::new(),
+            // The first few frames are covered by the input delay, nobody can press anything that early.
+            local_inputs: vec![0; config.input_delay as usize],
+            remote_inputs: Vec::new(),
+            predicted_remote: BTreeMap::new(),
+            remote_ack: 0,
+            next_checksum_frame: CHECKSUM_INTERVAL,
+            local_checksums: BTreeMap::new(),
+            remote_checksums: BTreeMap::new(),
+            last_checksum: (0, 0),
+            desync_frame: None,
+            config,
+        })
+    }
+
+    pub fn state(&self) -> &SimulationState {
+        &self.state
+    }
+
+    pub fn local_player(&self) -> usize {
+        self.config.local_player
+    }
+
+    pub fn status(&self) -> SessionStatus {
+        if let Some(frame) = self.desync_frame {
+            SessionStatus::Desynced { frame }
+        } else if self.is_stalled() {
+            SessionStatus::WaitingForRemote
+        } else {
+            SessionStatus::Running
+        }
+    }
+
+    /// Runs one tick with the local players input. Returns the events of the frame that got simulated,
+    /// or nothing if we had to wait for the other peer. Events from frames that were simulated again
+    /// during a rollback are not repeated.
+    pub fn advance(&mut self, local_input: PaddleInput) -> io::Result<Vec<SimulationEvent>> {
+        self.receive()?;
+
+        if self.is_stalled() {
+            self.send()?;
+            return Ok(Vec::new());
+        }
+
+        self.local_inputs.push(local_input);
+        self.send()?;
+
+        let frame = self.state.frame;
+        let events = self.simulate_frame(frame);
+
+        self.check_confirmed_frames();
+        Ok(events)
+    }
+
+    /// What the other peer is pressing as far as we know: their input for the next frame, or our guess at it.
+    pub fn remote_intent(&self) -> PaddleInput {
+        self.remote_input(self.state.frame)
+    }
+
+    fn is_stalled(&self) -> bool {
+        self.state.frame >= self.remote_inputs.len() as u32 + MAX_PREDICTION_FRAMES
+    }
+
+    fn confirmed_frames(&self) -> u32 {
+        (self.remote_inputs.len() as u32).min(self.state.frame)
+    }
+
+    fn remote_input(&self, frame: u32) -> PaddleInput {
+        match self.remote_inputs.get(frame as usize) {
+            Some(input) => *input,
+            // Guess they are still doing whatever they did last.
+            None => self.remote_inputs.last().copied().unwrap_or(0),
+        }
+    }
+
+    fn simulate_frame(&mut self, frame: u32) -> Vec<SimulationEvent> {
+        let local = self.local_inputs.get(frame as usize).copied().unwrap_or(0);
+        let remote = self.remote_input(frame);
+        if frame as usize >= self.remote_inputs.len() {
+            self.predicted_remote.insert(frame, remote);
+        }
+
+        let mut inputs = [0; 2];
+        inputs[self.config.local_player] = local;
+        inputs[1 - self.config.local_player] = remote;
+
+        self.snapshots.insert(frame, self.state.clone());
+        self.state.step(inputs)
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        let start = (self.remote_ack as usize).min(self.local_inputs.len());
+        let end = (start + MAX_INPUTS_PER_PACKET).min(self.local_inputs.len());
+
+        let packet = Packet {
+            ack: self.remote_inputs.len() as u32,
+            start_frame: start as u32,
+            inputs: self.local_inputs[start..end].to_vec(),
+            checksum_frame: self.last_checksum.0,
+            checksum: self.last_checksum.1,
+        };
+
+        match self.socket.send_to(&packet.encode(), self.config.remote_addr) {
+            Ok(_) => Ok(()),
+            // The other peer might not be up yet, which is fine, we'll just keep sending.
+            Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn receive(&mut self) -> io::Result<()> {
+        let confirmed_before = self.remote_inputs.len() as u32;
+        let mut buffer = [0u8; 512];
+
+        loop {
+            let (length, from) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => continue,
+                Err(error) => return Err(error),
+            };
+            if from != self.config.remote_addr {
+                continue;
+            }
+            let packet = match Packet::decode(&buffer[..length]) {
+                Some(packet) => packet,
+                None => continue,
+            };
+
+            self.remote_ack = self.remote_ack.max(packet.ack);
+
+            for (offset, input) in packet.inputs.iter().enumerate() {
+                if packet.start_frame as usize + offset == self.remote_inputs.len() {
+                    self.remote_inputs.push(*input);
+                }
+            }
+
+            if packet.checksum_frame != 0 {
+                self.remote_checksums.insert(packet.checksum_frame, packet.checksum);
+                self.compare_checksums(packet.checksum_frame);
+            }
+        }
+
+        // Find the first frame where our guess was wrong and simulate again from there.
+        let confirmed_after = (self.remote_inputs.len() as u32).min(self.state.frame);
+        let mispredicted = (confirmed_before..confirmed_after)
+            .find(|frame| self.predicted_remote.get(frame).copied() != Some(self.remote_inputs[*frame as usize]));
+
+        for frame in confirmed_before..self.remote_inputs.len() as u32 {
+            self.predicted_remote.remove(&frame);
+        }
+
+        if let Some(frame) = mispredicted {
+            self.rollback(frame);
+        }
+
+        Ok(())
+    }
+
+    fn rollback(&mut self, frame: u32) {
+        let current = self.state.frame;
+        self.state = match self.snapshots.get(&frame) {
+            Some(snapshot) => snapshot.clone(),
+            None => return,
+        };
+
+        for frame in frame..current {
+            self.simulate_frame(frame);
+        }
+    }
+
+    // Works out checksums for the frames both peers have confirmed inputs for, and forgets
+    // about snapshots that can't be rolled back to anymore.
+    fn check_confirmed_frames(&mut self) {
+        let confirmed = self.confirmed_frames();
+
+        while self.next_checksum_frame <= confirmed {
+            let frame = self.next_checksum_frame;
+            let checksum = if frame == self.state.frame {
+                Some(self.state.checksum())
+            } else {
+                self.snapshots.get(&frame).map(|snapshot| snapshot.checksum())
+            };
+
+            if let Some(checksum) = checksum {
+                self.local_checksums.insert(frame, checksum);
+                self.last_checksum = (frame, checksum);
+                self.compare_checksums(frame);
+            }
+            self.next_checksum_frame += CHECKSUM_INTERVAL;
+        }
+
+        // Keep the snapshot of the first unconfirmed frame, that is the furthest back a rollback can go.
+        self.snapshots = self.snapshots.split_off(&confirmed);
+        let oldest_checksum = confirmed.saturating_sub(CHECKSUM_INTERVAL * 4);
+        self.local_checksums = self.local_checksums.split_off(&oldest_checksum);
+        self.remote_checksums = self.remote_checksums.split_off(&oldest_checksum);
+    }
+
+    fn compare_checksums(&mut self, frame: u32) {
+        if let (Some(local), Some(remote)) = (self.local_checksums.get(&frame), self.remote_checksums.get(&frame)) {
+            if local != remote && self.desync_frame.is_none() {
+                self.desync_frame = Some(frame);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    fn packet() -> Packet {
+        Packet {
+            ack: 41,
+            start_frame: 40,
+            inputs: vec![1, 0, -1, -1, 1],
+            checksum_frame: 30,
+            checksum: 0xDEAD_BEEF_0123_4567,
+        }
+    }
+
+    #[test]
+    fn packets_survive_the_trip() {
+        let packet = packet();
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+
+        let empty = Packet { inputs: Vec::new(), ..self::packet() };
+        assert_eq!(Packet::decode(&empty.encode()), Some(empty));
+    }
+
+    #[test]
+    fn broken_packets_are_ignored() {
+        let bytes = packet().encode();
+        assert_eq!(Packet::decode(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(Packet::decode(&[bytes.as_slice(), &[0]].concat()), None);
+        assert_eq!(Packet::decode(&[&b"XX"[..], &bytes[2..]].concat()), None);
+        assert_eq!(Packet::decode(&[]), None);
+    }
+
+    // An address on localhost nobody is using right now.
+    fn free_address() -> SocketAddr {
+        UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    fn input(frame: u32, player: usize) -> PaddleInput {
+        ((frame / (11 + 5 * player as u32)) % 3) as PaddleInput - 1
+    }
+
+    #[test]
+    fn two_sessions_over_localhost_agree() {
+        const FRAMES: u32 = 300;
+        let addresses = [free_address(), free_address()];
+        let mut sessions: Vec<RollbackSession> = (0..2)
+            .map(|player| {
+                RollbackSession::new(NetConfig {
+                    local_addr: addresses[player],
+                    remote_addr: addresses[1 - player],
+                    local_player: player,
+                    input_delay: 2,
+                    seed: 5,
+                })
+                .unwrap()
+            })
+            .collect();
+
+        let mut ticks = 0;
+        while sessions.iter().any(|session| session.state().frame < FRAMES) {
+            for (player, session) in sessions.iter_mut().enumerate() {
+                if session.state().frame < FRAMES {
+                    session.advance(input(ticks, player)).unwrap();
+                }
+                assert!(!matches!(session.status(), SessionStatus::Desynced { .. }));
+            }
+            ticks += 1;
+            assert!(ticks < FRAMES * 10, "the sessions stopped making progress");
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // Let the last inputs arrive so neither side is still guessing.
+        for _ in 0..1000 {
+            if sessions.iter().all(|session| session.remote_inputs.len() as u32 >= FRAMES) {
+                break;
+            }
+            for session in sessions.iter_mut() {
+                session.receive().unwrap();
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(sessions.iter().all(|session| session.remote_inputs.len() as u32 >= FRAMES));
+
+        // Both ended up where a single simulation fed the same inputs ends up.
+        let mut expected = SimulationState::new(5);
+        for frame in 0..FRAMES as usize {
+            expected.step([sessions[0].local_inputs[frame], sessions[1].local_inputs[frame]]);
+        }
+        assert_eq!(sessions[0].state(), &expected);
+        assert_eq!(sessions[1].state(), &expected);
+        // And they did compare checksums along the way.
+        assert!(sessions.iter().all(|session| session.last_checksum.0 > 0));
+    }
+}
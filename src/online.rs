@@ -0,0 +1,345 @@
+use bevy::prelude::*;
+
+use std::time::Instant;
+
+use pong50::{client::ServerConnection, net::{RollbackSession, SessionStatus}, protocol::{MatchPhase, Snapshot}, simulation::{PaddleInput, SimulationEvent, ARENA_HEIGHT, ARENA_WIDTH, LOCAL_ONLY_RULES, PADDLE_HEIGHT, PADDLE_SPEED, PADDLE_WIDTH, TICK_SECONDS}};
+
+use crate::{Player, LoadedAudio, ball::{Ball, BallMaterial, spawn_ball}, controller::{ControllerView, NetworkController, NetworkIntent, PaddleControl, PaddleView}, game_state::AppState, paddle::Paddle, score::Scoreboard, spectator::{SpectatorInfo, SpectatorView}};
+
+// Never run more than this many ticks in one frame, so a long hitch doesn't snowball.
+const MAX_TICKS_PER_UPDATE: u32 = 4;
+
+pub struct OnlinePlugin;
+
+/// Only exists when the game was started with `--online`.
+pub struct OnlineSession {
+    pub session: RollbackSession,
+    accumulator: f32,
+}
+
+impl OnlineSession {
+    pub fn new(session: RollbackSession) -> Self {
+        OnlineSession {
+            session,
+            accumulator: 0.0,
+        }
+    }
+}
+
+/// Only exists while we are connected to a server, from `--connect` or the LAN lobby.
+pub struct ServerSession(pub ServerConnection);
+
+// Sets what the NetworkController on the other peer's paddle does.
+struct RemoteIntent(NetworkIntent);
+
+// What the last snapshot we drew looked like, so we can play sounds when it changes.
+#[derive(Default)]
+struct LastSnapshot(Option<Snapshot>);
+
+struct NetStatusText;
+
+impl Plugin for OnlinePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<LastSnapshot>()
+            .add_startup_system(setup.system())
+            .add_system_set(SystemSet::on_update(AppState::Start).with_system(start_online_match.system()))
+            .add_system_set(SystemSet::on_enter(AppState::Online).with_system(enter_online_state.system()))
+            .add_system_set(SystemSet::on_update(AppState::Online).with_system(online_update.system())
+                .with_system(server_update.system()));
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    asset_server: Res<AssetServer>
+) {
+    let window = windows.get_primary().unwrap();
+
+    commands
+    .spawn_bundle(TextBundle {
+        style: Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(35.0),
+                left: Val::Px(window.width() / 2.0 - 120.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::with_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/font.ttf"),
+                font_size: 18.0,
+                color: Color::YELLOW,
+            },
+            TextAlignment {
+                horizontal: HorizontalAlign::Center,
+                ..Default::default()
+            },
+        ),
+        ..Default::default()
+    })
+    .insert(NetStatusText);
+}
+
+// There is no menu for online matches. A rollback session goes straight in, a server sends us to
+// its lobby first.
+fn start_online_match(
+    session: Option<Res<OnlineSession>>,
+    server: Option<Res<ServerSession>>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if session.is_some() {
+        app_state.set(AppState::Online).unwrap();
+    } else if server.is_some() {
+        app_state.set(AppState::Lobby).unwrap();
+    }
+}
+
+// The simulation owns the ball now, so we only need a single one to show where it is. With a rollback
+// session the other peer's paddle is theirs to drive, through a NetworkController.
+fn enter_online_state(
+    mut commands: Commands,
+    query: Query<Entity, With<Ball>>,
+    paddles: Query<(Entity, &Paddle)>,
+    online: Option<Res<OnlineSession>>,
+    ball_material: Res<BallMaterial>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+    spawn_ball(&mut commands, &ball_material, Vec2::new(0.0, 0.0), Vec2::new(0.0, 0.0), 0.0);
+
+    if let Some(online) = online {
+        let remote = Player::ALL[1 - online.session.local_player()];
+        for (entity, _) in paddles.iter().filter(|(_, paddle)| paddle.player == remote) {
+            let (controller, intent) = NetworkController::new();
+            commands.entity(entity).insert(PaddleControl(Box::new(controller)));
+            commands.insert_resource(RemoteIntent(intent));
+        }
+    }
+}
+
+// Either set of keys works, you are the only one on this keyboard.
+fn local_input(keys: &Input<KeyCode>) -> PaddleInput {
+    if keys.pressed(KeyCode::W) || keys.pressed(KeyCode::Up) {
+        1
+    } else if keys.pressed(KeyCode::S) || keys.pressed(KeyCode::Down) {
+        -1
+    } else {
+        0
+    }
+}
+
+// Steps the rollback session at a fixed rate and then copies its state onto our entities.
+fn online_update(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    online: Option<ResMut<OnlineSession>>,
+    remote: Option<Res<RemoteIntent>>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut q: QuerySet<(
+        Query<(&Paddle, &mut Transform, Option<&mut PaddleControl>)>,
+        Query<&mut Transform, With<Ball>>,
+    )>,
+    mut text_query: Query<&mut Text, With<NetStatusText>>,
+    audio: Res<Audio>,
+    loaded_audio: Res<LoadedAudio>,
+) {
+    let mut online = match online {
+        Some(online) => online,
+        None => return,
+    };
+    let input = local_input(&keys);
+
+    online.accumulator = (online.accumulator + time.delta_seconds()).min(TICK_SECONDS * MAX_TICKS_PER_UPDATE as f32);
+    while online.accumulator >= TICK_SECONDS {
+        online.accumulator -= TICK_SECONDS;
+
+        let events = match online.session.advance(input) {
+            Ok(events) => events,
+            Err(error) => {
+                error!("Online session failed: {}", error);
+                break;
+            }
+        };
+
+        for event in events {
+            match event {
+                SimulationEvent::PaddleHit => audio.play(loaded_audio.paddle_hit.clone()),
+                SimulationEvent::WallHit => audio.play(loaded_audio.wall_hit.clone()),
+                SimulationEvent::Scored(_) => audio.play(loaded_audio.score.clone()),
+                SimulationEvent::MatchOver(_) => (),
+            }
+        }
+    }
+
+    if let Some(remote) = &remote {
+        remote.0.set(online.session.remote_intent() as f32);
+    }
+
+    let state = online.session.state();
+    let snapshot = Snapshot::from_state(state.frame, state.phase.into(), state);
+    show_snapshot(&snapshot, &mut q, &mut scoreboard);
+
+    // The simulation only moves on whole ticks, so each paddle is drawn as far ahead as it will have
+    // got by the end of the time left over, going by what its player is pressing. That keeps the
+    // paddles from stuttering when frames come quicker than ticks.
+    if online.session.status() == SessionStatus::Running {
+        let local = Player::ALL[online.session.local_player()];
+        let remote = Player::ALL[1 - online.session.local_player()];
+        let max_height = ARENA_HEIGHT / 2.0 - PADDLE_HEIGHT / 2.0;
+        for (paddle, mut transform, control) in q.q0_mut().iter_mut() {
+            let intent = match control {
+                _ if paddle.player == local => input as f32,
+                Some(mut control) if paddle.player == remote => control.0.intent(&ControllerView {
+                    paddle: PaddleView { player: paddle.player, position: transform.translation.truncate(), size: Vec2::new(PADDLE_WIDTH, PADDLE_HEIGHT) },
+                    paddles: &[],
+                    balls: &[],
+                    arena: Vec2::new(ARENA_WIDTH, ARENA_HEIGHT),
+                    scoreboard: &scoreboard,
+                    rally: snapshot.rally,
+                    keys: &keys,
+                    gamepad_buttons: &gamepad_buttons,
+                    gamepad_axes: &gamepad_axes,
+                    delta_seconds: time.delta_seconds(),
+                }),
+                _ => continue,
+            };
+            let y = transform.translation.y + intent.clamp(-1.0, 1.0) * PADDLE_SPEED * online.accumulator;
+            transform.translation.y = y.clamp(-max_height, max_height);
+        }
+    }
+
+    if let Ok(mut text) = text_query.single_mut() {
+        let you = player_name(online.session.local_player());
+        text.sections[0].value = match online.session.status() {
+            SessionStatus::Desynced { frame } => format!("Out of sync at frame {}!", frame),
+            SessionStatus::WaitingForRemote => format!("You are {}, waiting for opponent\n{}", you, LOCAL_ONLY_RULES),
+            SessionStatus::Running => match_status(&snapshot, you, [player_name(0), player_name(1)]),
+        };
+    }
+}
+
+// Sends our input to the dedicated server and draws the match a little in the past, blended
+// between the snapshots it sent us. Anyone without a side just watches.
+fn server_update(
+    keys: Res<Input<KeyCode>>,
+    server: Option<ResMut<ServerSession>>,
+    mut last_snapshot: ResMut<LastSnapshot>,
+    mut spectator: ResMut<SpectatorView>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut q: QuerySet<(
+        Query<(&Paddle, &mut Transform, Option<&mut PaddleControl>)>,
+        Query<&mut Transform, With<Ball>>,
+    )>,
+    mut text_query: Query<&mut Text, With<NetStatusText>>,
+    audio: Res<Audio>,
+    loaded_audio: Res<LoadedAudio>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    let mut server = match server {
+        Some(server) => server,
+        None => return,
+    };
+    let connection = &mut server.0;
+
+    let input = if connection.player().is_some() { local_input(&keys) } else { 0 };
+    if let Err(error) = connection.send_input(input).and_then(|_| connection.poll()) {
+        error!("Lost the server: {}", error);
+    }
+
+    // Once the match is over, or the server is gone, the lobby takes it from here.
+    if !connection.in_match() || !connection.is_connected() {
+        last_snapshot.0 = None;
+        spectator.0 = None;
+        if let Ok(mut text) = text_query.single_mut() {
+            text.sections[0].value.clear();
+        }
+        app_state.set(AppState::Lobby).unwrap();
+        return;
+    }
+
+    let lobby = connection.lobby();
+    let names = [lobby.names[0].as_str(), lobby.names[1].as_str()];
+    let you = connection.player().map_or("a spectator", |seat| names[seat]);
+    let status = if let Some(snapshot) = connection.interpolated(Instant::now()) {
+        // The server doesn't tell us about hits, but we can see them in the rally and the ball changing direction.
+        if let Some(last) = &last_snapshot.0 {
+            if snapshot.scores != last.scores {
+                audio.play(loaded_audio.score.clone());
+            } else if snapshot.rally > last.rally {
+                audio.play(loaded_audio.paddle_hit.clone());
+            } else if snapshot.phase == MatchPhase::Play && last.phase == MatchPhase::Play && snapshot.ball_velocity[1] != last.ball_velocity[1] {
+                audio.play(loaded_audio.wall_hit.clone());
+            }
+        }
+
+        spectator.0 = if connection.player().is_none() {
+            Some(SpectatorInfo {
+                players: vec![
+                    (names[0].to_string(), snapshot.scores[0]),
+                    (names[1].to_string(), snapshot.scores[1]),
+                ],
+                rally: snapshot.rally,
+                ball_speed: Vec2::new(snapshot.ball_velocity[0], snapshot.ball_velocity[1]).length(),
+            })
+        } else {
+            None
+        };
+
+        show_snapshot(&snapshot, &mut q, &mut scoreboard);
+        let status = match_status(&snapshot, you, names);
+        last_snapshot.0 = Some(snapshot);
+        status
+    } else {
+        format!("You are {}, waiting for the server", you)
+    };
+
+    if let Ok(mut text) = text_query.single_mut() {
+        text.sections[0].value = status;
+    }
+}
+
+fn show_snapshot(
+    snapshot: &Snapshot,
+    q: &mut QuerySet<(
+        Query<(&Paddle, &mut Transform, Option<&mut PaddleControl>)>,
+        Query<&mut Transform, With<Ball>>,
+    )>,
+    scoreboard: &mut Scoreboard,
+) {
+    for (paddle, mut transform, _) in q.q0_mut().iter_mut() {
+        match paddle.player {
+            Player::Player1 => transform.translation.y = snapshot.paddles[0],
+            Player::Player2 => transform.translation.y = snapshot.paddles[1],
+            _ => (),
+        }
+    }
+
+    for mut transform in q.q1_mut().iter_mut() {
+        transform.translation.x = snapshot.ball_position[0];
+        transform.translation.y = snapshot.ball_position[1];
+    }
+
+    scoreboard.player1 = snapshot.scores[0];
+    scoreboard.player2 = snapshot.scores[1];
+}
+
+fn match_status(snapshot: &Snapshot, you: &str, names: [&str; 2]) -> String {
+    match (snapshot.phase, snapshot.winner) {
+        (MatchPhase::Waiting, _) => format!("You are {}, waiting for opponent", you),
+        (MatchPhase::Done, Some(winner)) => format!("{} Wins!", names[winner]),
+        _ => format!("You are {}", you),
+    }
+}
+
+fn player_name(player: usize) -> &'static str {
+    if player == 0 { "Player 1" } else { "Player 2" }
+}
@@ -1,26 +1,64 @@
 use bevy::prelude::*;
 
-use crate::{Player, ball::{Ball, BALL_SCALE}};
+use pong50::simulation::{PADDLE_HEIGHT, PADDLE_SPEED, PADDLE_WIDTH};
 
-const PADDLE_SPEED: f32 = 500.0;
-pub const PADDLE_SCALE_X: f32 = 20.0;
-pub const PADDLE_SCALE_Y: f32 = 100.0;
+use crate::{Player, ball::{Ball, Rally}, controller::{BallView, Bots, ControllerView, PaddleControl, PaddleView, Recording}, game_state::AppState, profile::Profiles, rules::{Controller, GameRules, Movement}, score::Scoreboard, scripts::Scripts};
+
+// With momentum, how quickly a paddle gets up to its top speed and how quickly it slows down once let go.
+// Both are per second, as a share of the top speed and of the current speed.
+const PADDLE_ACCELERATION: f32 = 6.0;
+const PADDLE_FRICTION: f32 = 8.0;
+// A dash goes this many times the paddle's top speed for a moment, and then has to recharge.
+const DASH_SPEED: f32 = 3.0;
+const DASH_SECONDS: f32 = 0.1;
+const DASH_COOLDOWN: f32 = 1.0;
 
 
 pub struct PaddlePlugin;
 
 pub struct Paddle {
     pub player: Player,
+    /// How fast the paddle moved along its axis last frame, in pixels per second. Up and right are positive.
+    pub velocity: f32,
+}
+
+/// Size and speed for a single paddle. Every paddle gets its own copy so things like handicaps
+/// can change one paddle without touching the other. The sprite size is kept in sync with `size`.
+pub struct PaddleStats {
+    pub size: Vec2,
+    pub speed: f32,
+}
+
+impl Default for PaddleStats {
+    fn default() -> Self {
+        PaddleStats {
+            size: Vec2::new(PADDLE_WIDTH, PADDLE_HEIGHT),
+            speed: PADDLE_SPEED,
+        }
+    }
 }
 
-struct PaddleAI;
+/// A short burst of speed along the paddle's axis, for reaching a ball that would otherwise get past.
+#[derive(Default)]
+pub struct PaddleDash {
+    /// Seconds left of the dash in progress.
+    pub remaining: f32,
+    /// Seconds until the paddle can dash again.
+    pub cooldown: f32,
+    /// Which way the dash is going, 1.0 or -1.0.
+    pub direction: f32,
+}
+
+pub struct PaddleMaterial(pub Handle<ColorMaterial>);
 
 impl Plugin for PaddlePlugin {
     fn build(&self, app: &mut AppBuilder) {
         app
+            .init_resource::<Recording>()
             .add_startup_system(setup.system())
+            .add_system(apply_paddle_stats.system())
             .add_system(paddle_movement.system())
-            .add_system(paddle_ai_movement.system());
+            .add_system_set(SystemSet::on_exit(AppState::Start).with_system(prepare_paddles.system()));
     }
 }
 
@@ -28,123 +66,271 @@ impl Plugin for PaddlePlugin {
 fn setup(
     mut commands: Commands,
     mut materials: ResMut<Assets<ColorMaterial>>,
-    mut windows: ResMut<Windows>
+    mut windows: ResMut<Windows>,
+    rules: Res<GameRules>,
+    profiles: Res<Profiles>,
+    bots: Res<Bots>,
+    scripts: Res<Scripts>,
+    recording: Res<Recording>,
 ) {
 
     // Get the window
     let window = windows.get_primary_mut().unwrap();
+    let material = PaddleMaterial(materials.add(Color::rgb(1.0, 1.0, 1.0).into()));
 
     // Left Paddle
-    commands.spawn_bundle(SpriteBundle {
-        material: materials.add(Color::rgb(1.0, 1.0, 1.0).into()),
-        transform: Transform::from_xyz(0.0 - window.width() / 2.0 + 20.0, 0.0, 10.0),
-        sprite: Sprite::new(Vec2::new(PADDLE_SCALE_X, PADDLE_SCALE_Y)),
-        ..Default::default()
-    })
-    .insert(Paddle { player: Player::Player1 });
+    let control = match_control(Player::Player1, &rules, &profiles, &bots, &scripts, &recording);
+    spawn_paddle(&mut commands, &material, window, Player::Player1, PaddleStats::default(), control);
 
     // Right Paddle
+    let control = match_control(Player::Player2, &rules, &profiles, &bots, &scripts, &recording);
+    spawn_paddle(&mut commands, &material, window, Player::Player2, PaddleStats::default(), control);
+
+    commands.insert_resource(material);
+}
+
+// Puts a paddle on the edge of the screen the player is defending.
+fn spawn_paddle(
+    commands: &mut Commands,
+    material: &PaddleMaterial,
+    window: &Window,
+    player: Player,
+    stats: PaddleStats,
+    control: PaddleControl,
+) {
+    let translation = match player {
+        Player::Player1 => Vec2::new(0.0 - window.width() / 2.0 + 20.0, 0.0),
+        Player::Player2 => Vec2::new(0.0 + window.width() / 2.0 - 20.0, 0.0),
+        Player::Player3 => Vec2::new(0.0, 0.0 + window.height() / 2.0 - 20.0),
+        Player::Player4 => Vec2::new(0.0, 0.0 - window.height() / 2.0 + 20.0),
+    };
+
     commands.spawn_bundle(SpriteBundle {
-        material: materials.add(Color::rgb(1.0, 1.0, 1.0).into()),
-        transform: Transform::from_xyz(0.0 + window.width() / 2.0 - 20.0, 0.0, 10.0),
-        sprite: Sprite::new(Vec2::new(PADDLE_SCALE_X, PADDLE_SCALE_Y)),
+        material: material.0.clone(),
+        transform: Transform::from_xyz(translation.x, translation.y, 10.0),
+        sprite: Sprite::new(stats.size),
         ..Default::default()
     })
-    .insert(Paddle { player: Player::Player2 })
-    .insert(PaddleAI);
+    .insert(Paddle { player, velocity: 0.0 })
+    .insert(PaddleDash::default())
+    .insert(stats)
+    .insert(control);
 }
 
-fn paddle_movement(
-    input: Res<Input<KeyCode>>,
-    time: Res<Time>,
+// The stats a player starts the match with: the defaults turned to face the right way,
+// with their handicap on top.
+fn match_stats(player: Player, rules: &GameRules) -> PaddleStats {
+    let handicap = rules.handicap(player);
+    let defaults = PaddleStats::default();
+
+    let mut speed = defaults.speed * handicap.paddle_speed;
+    if rules.controller(player).is_ai() {
+        speed *= 1.0 - handicap.ai_slowdown;
+    }
+
+    let length = defaults.size.y * handicap.paddle_size;
+    let size = if player.is_horizontal() {
+        Vec2::new(length, defaults.size.x)
+    } else {
+        Vec2::new(defaults.size.x, length)
+    };
+
+    PaddleStats { size, speed }
+}
+
+// The controller picked in the menu for this player. Gamepads go to players in order, so the first
+// player on a gamepad gets the first one.
+fn match_control(player: Player, rules: &GameRules, profiles: &Profiles, bots: &Bots, scripts: &Scripts, recording: &Recording) -> PaddleControl {
+    let gamepad = rules
+        .mode
+        .players()
+        .iter()
+        .take_while(|other| **other != player)
+        .filter(|other| rules.controller(**other) == Controller::Gamepad)
+        .count();
+    PaddleControl::builtin(rules.controller(player), player, profiles.active().controls, gamepad, bots, scripts.active(rules), recording)
+}
+
+// Resizes the paddle sprite whenever its stats change, so what you see is what the ball collides with.
+fn apply_paddle_stats(mut query: Query<(&PaddleStats, &mut Sprite), Changed<PaddleStats>>) {
+    for (stats, mut sprite) in query.iter_mut() {
+        sprite.size = stats.size;
+    }
+}
+
+// Rules can only change on the Start screen, so once we leave it we make sure exactly the players
+// in this mode have a paddle, that each one has the controller picked for it, and that every paddle
+// has its stats rebuilt from the defaults with that players handicap on top. Replays are handed
+// the last match before the recording of this one starts.
+fn prepare_paddles(
+    mut commands: Commands,
+    rules: Res<GameRules>,
+    profiles: Res<Profiles>,
+    bots: Res<Bots>,
+    scripts: Res<Scripts>,
+    mut recording: ResMut<Recording>,
+    material: Res<PaddleMaterial>,
     windows: Res<Windows>,
-    mut query: Query<(&Paddle, &mut Transform)>
+    query: Query<(Entity, &Paddle)>,
 ) {
     let window = windows.get_primary().unwrap();
+    let players = rules.mode.players();
+    let mut has_paddle = [false; 4];
 
-    for (paddle, mut transform) in query.iter_mut() {
-
-        // Get Paddles movement direction based on key pressed.
-        let mut direction: f32 = 0.0;
-
-        match paddle.player {
-            Player::Player1 => {
-                if input.pressed(KeyCode::W) {
-                    direction = 1.0
-                } else if input.pressed(KeyCode::S) {
-                    direction = -1.0
-                }
-            }
-            Player::Player2 => ()
-            // Commented out so I can add AI for Player2
-            //{
-            //    if input.pressed(KeyCode::Up) {
-            //        direction = 1.0
-            //    } else if input.pressed(KeyCode::Down) {
-            //        direction = -1.0
-            //    }
-            //}
+    for (entity, paddle) in query.iter() {
+        if !players.contains(&paddle.player) {
+            commands.entity(entity).despawn();
+            continue;
         }
-        
-        // Calculate paddle movement
-        let mut y_translation = transform.translation.y + direction * PADDLE_SPEED * time.delta_seconds();
-
-        // Clamp our Paddles within the top and bottom of the screen
-        let max_height = window.height() / 2.0 - PADDLE_SCALE_Y / 2.0;
-        let min_height = -window.height() / 2.0 + PADDLE_SCALE_Y / 2.0;
-
-        if y_translation > max_height {
-            y_translation = max_height;
-        } else if y_translation < min_height {
-            y_translation = min_height;
+        has_paddle[paddle.player.index()] = true;
+
+        commands
+            .entity(entity)
+            .insert(match_stats(paddle.player, &rules))
+            .insert(match_control(paddle.player, &rules, &profiles, &bots, &scripts, &recording));
+    }
+
+    for player in players.iter() {
+        if !has_paddle[player.index()] {
+            let control = match_control(*player, &rules, &profiles, &bots, &scripts, &recording);
+            spawn_paddle(&mut commands, &material, window, *player, match_stats(*player, &rules), control);
         }
+    }
+    recording.start_match();
+}
 
-        // Apply our paddles movement
-        transform.translation.y = y_translation;
+// How fast the paddle should be going this frame. Direct movement goes wherever the controller says
+// at full speed, with momentum the controller only pushes the paddle and it takes a moment to get going
+// or to stop. A dash overrides both until it runs out.
+fn paddle_speed(
+    paddle: &Paddle,
+    stats: &PaddleStats,
+    dash: &mut PaddleDash,
+    direction: f32,
+    wants_dash: bool,
+    movement: Movement,
+    delta_seconds: f32,
+) -> f32 {
+    dash.cooldown = (dash.cooldown - delta_seconds).max(0.0);
+    dash.remaining = (dash.remaining - delta_seconds).max(0.0);
 
+    // A dash goes the way the paddle is being pushed, or the way it is already moving.
+    let heading = if direction != 0.0 { direction } else { paddle.velocity };
+    if wants_dash && dash.cooldown == 0.0 && heading != 0.0 {
+        dash.remaining = DASH_SECONDS;
+        dash.cooldown = DASH_COOLDOWN;
+        dash.direction = heading.signum();
+    }
+    if dash.remaining > 0.0 {
+        return dash.direction * stats.speed * DASH_SPEED;
+    }
+
+    match movement {
+        Movement::Direct => direction * stats.speed,
+        Movement::Momentum if direction != 0.0 => {
+            let speed = paddle.velocity + direction * stats.speed * PADDLE_ACCELERATION * delta_seconds;
+            speed.clamp(-stats.speed, stats.speed)
+        }
+        Movement::Momentum => paddle.velocity * (1.0 - PADDLE_FRICTION * delta_seconds).max(0.0),
     }
 }
 
-// I need an immutable reference for the balls Transform as well as a mutable reference
-// to the paddle Transform. Going to have to do that QuerySet thing again.
-fn paddle_ai_movement(
+// Moves a paddle along its axis, up and down for the side paddles and left and right for the 
+// top and bottom ones, and keeps it on the screen. The paddle remembers how fast it actually
+// moved, so one pressed against the edge of the screen isn't moving at all.
+fn move_paddle(
+    transform: &mut Transform,
+    paddle: &mut Paddle,
+    stats: &PaddleStats,
+    speed: f32,
+    delta_seconds: f32,
+    window: &Window,
+) {
+    let (position, length, extent) = if paddle.player.is_horizontal() {
+        (&mut transform.translation.x, stats.size.x, window.width())
+    } else {
+        (&mut transform.translation.y, stats.size.y, window.height())
+    };
+
+    // Calculate paddle movement
+    let mut translation = *position + speed * delta_seconds;
+
+    // Clamp our Paddles within the edges of the screen
+    let max = extent / 2.0 - length / 2.0;
+    let min = -extent / 2.0 + length / 2.0;
+
+    if translation > max {
+        translation = max;
+    } else if translation < min {
+        translation = min;
+    }
+
+    // Apply our paddles movement
+    if delta_seconds > 0.0 {
+        paddle.velocity = (translation - *position) / delta_seconds;
+    }
+    *position = translation;
+}
+
+// Every paddle asks its controller what to do, with a read only view of the balls, the other
+// paddles and the score. I need the Transforms of everything for that view and a mutable one for
+// the paddle being moved, so it is a QuerySet again.
+fn paddle_movement(
     mut query: QuerySet<(
-        Query<&Transform, With<Ball>>,
-        Query<&mut Transform, With<PaddleAI>>
+        Query<(&Transform, &Ball)>,
+        Query<(&Paddle, &PaddleStats, &Transform)>,
+        Query<(&mut Paddle, &PaddleStats, &mut PaddleDash, &mut PaddleControl, &mut Transform)>,
     )>,
+    keys: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    scoreboard: Res<Scoreboard>,
+    rally: Res<Rally>,
     time: Res<Time>,
     windows: Res<Windows>,
+    rules: Res<GameRules>,
+    app_state: Res<State<AppState>>,
+    mut recording: ResMut<Recording>,
 ) {
-    let mut ball_y = 0.0;
-    if let Ok(ball_transform) = query.q0().single() {
-        ball_y = ball_transform.translation.y;
+    // Online matches are run by the rollback session or the server instead.
+    if app_state.current().is_networked() {
+        return;
     }
 
-    if let Ok(mut paddle_transform) = query.q1_mut().single_mut() {
-        let paddle_y = paddle_transform.translation.y;
-
-        let mut direction: f32 = 0.0;
-        if paddle_y + PADDLE_SCALE_Y / 4.0 < ball_y {
-            direction = 1.0;
-        } else if paddle_y - PADDLE_SCALE_Y / 4.0 > ball_y {
-            direction = -1.0;
-        }
-
-        let window = windows.get_primary().unwrap();
+    let window = windows.get_primary().unwrap();
 
-        // Calculate paddle movement
-        let mut y_translation = paddle_transform.translation.y + direction * PADDLE_SPEED * time.delta_seconds();
-        // Clamp our Paddles within the top and bottom of the screen
-        let max_height = window.height() / 2.0 - PADDLE_SCALE_Y / 2.0;
-        let min_height = -window.height() / 2.0 + PADDLE_SCALE_Y / 2.0;
+    let balls: Vec<BallView> = query
+        .q0()
+        .iter()
+        .map(|(transform, ball)| BallView { position: transform.translation.truncate(), velocity: ball.velocity, last_hit: ball.last_hit })
+        .collect();
+    let paddles: Vec<PaddleView> = query
+        .q1()
+        .iter()
+        .map(|(paddle, stats, transform)| PaddleView { player: paddle.player, position: transform.translation.truncate(), size: stats.size })
+        .collect();
 
-        if y_translation > max_height {
-            y_translation = max_height;
-        } else if y_translation < min_height {
-            y_translation = min_height;
+    for (mut paddle, stats, mut dash, mut control, mut transform) in query.q2_mut().iter_mut() {
+        let view = ControllerView {
+            paddle: PaddleView { player: paddle.player, position: transform.translation.truncate(), size: stats.size },
+            paddles: &paddles,
+            balls: &balls,
+            arena: Vec2::new(window.width(), window.height()),
+            scoreboard: &scoreboard,
+            rally: rally.0,
+            keys: &keys,
+            gamepad_buttons: &gamepad_buttons,
+            gamepad_axes: &gamepad_axes,
+            delta_seconds: time.delta_seconds(),
+        };
+        let direction = control.0.intent(&view).clamp(-1.0, 1.0);
+        // Only the match itself is recorded, that is where a replay starts playing.
+        if matches!(app_state.current(), AppState::Serve | AppState::Play) {
+            recording.record(paddle.player, direction);
         }
+        let wants_dash = control.0.dash(&view);
 
-        // Apply our paddles movement
-        paddle_transform.translation.y = y_translation;
+        let speed = paddle_speed(&paddle, stats, &mut dash, direction, wants_dash, rules.movement, time.delta_seconds());
+        move_paddle(&mut transform, &mut paddle, stats, speed, time.delta_seconds(), window);
     }
 }
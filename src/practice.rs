@@ -0,0 +1,265 @@
+use bevy::prelude::*;
+use rand::{thread_rng, Rng};
+
+use crate::{ball::Ball, game_state::AppState, rules::{BallMachine, GameMode, GameRules, SpinPattern}, score::{PointScored, Scoreboard}, LoadedAudio, Player};
+
+/// How many balls the machine serves in one practice session.
+pub const PRACTICE_BALLS: u32 = 20;
+// Seconds between one ball being decided and the machine serving the next.
+const MACHINE_INTERVAL: f32 = 1.0;
+// The machine stands just inside the right edge, and moves up and down this far from the middle between serves.
+const MACHINE_INSET: f32 = 40.0;
+const MACHINE_Y_RANGE: f32 = 150.0;
+// In radians per second, see Ball::spin.
+const MACHINE_SPIN: f32 = 6.0;
+// The targets are the top and bottom corners of the right edge. A return through one of them is
+// worth the bonus on top of the point for getting it back at all.
+const TARGET_HEIGHT: f32 = 140.0;
+const TARGET_WIDTH: f32 = 10.0;
+const TARGET_BONUS: u32 = 2;
+
+pub struct PracticePlugin;
+
+/// How the practice session in progress is going.
+#[derive(Default)]
+pub struct Practice {
+    pub returns: u32,
+    /// Returns that went through a target.
+    pub targets: u32,
+    pub misses: u32,
+}
+
+impl Practice {
+    /// Balls that have been returned or missed so far.
+    pub fn played(&self) -> u32 {
+        self.returns + self.misses
+    }
+}
+
+// Time since the ball machine was last ready to serve.
+struct MachineTimer(f32);
+
+struct TargetMaterial(Handle<ColorMaterial>);
+
+struct TargetZone;
+
+struct PracticeText;
+
+impl Plugin for PracticePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<Practice>()
+            .insert_resource(MachineTimer(0.0))
+            .add_startup_system(setup.system())
+            .add_system(update_practice_text.system())
+            .add_system_set(SystemSet::on_enter(AppState::Start).with_system(remove_targets.system()))
+            .add_system_set(SystemSet::on_exit(AppState::Start).with_system(start_session.system()))
+            .add_system_set(SystemSet::on_enter(AppState::Serve).with_system(reset_machine.system()))
+            .add_system_set(SystemSet::on_update(AppState::Serve).with_system(machine_feed.system()))
+            .add_system_set(SystemSet::on_update(AppState::Play).with_system(score_practice.system()));
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    windows: Res<Windows>,
+) {
+    let window = windows.get_primary().unwrap();
+
+    commands.insert_resource(TargetMaterial(materials.add(Color::rgba(0.3, 0.9, 0.3, 0.5).into())));
+
+    commands
+    .spawn_bundle(TextBundle {
+        style: Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(40.0),
+                left: Val::Px(window.width() / 2.0 - 180.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::default(),
+        ..Default::default()
+    })
+    .insert(PracticeText);
+}
+
+/// Where the ball machine serves the ball'th ball of a session from, and its velocity and spin.
+/// The machine is on the right edge of an arena this wide, so every serve heads left.
+pub fn machine_serve(machine: &BallMachine, ball: u32, arena_width: f32) -> (Vec2, Vec2, f32) {
+    let mut rng = thread_rng();
+    let position = Vec2::new(
+        arena_width / 2.0 - MACHINE_INSET,
+        rng.gen_range(-MACHINE_Y_RANGE..=MACHINE_Y_RANGE),
+    );
+
+    let angle = rng.gen_range(-machine.angle..=machine.angle).to_radians();
+    let velocity = Vec2::new(-angle.cos(), angle.sin()) * machine.speed;
+
+    // Heading left, clockwise spin curves the ball up the screen and anticlockwise spin down it.
+    let spin = match machine.spin {
+        SpinPattern::NoSpin => 0.0,
+        SpinPattern::CurveUp => -MACHINE_SPIN,
+        SpinPattern::CurveDown => MACHINE_SPIN,
+        SpinPattern::Alternating if ball.is_multiple_of(2) => -MACHINE_SPIN,
+        SpinPattern::Alternating => MACHINE_SPIN,
+        SpinPattern::Random => rng.gen_range(-MACHINE_SPIN..=MACHINE_SPIN),
+    };
+
+    (position, velocity, spin)
+}
+
+fn start_session(
+    mut commands: Commands,
+    mut practice: ResMut<Practice>,
+    rules: Res<GameRules>,
+    material: Res<TargetMaterial>,
+    windows: Res<Windows>,
+) {
+    *practice = Practice::default();
+    if rules.mode != GameMode::Practice {
+        return;
+    }
+
+    let window = windows.get_primary().unwrap();
+    let x = window.width() / 2.0 - TARGET_WIDTH / 2.0;
+    let y = window.height() / 2.0 - TARGET_HEIGHT / 2.0;
+    for y in [y, -y].iter() {
+        commands
+            .spawn_bundle(SpriteBundle {
+                material: material.0.clone(),
+                transform: Transform::from_xyz(x, *y, 0.0),
+                sprite: Sprite::new(Vec2::new(TARGET_WIDTH, TARGET_HEIGHT)),
+                ..Default::default()
+            })
+            .insert(TargetZone);
+    }
+}
+
+fn remove_targets(
+    mut commands: Commands,
+    query: Query<Entity, With<TargetZone>>,
+) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+fn reset_machine(mut timer: ResMut<MachineTimer>) {
+    timer.0 = 0.0;
+}
+
+// Nobody has to press Enter for every ball, the machine serves the next one on its own.
+fn machine_feed(
+    time: Res<Time>,
+    rules: Res<GameRules>,
+    mut timer: ResMut<MachineTimer>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if rules.mode != GameMode::Practice {
+        return;
+    }
+
+    timer.0 += time.delta_seconds();
+    if timer.0 >= MACHINE_INTERVAL {
+        timer.0 = 0.0;
+        // Enter could have beaten us to it this frame.
+        let _ = app_state.set(AppState::Play);
+    }
+}
+
+// Practice is scored here rather than in score::scored. A ball that leaves on the right after the
+// player touched it is a return, one that leaves on the left is a miss.
+fn score_practice(
+    mut commands: Commands,
+    mut practice: ResMut<Practice>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut app_state: ResMut<State<AppState>>,
+    query: Query<(Entity, &Transform, &Ball)>,
+    windows: Res<Windows>,
+    audio: Res<Audio>,
+    loaded_audio: Res<LoadedAudio>,
+    rules: Res<GameRules>,
+    mut points: EventWriter<PointScored>,
+) {
+    if rules.mode != GameMode::Practice {
+        return;
+    }
+    let window = windows.get_primary().unwrap();
+
+    let mut decided = false;
+    for (entity, transform, ball) in query.iter() {
+        let position = transform.translation;
+        let conceded = if position.x > window.width() / 2.0 {
+            Player::Player2
+        } else if position.x < -window.width() / 2.0 {
+            Player::Player1
+        } else {
+            continue;
+        };
+
+        audio.play(loaded_audio.score.clone());
+        commands.entity(entity).despawn();
+        decided = true;
+
+        if conceded == Player::Player2 && ball.last_hit == Some(Player::Player1) {
+            practice.returns += 1;
+            scoreboard.player1 += 1;
+            if position.y.abs() > window.height() / 2.0 - TARGET_HEIGHT {
+                practice.targets += 1;
+                scoreboard.player1 += TARGET_BONUS;
+            }
+        } else {
+            practice.misses += 1;
+        }
+        points.send(PointScored { scorer: None });
+    }
+
+    if decided {
+        let next = if practice.played() >= PRACTICE_BALLS { AppState::Done } else { AppState::Serve };
+        app_state.set(next).unwrap();
+    }
+}
+
+fn update_practice_text(
+    app_state: Res<State<AppState>>,
+    practice: Res<Practice>,
+    rules: Res<GameRules>,
+    asset_server: Res<AssetServer>,
+    mut query: Query<&mut Text, With<PracticeText>>,
+) {
+    if let Ok(mut text) = query.single_mut() {
+        let value = match app_state.current() {
+            _ if rules.mode != GameMode::Practice => None,
+            AppState::Serve | AppState::Play => Some(format!(
+                "Ball {} of {}   Returns: {}   Targets: {}",
+                (practice.played() + 1).min(PRACTICE_BALLS),
+                PRACTICE_BALLS,
+                practice.returns,
+                practice.targets,
+            )),
+            AppState::Done => Some(format!(
+                "Returned {} of {} balls, {} into the targets",
+                practice.returns,
+                PRACTICE_BALLS,
+                practice.targets,
+            )),
+            _ => None,
+        };
+
+        text.sections = match value {
+            Some(value) => vec![TextSection {
+                value,
+                style: TextStyle {
+                    font: asset_server.load("fonts/font.ttf"),
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                },
+            }],
+            None => Vec::new(),
+        };
+    }
+}
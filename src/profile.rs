@@ -0,0 +1,163 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{achievements::Achievement, ball::BallMaterial, paddle::PaddleMaterial, rating::INITIAL_RATING, storage};
+
+const PROFILES_FILE: &str = "profiles.ron";
+
+pub struct ProfilePlugin;
+
+/// Which keys move your paddle. Whoever plays on the right paddle gets the other set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControlScheme {
+    WS,
+    Arrows,
+}
+
+impl ControlScheme {
+    pub const ALL: [ControlScheme; 2] = [ControlScheme::WS, ControlScheme::Arrows];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ControlScheme::WS => "W / S",
+            ControlScheme::Arrows => "Up / Down",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Theme {
+    Classic,
+    Midnight,
+    Grass,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::Classic, Theme::Midnight, Theme::Grass];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Theme::Classic => "Classic",
+            Theme::Midnight => "Midnight",
+            Theme::Grass => "Grass",
+        }
+    }
+
+    // Background, paddles and ball.
+    fn colors(&self) -> (Color, Color, Color) {
+        match self {
+            Theme::Classic => (Color::rgb(0.4, 0.4, 0.4), Color::rgb(1.0, 1.0, 1.0), Color::rgb(1.0, 0.5, 0.5)),
+            Theme::Midnight => (Color::rgb(0.02, 0.02, 0.08), Color::rgb(0.3, 0.9, 1.0), Color::rgb(1.0, 0.9, 0.2)),
+            Theme::Grass => (Color::rgb(0.1, 0.35, 0.12), Color::rgb(0.95, 0.95, 0.9), Color::rgb(0.9, 1.0, 0.3)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    pub controls: ControlScheme,
+    pub theme: Theme,
+    // Profiles saved before ratings existed start out like a new player.
+    #[serde(default = "initial_rating")]
+    pub rating: f32,
+    /// Matches that changed the rating. Matches against other humans don't.
+    #[serde(default)]
+    pub rated_matches: u32,
+    /// In the order they were unlocked.
+    #[serde(default)]
+    pub achievements: Vec<Achievement>,
+    /// The longest run of returns in squash.
+    #[serde(default)]
+    pub squash_best: u32,
+}
+
+fn initial_rating() -> f32 {
+    INITIAL_RATING
+}
+
+impl Profile {
+    pub fn new(name: &str) -> Self {
+        Profile {
+            name: name.to_string(),
+            controls: ControlScheme::WS,
+            theme: Theme::Classic,
+            rating: INITIAL_RATING,
+            rated_matches: 0,
+            achievements: Vec::new(),
+            squash_best: 0,
+        }
+    }
+}
+
+/// Everyone who has played on this machine. The active profile belongs to whoever is at the
+/// keyboard: it plays the left paddle in local matches and is the name other people see online.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Profiles {
+    pub list: Vec<Profile>,
+    /// Index into list.
+    pub active: usize,
+}
+
+impl Profiles {
+    pub fn load() -> Self {
+        storage::load(PROFILES_FILE)
+    }
+
+    pub fn active(&self) -> &Profile {
+        &self.list[self.active]
+    }
+
+    pub fn active_mut(&mut self) -> &mut Profile {
+        &mut self.list[self.active]
+    }
+
+    /// Makes the profile with this name the active one, creating it if this is their first time.
+    pub fn select_or_create(&mut self, name: &str) {
+        self.active = match self.list.iter().position(|profile| profile.name == name) {
+            Some(index) => index,
+            None => {
+                self.list.push(Profile::new(name));
+                self.list.len() - 1
+            }
+        };
+    }
+}
+
+impl Plugin for ProfilePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system(save_profiles.system())
+            .add_system(apply_theme.system());
+    }
+}
+
+// Profiles only change from the menu or at the end of a match, so saving the whole file every time is cheap enough.
+fn save_profiles(
+    profiles: Res<Profiles>,
+) {
+    if profiles.is_changed() {
+        storage::save(PROFILES_FILE, &*profiles);
+    }
+}
+
+fn apply_theme(
+    profiles: Res<Profiles>,
+    mut clear_color: ResMut<ClearColor>,
+    paddle_material: Res<PaddleMaterial>,
+    ball_material: Res<BallMaterial>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    if !profiles.is_changed() {
+        return;
+    }
+
+    let (background, paddle, ball) = profiles.active().theme.colors();
+    clear_color.0 = background;
+    if let Some(material) = materials.get_mut(&paddle_material.0) {
+        material.color = paddle;
+    }
+    if let Some(material) = materials.get_mut(&ball_material.0) {
+        material.color = ball;
+    }
+}
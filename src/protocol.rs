@@ -0,0 +1,325 @@
+use std::{io::{self, Read}, net::TcpStream};
+
+use crate::simulation::{MatchRules, PaddleInput, Phase, SimulationState};
+
+// What goes over the wire between the dedicated server and its clients. Joining, the lobby and
+// leaving go over TCP as lines of text, and everything that happens every tick goes over UDP.
+
+const SNAPSHOT_MAGIC: [u8; 2] = *b"PS";
+const INPUT_MAGIC: [u8; 2] = *b"PI";
+
+/// Where the match is at, as far as the server is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchPhase {
+    /// Not everyone has joined yet.
+    Waiting,
+    Serve,
+    Play,
+    Done,
+}
+
+impl From<Phase> for MatchPhase {
+    fn from(phase: Phase) -> Self {
+        match phase {
+            Phase::Serve => MatchPhase::Serve,
+            Phase::Play => MatchPhase::Play,
+            Phase::Done => MatchPhase::Done,
+        }
+    }
+}
+
+/// Everything a client needs to draw one tick of the match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub tick: u32,
+    pub phase: MatchPhase,
+    pub winner: Option<usize>,
+    pub ball_position: [f32; 2],
+    pub ball_velocity: [f32; 2],
+    pub paddles: [f32; 2],
+    pub scores: [u32; 2],
+    /// How many times the ball has been hit since the last serve.
+    pub rally: u32,
+}
+
+impl Snapshot {
+    pub fn from_state(tick: u32, phase: MatchPhase, state: &SimulationState) -> Self {
+        Snapshot {
+            tick,
+            phase,
+            winner: state.winner,
+            ball_position: state.ball_position,
+            ball_velocity: state.ball_velocity,
+            paddles: state.paddles,
+            scores: state.scores,
+            rally: state.rally,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(44);
+        bytes.extend_from_slice(&SNAPSHOT_MAGIC);
+        bytes.extend_from_slice(&self.tick.to_le_bytes());
+        bytes.push(match self.phase {
+            MatchPhase::Waiting => 0,
+            MatchPhase::Serve => 1,
+            MatchPhase::Play => 2,
+            MatchPhase::Done => 3,
+        });
+        bytes.push(self.winner.map_or(u8::MAX, |winner| winner as u8));
+        for value in self.ball_position.iter().chain(self.ball_velocity.iter()).chain(self.paddles.iter()) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in self.scores.iter().chain(std::iter::once(&self.rally)) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Snapshot> {
+        if bytes.len() != 44 || bytes[0..2] != SNAPSHOT_MAGIC {
+            return None;
+        }
+        let float = |at: usize| -> Option<f32> { Some(f32::from_le_bytes(bytes[at..at + 4].try_into().ok()?)) };
+        let int = |at: usize| -> Option<u32> { Some(u32::from_le_bytes(bytes[at..at + 4].try_into().ok()?)) };
+
+        Some(Snapshot {
+            tick: int(2)?,
+            phase: match bytes[6] {
+                0 => MatchPhase::Waiting,
+                1 => MatchPhase::Serve,
+                2 => MatchPhase::Play,
+                3 => MatchPhase::Done,
+                _ => return None,
+            },
+            winner: if bytes[7] == u8::MAX { None } else { Some(bytes[7] as usize) },
+            ball_position: [float(8)?, float(12)?],
+            ball_velocity: [float(16)?, float(20)?],
+            paddles: [float(24)?, float(28)?],
+            scores: [int(32)?, int(36)?],
+            rally: int(40)?,
+        })
+    }
+}
+
+/// A clients input for one tick. The sequence number lets the server throw away packets that
+/// arrive out of order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputPacket {
+    pub token: u32,
+    pub sequence: u32,
+    pub input: PaddleInput,
+}
+
+impl InputPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(11);
+        bytes.extend_from_slice(&INPUT_MAGIC);
+        bytes.extend_from_slice(&self.token.to_le_bytes());
+        bytes.extend_from_slice(&self.sequence.to_le_bytes());
+        bytes.push(self.input as u8);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<InputPacket> {
+        if bytes.len() != 11 || bytes[0..2] != INPUT_MAGIC {
+            return None;
+        }
+
+        Some(InputPacket {
+            token: u32::from_le_bytes(bytes[2..6].try_into().ok()?),
+            sequence: u32::from_le_bytes(bytes[6..10].try_into().ok()?),
+            input: bytes[10] as PaddleInput,
+        })
+    }
+}
+
+const MAX_NAME_LENGTH: usize = 16;
+
+/// Who is sitting where in the lobby, and what they are about to play.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LobbyState {
+    /// The token of the client on each side, Player1 on the left and Player2 on the right.
+    pub seats: [Option<u32>; 2],
+    pub ready: [bool; 2],
+    /// Empty for a side nobody is sitting on.
+    pub names: [String; 2],
+    pub rules: MatchRules,
+}
+
+impl LobbyState {
+    pub fn sit(&mut self, seat: usize, token: u32, name: &str) {
+        self.seats[seat] = Some(token);
+        self.ready[seat] = false;
+        self.names[seat] = name.to_string();
+    }
+
+    pub fn leave(&mut self, seat: usize) {
+        self.seats[seat] = None;
+        self.ready[seat] = false;
+        self.names[seat].clear();
+    }
+
+    pub fn seat_of(&self, token: u32) -> Option<usize> {
+        self.seats.iter().position(|seat| *seat == Some(token))
+    }
+
+    /// Both sides are taken and both players said they are ready.
+    pub fn all_ready(&self) -> bool {
+        self.seats.iter().all(Option::is_some) && self.ready.iter().all(|ready| *ready)
+    }
+}
+
+/// The lines sent over the TCP connection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlMessage {
+    /// Client to server, entering the lobby under a name.
+    Join { name: String },
+    /// Server to client. The token goes into every input packet and tells the client which seat is theirs.
+    Welcome { token: u32 },
+    /// Client to server, taking a side or giving it up with None.
+    Side(Option<usize>),
+    /// Client to server, only listened to from seated players.
+    Rules(MatchRules),
+    /// Client to server.
+    Ready(bool),
+    /// Server to client, whenever anything in the lobby changes. Also means any match is over.
+    Lobby(LobbyState),
+    /// Server to client, the match starts with the next snapshot.
+    Start,
+}
+
+impl ControlMessage {
+    pub fn to_line(&self) -> String {
+        let token = |seat: Option<u32>| seat.map_or("-".to_string(), |token| token.to_string());
+        match self {
+            ControlMessage::Join { name } => format!("JOIN {}\n", clean_name(name)),
+            ControlMessage::Welcome { token } => format!("WELCOME {}\n", token),
+            ControlMessage::Side(side) => format!("SIDE {}\n", side.map_or("-".to_string(), |side| side.to_string())),
+            ControlMessage::Rules(rules) => format!("RULES {} {}\n", rules.victory_score, rules.speed_up),
+            ControlMessage::Ready(ready) => format!("READY {}\n", *ready as u8),
+            // Names can have spaces in them, so they go last and are split off with tabs.
+            ControlMessage::Lobby(lobby) => format!(
+                "LOBBY {} {} {} {} {} {}\t{}\t{}\n",
+                token(lobby.seats[0]),
+                token(lobby.seats[1]),
+                lobby.ready[0] as u8,
+                lobby.ready[1] as u8,
+                lobby.rules.victory_score,
+                lobby.rules.speed_up,
+                clean_name(&lobby.names[0]),
+                clean_name(&lobby.names[1])
+            ),
+            ControlMessage::Start => "START\n".to_string(),
+        }
+    }
+
+    pub fn parse(line: &str) -> Option<ControlMessage> {
+        let mut parts = line.split('\t');
+        let mut words = parts.next()?.split_whitespace();
+        match words.next()? {
+            "JOIN" => Some(ControlMessage::Join {
+                name: clean_name(line.trim_start().strip_prefix("JOIN")?),
+            }),
+            "WELCOME" => Some(ControlMessage::Welcome { token: words.next()?.parse().ok()? }),
+            "SIDE" => {
+                let side = seat(words.next())?.map(|side| side as usize);
+                if side.is_some_and(|side| side > 1) {
+                    return None;
+                }
+                Some(ControlMessage::Side(side))
+            }
+            "RULES" => {
+                let rules = MatchRules {
+                    victory_score: words.next()?.parse().ok()?,
+                    speed_up: words.next()?.parse().ok()?,
+                };
+                // Rules the lobby doesn't offer are dropped like any other line we don't understand.
+                if !rules.is_offered() {
+                    return None;
+                }
+                Some(ControlMessage::Rules(rules))
+            }
+            "READY" => Some(ControlMessage::Ready(flag(words.next())?)),
+            "LOBBY" => Some(ControlMessage::Lobby(LobbyState {
+                seats: [seat(words.next())?, seat(words.next())?],
+                ready: [flag(words.next())?, flag(words.next())?],
+                names: [clean_name(parts.next()?), clean_name(parts.next()?)],
+                rules: MatchRules {
+                    victory_score: words.next()?.parse().ok()?,
+                    speed_up: words.next()?.parse().ok()?,
+                },
+            })),
+            "START" => Some(ControlMessage::Start),
+            _ => None,
+        }
+    }
+}
+
+/// Names are shown on other peoples screens and sent as part of a line, so they are kept short
+/// and anything that would get in the way is thrown out.
+pub fn clean_name(name: &str) -> String {
+    name.chars()
+        .filter(|character| !character.is_control())
+        .take(MAX_NAME_LENGTH)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+// A token, or "-" for nobody.
+fn seat(word: Option<&str>) -> Option<Option<u32>> {
+    match word? {
+        "-" => Some(None),
+        word => Some(Some(word.parse().ok()?)),
+    }
+}
+
+fn flag(word: Option<&str>) -> Option<bool> {
+    match word? {
+        "0" => Some(false),
+        "1" => Some(true),
+        _ => None,
+    }
+}
+
+// No message comes close to this. A peer that sends more without ending the line isn't speaking our protocol.
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// Collects whole lines from a non-blocking TCP stream, keeping partial lines around until the rest shows up.
+#[derive(Default)]
+pub struct LineReader {
+    buffer: Vec<u8>,
+}
+
+impl LineReader {
+    /// Returns any complete lines that have arrived. Fails with `UnexpectedEof` once the other side hangs up,
+    /// and with `InvalidData` if it sends a line longer than anything we would ever send.
+    pub fn read_lines(&mut self, stream: &mut TcpStream) -> io::Result<Vec<String>> {
+        let mut chunk = [0u8; 512];
+        loop {
+            match stream.read(&mut chunk) {
+                Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")),
+                Ok(length) => {
+                    self.buffer.extend_from_slice(&chunk[..length]);
+                    if length < chunk.len() {
+                        break;
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error),
+            }
+        }
+
+        let mut lines = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            // Only the line ending goes, a tab at the end can mean an empty name.
+            lines.push(String::from_utf8_lossy(&line).trim_end_matches(&['\r', '\n'][..]).to_string());
+        }
+        if self.buffer.len() > MAX_LINE_LENGTH {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+        }
+        Ok(lines)
+    }
+}
@@ -0,0 +1,252 @@
+use bevy::prelude::*;
+
+use crate::{game_state::AppState, history::{opponent, opponents_of_player1, Opponent}, profile::Profiles, rules::{AiDifficulty, GameRules}, score::MatchResult, Player};
+
+/// Where every new profile starts, level with the Easy AI.
+pub const INITIAL_RATING: f32 = 1200.0;
+// How far a single match can move a rating.
+const K_FACTOR: f32 = 32.0;
+
+pub struct RatingPlugin;
+
+/// The active profile's rating before and after the last match, if that match was rated.
+#[derive(Default)]
+pub struct RatingChange(pub Option<(f32, f32)>);
+
+struct RatingText;
+struct LeaderboardText;
+
+impl Plugin for RatingPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<RatingChange>()
+            .add_startup_system(setup.system())
+            .add_system(rate_match.system())
+            .add_system(update_rating_text.system())
+            .add_system(update_leaderboard_text.system())
+            .add_system_set(SystemSet::on_exit(AppState::Start).with_system(clear_rating_change.system()))
+            .add_system_set(SystemSet::on_update(AppState::Start).with_system(open_leaderboard.system()))
+            .add_system_set(SystemSet::on_update(AppState::Leaderboard).with_system(close_leaderboard.system()));
+    }
+}
+
+/// The AI never learns, so each difficulty keeps a fixed rating. That also gives the ratings of
+/// profiles on different machines something to be measured against.
+pub fn ai_rating(difficulty: AiDifficulty) -> f32 {
+    match difficulty {
+        AiDifficulty::Hard => 1800.0,
+        AiDifficulty::Medium => 1500.0,
+        AiDifficulty::Easy => 1200.0,
+        AiDifficulty::Beginner => 900.0,
+    }
+}
+
+/// The chance Elo gives a player rated `rating` of beating one rated `opponent`.
+pub fn expected_score(rating: f32, opponent: f32) -> f32 {
+    1.0 / (1.0 + 10f32.powf((opponent - rating) / 400.0))
+}
+
+/// The new rating after a match against all of these opponents. With more than one opponent each
+/// of them counts as a separate game against the winner, and the change is averaged so a four
+/// player match isn't worth three times as much.
+pub fn updated_rating(rating: f32, opponents: &[f32], won: bool) -> f32 {
+    if opponents.is_empty() {
+        return rating;
+    }
+    let score = if won { 1.0 } else { 0.0 };
+    let change: f32 = opponents.iter().map(|opponent| K_FACTOR * (score - expected_score(rating, *opponent))).sum();
+    rating + change / opponents.len() as f32
+}
+
+fn setup(
+    mut commands: Commands,
+    windows: Res<Windows>,
+) {
+    let window = windows.get_primary().unwrap();
+
+    // Sits under the winner on the Done screen.
+    commands
+    .spawn_bundle(TextBundle {
+        style: Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(35.0),
+                left: Val::Px(window.width() / 2.0 - 110.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::default(),
+        ..Default::default()
+    })
+    .insert(RatingText);
+
+    commands
+    .spawn_bundle(TextBundle {
+        style: Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(60.0),
+                left: Val::Px(window.width() / 2.0 - 150.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::default(),
+        ..Default::default()
+    })
+    .insert(LeaderboardText);
+}
+
+fn clear_rating_change(mut change: ResMut<RatingChange>) {
+    change.0 = None;
+}
+
+// Like the history, only the active profile on the left paddle is rated. Other humans don't have
+// a profile in a local match and bots can be anything, so only AI opponents count.
+fn rate_match(
+    mut events: EventReader<MatchResult>,
+    rules: Res<GameRules>,
+    mut profiles: ResMut<Profiles>,
+    mut change: ResMut<RatingChange>,
+) {
+    for result in events.iter() {
+        if !rules.controller(Player::Player1).is_human() {
+            continue;
+        }
+
+        let opponents: Vec<f32> = opponents_of_player1(result)
+            .iter()
+            .filter_map(|player| match opponent(*player, result, &rules) {
+                Opponent::Ai(difficulty) => Some(ai_rating(difficulty)),
+                Opponent::Human | Opponent::Bot | Opponent::Script | Opponent::Adaptive | Opponent::Replay => None,
+            })
+            .collect();
+        if opponents.is_empty() {
+            continue;
+        }
+
+        let profile = profiles.active_mut();
+        let before = profile.rating;
+        profile.rating = updated_rating(before, &opponents, result.winner == Player::Player1);
+        profile.rated_matches += 1;
+        change.0 = Some((before, profile.rating));
+    }
+}
+
+fn update_rating_text(
+    app_state: Res<State<AppState>>,
+    change: Res<RatingChange>,
+    asset_server: Res<AssetServer>,
+    mut query: Query<&mut Text, With<RatingText>>,
+) {
+    if let Ok(mut text) = query.single_mut() {
+        text.sections.clear();
+        if let (AppState::Done, Some((before, after))) = (app_state.current(), change.0) {
+            let difference = after.round() - before.round();
+            text.sections.push(TextSection {
+                value: format!("Rating {:.0} -> {:.0} ({:+})", before, after, difference),
+                style: TextStyle {
+                    font: asset_server.load("fonts/font.ttf"),
+                    font_size: 18.0,
+                    color: if difference < 0.0 { Color::rgb(1.0, 0.5, 0.5) } else { Color::rgb(0.5, 1.0, 0.5) },
+                },
+            });
+        }
+    }
+}
+
+fn open_leaderboard(
+    keys: Res<Input<KeyCode>>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if keys.just_pressed(KeyCode::R) {
+        app_state.set(AppState::Leaderboard).unwrap();
+    }
+}
+
+fn close_leaderboard(
+    keys: Res<Input<KeyCode>>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if keys.just_pressed(KeyCode::Back) {
+        app_state.set(AppState::Start).unwrap();
+    }
+}
+
+// Every profile on this machine ranked together with the AI difficulties they measure themselves against.
+fn update_leaderboard_text(
+    app_state: Res<State<AppState>>,
+    profiles: Res<Profiles>,
+    asset_server: Res<AssetServer>,
+    mut query: Query<&mut Text, With<LeaderboardText>>,
+) {
+    if let Ok(mut text) = query.single_mut() {
+        if *app_state.current() != AppState::Leaderboard {
+            text.sections.clear();
+            return;
+        }
+
+        let mut entries: Vec<(String, f32, Color)> = profiles
+            .list
+            .iter()
+            .enumerate()
+            .map(|(index, profile)| {
+                let color = if index == profiles.active { Color::YELLOW } else { Color::WHITE };
+                (format!("{} ({} rated)", profile.name, profile.rated_matches), profile.rating, color)
+            })
+            .collect();
+        entries.extend(AiDifficulty::ALL.iter().map(|difficulty| (format!("AI ({})", difficulty.name()), ai_rating(*difficulty), Color::GRAY)));
+        entries.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+        let font = asset_server.load("fonts/font.ttf");
+        let style = |color: Color| TextStyle {
+            font: font.clone(),
+            font_size: 16.0,
+            color,
+        };
+
+        text.sections = vec![TextSection {
+            value: "Leaderboard\n\n".to_string(),
+            style: style(Color::WHITE),
+        }];
+        text.sections.extend(entries.into_iter().enumerate().map(|(rank, (name, rating, color))| TextSection {
+            value: format!("{:>2}. {:.0}  {}\n", rank + 1, rating, name),
+            style: style(color),
+        }));
+        text.sections.push(TextSection {
+            value: "\nBackspace to go back".to_string(),
+            style: style(Color::GRAY),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn equal_ratings_are_an_even_match() {
+        assert!(close(expected_score(1500.0, 1500.0), 0.5));
+        assert!(close(updated_rating(1500.0, &[1500.0], true), 1500.0 + K_FACTOR / 2.0));
+        assert!(close(updated_rating(1500.0, &[1500.0], false), 1500.0 - K_FACTOR / 2.0));
+    }
+
+    #[test]
+    fn four_hundred_points_is_ten_to_one() {
+        assert!(close(expected_score(1600.0, 1200.0), 10.0 / 11.0));
+        assert!(close(expected_score(1200.0, 1600.0), 1.0 / 11.0));
+    }
+
+    #[test]
+    fn several_opponents_are_averaged() {
+        assert!(close(updated_rating(1500.0, &[1500.0, 1500.0, 1500.0], true), 1500.0 + K_FACTOR / 2.0));
+        assert_eq!(updated_rating(1500.0, &[], true), 1500.0);
+    }
+}
@@ -0,0 +1,393 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Player;
+
+/// Which variant of Pong we are playing. Picked on the Start screen through the menu.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameMode {
+    Classic,
+    MultiBall,
+    FourPlayer,
+    /// One player on their own, returning balls fed by a ball machine on the right.
+    Practice,
+    /// One player against the right wall, squash style, for as long a rally as they can manage.
+    Squash,
+}
+
+// How many balls are put into play on every serve in the multi-ball mode.
+const MULTI_BALL_COUNT: usize = 3;
+
+impl GameMode {
+    pub const ALL: [GameMode; 5] = [GameMode::Classic, GameMode::MultiBall, GameMode::FourPlayer, GameMode::Practice, GameMode::Squash];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameMode::Classic => "Classic",
+            GameMode::MultiBall => "Multi-Ball",
+            GameMode::FourPlayer => "Four Player",
+            GameMode::Practice => "Practice",
+            GameMode::Squash => "Squash",
+        }
+    }
+
+    pub fn ball_count(&self) -> usize {
+        match self {
+            GameMode::MultiBall => MULTI_BALL_COUNT,
+            _ => 1,
+        }
+    }
+
+    /// The players that have a paddle in this mode.
+    pub fn players(&self) -> &'static [Player] {
+        match self {
+            GameMode::FourPlayer => &Player::ALL,
+            GameMode::Practice | GameMode::Squash => &Player::ALL[..1],
+            _ => &Player::ALL[..2],
+        }
+    }
+
+    /// The edges of the screen a ball can leave through. Everything else is a wall.
+    pub fn open_edges(&self) -> &'static [Player] {
+        match self {
+            // The ball machine stands on the right, returns go past it.
+            GameMode::Practice => &Player::ALL[..2],
+            _ => self.players(),
+        }
+    }
+
+    /// Modes with a single player. They keep their own score rather than going through score::scored,
+    /// and never end in a MatchResult.
+    pub fn is_solo(&self) -> bool {
+        matches!(self, GameMode::Practice | GameMode::Squash)
+    }
+}
+
+/// How points are counted in the four player mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scoring {
+    /// The last player to touch the ball scores when it goes into someone elses goal.
+    Points,
+    /// Everybody starts with a few lives and loses one for every goal let in. The last player
+    /// standing wins, and the goals of eliminated players are closed off with a wall.
+    Elimination,
+}
+
+impl Scoring {
+    pub const ALL: [Scoring; 2] = [Scoring::Points, Scoring::Elimination];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scoring::Points => "Points",
+            Scoring::Elimination => "Elimination",
+        }
+    }
+}
+
+/// Who is driving a paddle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Controller {
+    /// Someone at the keyboard.
+    Human,
+    Ai,
+    Gamepad,
+    /// An AI that works out where the ball is going instead of chasing it.
+    PredictiveAi,
+    /// A program outside the game, given with `--bot` on the command line.
+    Bot,
+    /// The intent hook of the match script.
+    Script,
+    /// An AI that makes itself easier or harder during the match to keep it close.
+    AdaptiveAi,
+    /// The same moves this paddle made in the last match, see controller::Recording.
+    Replay,
+}
+
+impl Controller {
+    pub const ALL: [Controller; 5] = [Controller::Human, Controller::Gamepad, Controller::Ai, Controller::PredictiveAi, Controller::AdaptiveAi];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Controller::Human => "Human",
+            Controller::Ai => "AI",
+            Controller::Gamepad => "Gamepad",
+            Controller::PredictiveAi => "Predictive AI",
+            Controller::Bot => "Bot",
+            Controller::Script => "Script AI",
+            Controller::AdaptiveAi => "Adaptive AI",
+            Controller::Replay => "Replay",
+        }
+    }
+
+    /// The AIs that play at a fixed strength, set by their slowdown handicap.
+    pub fn is_ai(&self) -> bool {
+        matches!(self, Controller::Ai | Controller::PredictiveAi)
+    }
+
+    /// Someone sitting in front of the game, as opposed to the AI or a bot.
+    pub fn is_human(&self) -> bool {
+        matches!(self, Controller::Human | Controller::Gamepad)
+    }
+}
+
+/// AI opponents are told apart by how much their slowdown handicap holds them back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AiDifficulty {
+    Hard,
+    Medium,
+    Easy,
+    Beginner,
+}
+
+impl AiDifficulty {
+    /// In the same order as AI_SLOWDOWN_OPTIONS.
+    pub const ALL: [AiDifficulty; 4] = [AiDifficulty::Hard, AiDifficulty::Medium, AiDifficulty::Easy, AiDifficulty::Beginner];
+
+    pub fn from_slowdown(slowdown: f32) -> AiDifficulty {
+        AiDifficulty::ALL
+            .iter()
+            .zip(AI_SLOWDOWN_OPTIONS.iter())
+            .rev()
+            .find(|(_, option)| slowdown >= **option - 0.01)
+            .map_or(AiDifficulty::Hard, |(difficulty, _)| *difficulty)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AiDifficulty::Hard => "Hard",
+            AiDifficulty::Medium => "Medium",
+            AiDifficulty::Easy => "Easy",
+            AiDifficulty::Beginner => "Beginner",
+        }
+    }
+}
+
+/// How the ball machine in practice mode puts spin on its serves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpinPattern {
+    NoSpin,
+    CurveUp,
+    CurveDown,
+    /// Up on one serve, down on the next.
+    Alternating,
+    Random,
+}
+
+impl SpinPattern {
+    pub const ALL: [SpinPattern; 5] = [SpinPattern::NoSpin, SpinPattern::CurveUp, SpinPattern::CurveDown, SpinPattern::Alternating, SpinPattern::Random];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpinPattern::NoSpin => "None",
+            SpinPattern::CurveUp => "Curve Up",
+            SpinPattern::CurveDown => "Curve Down",
+            SpinPattern::Alternating => "Alternating",
+            SpinPattern::Random => "Random",
+        }
+    }
+}
+
+pub const MACHINE_SPEED_OPTIONS: [f32; 4] = [250.0, 350.0, 450.0, 600.0];
+/// In degrees either side of straight across.
+pub const MACHINE_ANGLE_OPTIONS: [f32; 4] = [0.0, 15.0, 30.0, 45.0];
+
+/// The settings of the ball machine in practice mode. Every serve goes out at the same speed, at a
+/// random angle up to `angle` degrees either way.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BallMachine {
+    pub speed: f32,
+    pub angle: f32,
+    pub spin: SpinPattern,
+}
+
+impl Default for BallMachine {
+    fn default() -> Self {
+        BallMachine {
+            speed: MACHINE_SPEED_OPTIONS[1],
+            angle: MACHINE_ANGLE_OPTIONS[1],
+            spin: SpinPattern::NoSpin,
+        }
+    }
+}
+
+// How much faster the ball gets with each paddle hit, as a share of its serve speed. The stepped curve
+// only speeds up every few hits, by a bigger step.
+const SPEED_UP_PER_HIT: f32 = 0.1;
+const SPEED_UP_STEP: f32 = 0.25;
+const SPEED_UP_STEP_HITS: u32 = 4;
+
+/// How a ball speeds up over a rally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SpeedCurve {
+    Linear,
+    // Every ball used to get 10% faster with each hit, so that is what rules saved before there was a choice get.
+    #[default]
+    Exponential,
+    Stepped,
+}
+
+impl SpeedCurve {
+    pub const ALL: [SpeedCurve; 3] = [SpeedCurve::Linear, SpeedCurve::Exponential, SpeedCurve::Stepped];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpeedCurve::Linear => "Linear",
+            SpeedCurve::Exponential => "Exponential",
+            SpeedCurve::Stepped => "Stepped",
+        }
+    }
+
+    /// How many times faster than its serve a ball is after this many paddle hits.
+    pub fn factor(&self, hits: u32) -> f32 {
+        match self {
+            SpeedCurve::Linear => 1.0 + SPEED_UP_PER_HIT * hits as f32,
+            SpeedCurve::Exponential => (1.0 + SPEED_UP_PER_HIT).powi(hits as i32),
+            SpeedCurve::Stepped => 1.0 + SPEED_UP_STEP * (hits / SPEED_UP_STEP_HITS) as f32,
+        }
+    }
+}
+
+/// In pixels per second. The fastest option has to stay above the speed the fast return achievement asks for.
+pub const MAX_SPEED_OPTIONS: [f32; 4] = [600.0, 1200.0, 1800.0, 2400.0];
+
+/// How paddles get moving.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Movement {
+    /// Straight to full speed, and a dead stop the moment you let go.
+    #[default]
+    Direct,
+    /// Paddles speed up and slow down, and carry their speed into the ball.
+    Momentum,
+}
+
+impl Movement {
+    pub const ALL: [Movement; 2] = [Movement::Direct, Movement::Momentum];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Movement::Direct => "Direct",
+            Movement::Momentum => "Momentum",
+        }
+    }
+}
+
+/// How strongly spin acts on the ball, 0.0 turns it off altogether.
+pub const SPIN_OPTIONS: [f32; 4] = [0.0, 0.5, 1.0, 2.0];
+
+pub const POINT_BONUS_OPTIONS: [u32; 4] = [0, 1, 2, 3];
+pub const PADDLE_SIZE_OPTIONS: [f32; 5] = [0.5, 0.75, 1.0, 1.25, 1.5];
+pub const PADDLE_SPEED_OPTIONS: [f32; 5] = [0.5, 0.75, 1.0, 1.25, 1.5];
+pub const AI_SLOWDOWN_OPTIONS: [f32; 4] = [0.0, 0.15, 0.3, 0.5];
+
+/// Evens out a match between players of different skill. The multipliers are applied on top of
+/// the default PaddleStats when we leave the Start screen.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Handicap {
+    /// Points the player already has when the match starts. In elimination these are extra lives.
+    pub point_bonus: u32,
+    pub paddle_size: f32,
+    pub paddle_speed: f32,
+    /// Fraction of speed taken away from the paddle, but only when the AI is driving it.
+    pub ai_slowdown: f32,
+}
+
+impl Handicap {
+    pub fn is_active(&self) -> bool {
+        *self != Handicap::default()
+    }
+}
+
+impl Default for Handicap {
+    fn default() -> Self {
+        Handicap {
+            point_bonus: 0,
+            paddle_size: 1.0,
+            paddle_speed: 1.0,
+            ai_slowdown: 0.0,
+        }
+    }
+}
+
+/// The rules for the current match. The menu writes to this while we are in AppState::Start
+/// and everything else only reads from it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameRules {
+    pub mode: GameMode,
+    /// Index into the Arenas resource.
+    pub arena: usize,
+    /// Index into the Scripts resource, for the script whose hooks are used in this match.
+    #[serde(default)]
+    pub script: Option<usize>,
+    pub scoring: Scoring,
+    /// Indexed by Player::index.
+    pub controllers: [Controller; 4],
+    /// Indexed by Player::index.
+    pub handicaps: [Handicap; 4],
+    #[serde(default)]
+    pub machine: BallMachine,
+    /// How much the ball curves and kicks off walls when it spins, and how much spin a moving paddle puts on it.
+    #[serde(default = "default_spin")]
+    pub spin: f32,
+    #[serde(default)]
+    pub speed_curve: SpeedCurve,
+    /// No ball ever goes faster than this, in pixels per second.
+    #[serde(default = "default_max_speed")]
+    pub max_speed: f32,
+    #[serde(default)]
+    pub movement: Movement,
+}
+
+// Rules saved before spin existed get it at full strength, like new ones.
+fn default_spin() -> f32 {
+    1.0
+}
+
+// Balls used to have no speed limit at all, so the default is the loosest one and every achievement
+// can still be earned without touching the menu.
+fn default_max_speed() -> f32 {
+    MAX_SPEED_OPTIONS[3]
+}
+
+impl GameRules {
+    pub fn controller(&self, player: Player) -> Controller {
+        self.controllers[player.index()]
+    }
+
+    pub fn handicap(&self, player: Player) -> &Handicap {
+        &self.handicaps[player.index()]
+    }
+
+    pub fn handicap_mut(&mut self, player: Player) -> &mut Handicap {
+        &mut self.handicaps[player.index()]
+    }
+
+    pub fn any_handicap(&self) -> bool {
+        self.mode.players().iter().any(|player| self.handicap(*player).is_active())
+    }
+
+    /// How fast a ball served at serve_speed should leave the paddle on its hits'th hit.
+    pub fn rally_speed(&self, serve_speed: f32, hits: u32) -> f32 {
+        (serve_speed * self.speed_curve.factor(hits)).min(self.max_speed)
+    }
+
+    pub fn is_elimination(&self) -> bool {
+        self.mode == GameMode::FourPlayer && self.scoring == Scoring::Elimination
+    }
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        GameRules {
+            mode: GameMode::Classic,
+            arena: 0,
+            script: None,
+            scoring: Scoring::Points,
+            controllers: [Controller::Human, Controller::Ai, Controller::Ai, Controller::Ai],
+            handicaps: [Handicap::default(); 4],
+            machine: BallMachine::default(),
+            spin: default_spin(),
+            speed_curve: SpeedCurve::default(),
+            max_speed: default_max_speed(),
+            movement: Movement::default(),
+        }
+    }
+}
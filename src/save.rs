@@ -0,0 +1,203 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{ball::{spawn_ball, Ball, BallMaterial, Rally}, game_state::AppState, paddle::Paddle, profile::Profiles, rules::{GameMode, GameRules}, score::Scoreboard, stats::MatchStats, storage, Player, Server};
+
+const SAVE_FILE: &str = "saved_match.ron";
+// How often a match in progress is written out, in seconds, so even a crash loses very little.
+const AUTOSAVE_SECONDS: f32 = 5.0;
+
+pub struct SavePlugin;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SavedBall {
+    position: [f32; 2],
+    velocity: [f32; 2],
+    last_hit: Option<Player>,
+    #[serde(default)]
+    spin: f32,
+    #[serde(default)]
+    serve_speed: f32,
+    #[serde(default)]
+    hits: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SavedPaddle {
+    player: Player,
+    position: [f32; 2],
+}
+
+/// Everything needed to pick a local match back up where it was left. The random bounces come
+/// from thread_rng, which has no state worth keeping, and moving obstacles just start over.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedMatch {
+    profile: String,
+    /// Serve or Play.
+    state: AppState,
+    rules: GameRules,
+    /// Indexed by Player::index.
+    scores: [u32; 4],
+    server: Player,
+    rally: u32,
+    balls: Vec<SavedBall>,
+    paddles: Vec<SavedPaddle>,
+    stats: MatchStats,
+}
+
+/// The match waiting to be resumed from the Start screen, if there is one.
+pub struct ResumableMatch(pub Option<SavedMatch>);
+
+// Put in while resuming, until the paddles for the saved rules exist and the saved positions are back.
+struct PendingResume(SavedMatch);
+
+struct AutosaveTimer(f32);
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .insert_resource(ResumableMatch(storage::load::<Option<SavedMatch>>(SAVE_FILE)))
+            .insert_resource(AutosaveTimer(0.0))
+            .add_system(autosave.system())
+            .add_system(finish_resume.system())
+            .add_system_set(SystemSet::on_update(AppState::Start).with_system(resume_match.system()))
+            .add_system_set(SystemSet::on_enter(AppState::Done).with_system(forget_saved_match.system()));
+    }
+}
+
+// Escape quits the game, so that saves straight away instead of waiting for the timer.
+fn autosave(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    app_state: Res<State<AppState>>,
+    mut timer: ResMut<AutosaveTimer>,
+    profiles: Res<Profiles>,
+    rules: Res<GameRules>,
+    scoreboard: Res<Scoreboard>,
+    server: Res<Server>,
+    rally: Res<Rally>,
+    stats: Res<MatchStats>,
+    balls: Query<(&Ball, &Transform)>,
+    paddles: Query<(&Paddle, &Transform)>,
+) {
+    // A practice session is over in a couple of minutes, so there's nothing worth coming back to.
+    let state = app_state.current();
+    if !matches!(state, AppState::Serve | AppState::Play) || rules.mode == GameMode::Practice {
+        timer.0 = 0.0;
+        return;
+    }
+
+    timer.0 += time.delta_seconds();
+    if timer.0 < AUTOSAVE_SECONDS && !keys.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    timer.0 = 0.0;
+
+    let saved = SavedMatch {
+        profile: profiles.active().name.clone(),
+        state: state.clone(),
+        rules: rules.clone(),
+        scores: scoreboard.all(),
+        server: server.0,
+        rally: rally.0,
+        balls: balls
+            .iter()
+            .map(|(ball, transform)| SavedBall {
+                position: [transform.translation.x, transform.translation.y],
+                velocity: [ball.velocity.x, ball.velocity.y],
+                last_hit: ball.last_hit,
+                spin: ball.spin,
+                serve_speed: ball.serve_speed,
+                hits: ball.hits,
+            })
+            .collect(),
+        paddles: paddles
+            .iter()
+            .map(|(paddle, transform)| SavedPaddle {
+                player: paddle.player,
+                position: [transform.translation.x, transform.translation.y],
+            })
+            .collect(),
+        stats: stats.clone(),
+    };
+    storage::save(SAVE_FILE, &Some(saved));
+}
+
+// Leaving the Start screen the usual way sets everything up for the saved rules. The saved scores
+// and positions are put back on top of that once it is done, by finish_resume.
+fn resume_match(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut resumable: ResMut<ResumableMatch>,
+    mut rules: ResMut<GameRules>,
+    mut profiles: ResMut<Profiles>,
+    mut server: ResMut<Server>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if !keys.just_pressed(KeyCode::C) {
+        return;
+    }
+    if let Some(saved) = resumable.0.take() {
+        *rules = saved.rules.clone();
+        profiles.select_or_create(&saved.profile);
+        // The stats look at who served as soon as we are back in Play.
+        server.0 = saved.server;
+        app_state.set(saved.state.clone()).unwrap();
+        commands.insert_resource(PendingResume(saved));
+    }
+}
+
+fn finish_resume(
+    mut commands: Commands,
+    pending: Option<Res<PendingResume>>,
+    app_state: Res<State<AppState>>,
+    mut scoreboard: ResMut<Scoreboard>,
+    mut rally: ResMut<Rally>,
+    mut stats: ResMut<MatchStats>,
+    ball_material: Res<BallMaterial>,
+    balls: Query<Entity, With<Ball>>,
+    mut paddles: Query<(&Paddle, &mut Transform)>,
+) {
+    let pending = match pending {
+        Some(pending) => pending,
+        None => return,
+    };
+    let saved = &pending.0;
+    if *app_state.current() != saved.state || paddles.iter_mut().count() != saved.rules.mode.players().len() {
+        return;
+    }
+
+    scoreboard.player1 = saved.scores[0];
+    scoreboard.player2 = saved.scores[1];
+    scoreboard.player3 = saved.scores[2];
+    scoreboard.player4 = saved.scores[3];
+    rally.0 = saved.rally;
+    *stats = saved.stats.clone();
+
+    for entity in balls.iter() {
+        commands.entity(entity).despawn();
+    }
+    for ball in saved.balls.iter() {
+        let position = Vec2::new(ball.position[0], ball.position[1]);
+        let velocity = Vec2::new(ball.velocity[0], ball.velocity[1]);
+        let entity = spawn_ball(&mut commands, &ball_material, position, velocity, ball.spin);
+        // Balls saved before the speed curve carry on from however fast they were going.
+        let serve_speed = if ball.serve_speed > 0.0 { ball.serve_speed } else { velocity.length() };
+        commands.entity(entity).insert(Ball { velocity, last_hit: ball.last_hit, spin: ball.spin, serve_speed, hits: ball.hits });
+    }
+
+    for (paddle, mut transform) in paddles.iter_mut() {
+        if let Some(saved_paddle) = saved.paddles.iter().find(|saved_paddle| saved_paddle.player == paddle.player) {
+            transform.translation.x = saved_paddle.position[0];
+            transform.translation.y = saved_paddle.position[1];
+        }
+    }
+
+    commands.remove_resource::<PendingResume>();
+}
+
+// A finished match has nothing left to resume.
+fn forget_saved_match(mut resumable: ResMut<ResumableMatch>) {
+    resumable.0 = None;
+    storage::remove(SAVE_FILE);
+}
@@ -1,27 +1,79 @@
 use bevy::prelude::*;
 
-use crate::{Player, game_state::AppState, ball::Ball, Server, LoadedAudio};
+use pong50::{scripting::ScriptPoint, simulation::VICTORY_SCORE};
 
-const VICTORY_SCORE: u32 = 2;
+use crate::{Player, arena::is_goal, game_state::AppState, ball::{Ball, Rally}, Server, LoadedAudio, rules::{GameRules, GameMode, Handicap}, scripts::{Scripts, hook_or}};
+
+const ELIMINATION_LIVES: u32 = 3;
 
 pub struct ScorePlugin;
 
+/// Points for every player. In elimination this counts the lives a player has left instead.
+#[derive(Default)]
 pub struct Scoreboard {
     pub player1: u32,
     pub player2: u32,
+    pub player3: u32,
+    pub player4: u32,
+    pub winner: Option<Player>,
+}
+
+impl Scoreboard {
+    pub fn score(&self, player: Player) -> u32 {
+        match player {
+            Player::Player1 => self.player1,
+            Player::Player2 => self.player2,
+            Player::Player3 => self.player3,
+            Player::Player4 => self.player4,
+        }
+    }
+
+    /// Every player's score, indexed by Player::index.
+    pub fn all(&self) -> [u32; 4] {
+        [self.player1, self.player2, self.player3, self.player4]
+    }
+
+    pub fn score_mut(&mut self, player: Player) -> &mut u32 {
+        match player {
+            Player::Player1 => &mut self.player1,
+            Player::Player2 => &mut self.player2,
+            Player::Player3 => &mut self.player3,
+            Player::Player4 => &mut self.player4,
+        }
+    }
 }
 
 struct ScoreText(Player);
 
+/// Sent every time a ball leaves the arena, whether or not anyone gets a point for it.
+pub struct PointScored {
+    /// None for own goals in the four player mode, and for every point in elimination and practice.
+    pub scorer: Option<Player>,
+}
+
+/// Sent once a match is decided. It keeps the handicaps that were in play next to the
+/// final score, so a handicapped win can be told apart from a fair one.
+#[derive(Debug, Clone)]
+pub struct MatchResult {
+    pub winner: Player,
+    pub player1: u32,
+    pub player2: u32,
+    pub player3: u32,
+    pub player4: u32,
+    pub mode: GameMode,
+    /// Indexed by Player::index.
+    pub handicaps: [Handicap; 4],
+}
+
 impl Plugin for ScorePlugin {
     fn build(&self, app: &mut AppBuilder) {
         app
-            .insert_resource(Scoreboard {
-                player1: 0,
-                player2: 0,
-            })
+            .insert_resource(Scoreboard::default())
+            .add_event::<MatchResult>()
+            .add_event::<PointScored>()
             .add_startup_system(setup.system())
             .add_system(update_scoreboard.system())
+            .add_system_set(SystemSet::on_exit(AppState::Start).with_system(apply_point_bonus.system()))
             .add_system_set(SystemSet::on_update(AppState::Play).with_system(scored.system()));
     }
 }
@@ -89,56 +141,189 @@ fn setup(
         ),
         ..Default::default()
     }).insert(ScoreText(Player::Player2));
+
+    // Player3 and Player4 Score Text, next to the top and bottom paddles. These stay empty
+    // unless we are playing the four player mode.
+    for (player, bottom) in [
+        (Player::Player3, window.height() - 140.0),
+        (Player::Player4, 60.0),
+    ].iter() {
+        commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(*bottom),
+                    left: Val::Px(window.width() / 2.0 - 20.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/font.ttf"),
+                    font_size: 60.0,
+                    color: Color::WHITE,
+                },
+                TextAlignment {
+                    horizontal: HorizontalAlign::Center,
+                    ..Default::default()
+                },
+            ),
+            ..Default::default()
+        }).insert(ScoreText(*player));
+    }
 }
 
 fn update_scoreboard(
     scoreboard: Res<Scoreboard>,
+    rules: Res<GameRules>,
     mut query: Query<(&mut Text, &ScoreText)>,
 ) {
     for (mut text, scoretext) in query.iter_mut() {
-        match scoretext.0 {
-            Player::Player1 => {
-                text.sections[0].value = scoreboard.player1.to_string();
-            }
-            Player::Player2 => {
-                text.sections[0].value = scoreboard.player2.to_string();
-            }
-        }
+        text.sections[0].value = if rules.mode.players().contains(&scoretext.0) {
+            scoreboard.score(scoretext.0).to_string()
+        } else {
+            String::new()
+        };
     }
 }
 
+// Bonus points from the handicaps are handed out as the match leaves the Start screen.
+// In elimination everybody gets their lives here as well, and the bonus counts as extra lives.
+// Playing alone, the score starts from nothing. A bonus that would already win the match is cut
+// down to one point short of it, so there is always something to play for.
+fn apply_point_bonus(
+    mut scoreboard: ResMut<Scoreboard>,
+    rules: Res<GameRules>,
+) {
+    let base = if rules.is_elimination() { ELIMINATION_LIVES } else { 0 };
+
+    for player in Player::ALL.iter() {
+        let bonus = match rules.handicap(*player).point_bonus {
+            _ if rules.mode.is_solo() => 0,
+            bonus if rules.is_elimination() => bonus,
+            bonus => bonus.min(VICTORY_SCORE - 1),
+        };
+        *scoreboard.score_mut(*player) = base + bonus;
+    }
+}
+
+// Every ball is scored on its own and removed from play. We only go back to Serve once
+// the last ball is gone, so in multi-ball the rally keeps going while any ball is left.
 fn scored(
+    mut commands: Commands,
     mut scoreboard: ResMut<Scoreboard>,
     mut app_state: ResMut<State<AppState>>,
     mut server: ResMut<Server>,
-    mut query: Query<&mut Transform, With<Ball>>,
+    query: Query<(Entity, &Transform, &Ball)>,
     windows: Res<Windows>,
     audio: Res<Audio>,
     loaded_audio: Res<LoadedAudio>,
+    rules: Res<GameRules>,
+    mut match_results: EventWriter<MatchResult>,
+    mut points: EventWriter<PointScored>,
+    rally: Res<Rally>,
+    scripts: Res<Scripts>,
 ) {
+    // Practice and squash have their own scoring, see practice::score_practice and squash::score_squash.
+    if rules.mode.is_solo() {
+        return;
+    }
     let window = windows.get_primary().unwrap();
+    let script = scripts.active(&rules);
+
+    let mut balls_left = 0;
+    let mut any_scored = false;
+    let mut winner = None;
 
-    for mut transform in query.iter_mut() {
-        if transform.translation.x > window.width() / 2.0 {
-            audio.play(loaded_audio.score.clone());
-            scoreboard.player1 += 1;
-            server.0 = Player::Player2;
-            if scoreboard.player1 >= VICTORY_SCORE {
-                transform.translation.x += window.width();
-                app_state.set(AppState::Done).unwrap();
-            } else {
-                app_state.set(AppState::Serve).unwrap();
+    for (entity, transform, ball) in query.iter() {
+        if winner.is_some() {
+            break;
+        }
+
+        // Work out whose goal the ball went into, if any. A ball past an edge that isn't a goal is
+        // still in play, ball::ball_collision bounces it back.
+        let position = transform.translation;
+        let past_edge = [
+            (Player::Player1, position.x < -window.width() / 2.0),
+            (Player::Player2, position.x > window.width() / 2.0),
+            (Player::Player3, position.y > window.height() / 2.0),
+            (Player::Player4, position.y < -window.height() / 2.0),
+        ];
+        let conceded = match past_edge.iter().find(|(player, past)| *past && is_goal(*player, &rules, &scoreboard)) {
+            Some((player, _)) => *player,
+            None => {
+                balls_left += 1;
+                continue;
+            }
+        };
+
+        audio.play(loaded_audio.score.clone());
+        any_scored = true;
+        commands.entity(entity).despawn();
+
+        // In two player modes the other player always gets the point. With four players
+        // it goes to whoever touched the ball last, and own goals don't count.
+        let scorer = match conceded {
+            _ if rules.is_elimination() => None,
+            _ if rules.mode == GameMode::FourPlayer => ball.last_hit.filter(|player| *player != conceded),
+            Player::Player1 => Some(Player::Player2),
+            Player::Player2 => Some(Player::Player1),
+            _ => None,
+        };
+
+        // The match script can change how much a point is worth and who serves next.
+        let point = ScriptPoint {
+            scorer: scorer.map(|player| player.index()),
+            conceded: conceded.index(),
+            rally: rally.0,
+            scores: scoreboard.all(),
+        };
+        let worth = hook_or(script.and_then(|script| script.points(&point)), 1);
+        let next_server = hook_or(script.and_then(|script| script.next_server(&point)), conceded.index());
+        server.0 = Player::ALL[next_server];
+
+        if rules.is_elimination() {
+            let lives = scoreboard.score_mut(conceded);
+            // A point always costs a life, otherwise a script returning 0 would make a match that never ends.
+            *lives = lives.saturating_sub(worth.max(1));
+
+            let alive: Vec<Player> = rules
+                .mode
+                .players()
+                .iter()
+                .copied()
+                .filter(|player| scoreboard.score(*player) > 0)
+                .collect();
+            if alive.len() == 1 {
+                winner = Some(alive[0]);
             }
-        } else if transform.translation.x < -window.width() / 2.0 {
-            audio.play(loaded_audio.score.clone());
-            scoreboard.player2 += 1;
-            server.0 = Player::Player1;
-            if scoreboard.player2 >= VICTORY_SCORE {
-                transform.translation.x += window.width();
-                app_state.set(AppState::Done).unwrap();
-            } else {
-                app_state.set(AppState::Serve).unwrap();
+        } else if let Some(scorer) = scorer {
+            let score = scoreboard.score_mut(scorer);
+            *score = score.saturating_add(worth);
+            if *score >= VICTORY_SCORE {
+                winner = Some(scorer);
             }
         }
+        points.send(PointScored { scorer });
+    }
+
+    if let Some(winner) = winner {
+        scoreboard.winner = Some(winner);
+        match_results.send(MatchResult {
+            winner,
+            player1: scoreboard.player1,
+            player2: scoreboard.player2,
+            player3: scoreboard.player3,
+            player4: scoreboard.player4,
+            mode: rules.mode,
+            handicaps: rules.handicaps,
+        });
+        app_state.set(AppState::Done).unwrap();
+    } else if any_scored && balls_left == 0 {
+        app_state.set(AppState::Serve).unwrap();
     }
 }
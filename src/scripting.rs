@@ -0,0 +1,196 @@
+// Rhai scripts for AI behaviour and rule tweaks. A script is a file of functions, and the game calls
+// the ones it finds by name. Every hook is optional, whatever a script leaves out works the usual way.
+//
+//     fn intent(me, balls, paddles, scores)   how to move a "Script AI" paddle, from -1.0 to 1.0
+//     fn bounce(ball, paddle)                 the ball as it leaves a paddle, return it with a new vx and vy
+//     fn serve(serve)                         a ball being served, return it with a new vx and vy
+//     fn points(point)                        how many points the scorer gets, or lives taken in elimination
+//     fn next_server(point)                   which player serves after a point
+//
+// Balls are maps of x, y, vx, vy and last_hit, paddles are maps of player, x, y, width and height,
+// scores is an array with one entry per player and players are numbered 0 to 3: left, right, top and
+// bottom. A point is a map of scorer, conceded, rally and scores, with -1 for nobody, and a serve is
+// a map of server, ball, vx, vy and scores.
+//
+// Scripts only ever see copies of the match, so the only way they can change it is through what the
+// hooks return. They can't import modules or eval strings, and every call is cut off once it has run
+// for too long or used too much memory, so a broken script can't hang the game.
+
+use std::sync::Arc;
+
+use rhai::{module_resolvers::DummyModuleResolver, Array, CallFnOptions, Dynamic, Engine, FuncArgs, Map, Scope, AST, INT};
+
+// Limits for a single call into a script. A hook runs many times a second, so these are tight.
+const MAX_OPERATIONS: u64 = 100_000;
+const MAX_CALL_LEVELS: usize = 32;
+const MAX_EXPRESSION_DEPTH: usize = 64;
+const MAX_STRING_SIZE: usize = 4096;
+const MAX_ARRAY_SIZE: usize = 1024;
+const MAX_MAP_SIZE: usize = 256;
+
+/// An engine that scripts can do as little harm with as possible.
+pub fn sandboxed_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_module_resolver(DummyModuleResolver::new())
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .set_max_expr_depths(MAX_EXPRESSION_DEPTH, MAX_EXPRESSION_DEPTH)
+        .set_max_string_size(MAX_STRING_SIZE)
+        .set_max_array_size(MAX_ARRAY_SIZE)
+        .set_max_map_size(MAX_MAP_SIZE)
+        .disable_symbol("eval");
+    engine
+}
+
+/// A ball, as scripts see it.
+#[derive(Debug, Clone)]
+pub struct ScriptBall {
+    pub position: [f32; 2],
+    pub velocity: [f32; 2],
+    pub last_hit: Option<usize>,
+}
+
+/// A paddle, as scripts see it.
+#[derive(Debug, Clone)]
+pub struct ScriptPaddle {
+    pub player: usize,
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+}
+
+/// A ball that just left the arena.
+#[derive(Debug, Clone)]
+pub struct ScriptPoint {
+    /// Who gets the point. Nobody for own goals in the four player mode, and for every point in elimination.
+    pub scorer: Option<usize>,
+    pub conceded: usize,
+    /// Paddle hits since the last serve.
+    pub rally: u32,
+    pub scores: [u32; 4],
+}
+
+/// A script that compiled, ready to have its hooks called.
+pub struct Script {
+    /// The file name, without the extension.
+    pub name: String,
+    engine: Arc<Engine>,
+    ast: AST,
+}
+
+impl Script {
+    pub fn compile(engine: &Arc<Engine>, name: &str, source: &str) -> Result<Script, String> {
+        let ast = engine.compile(source).map_err(|error| error.to_string())?;
+        Ok(Script { name: name.to_string(), engine: engine.clone(), ast })
+    }
+
+    pub fn has_hook(&self, hook: &str) -> bool {
+        self.ast.iter_functions().any(|function| function.name == hook)
+    }
+
+    // None when the script doesn't have the hook. Calls start from an empty scope and skip the top
+    // level of the script, so nothing carries over from one call to the next.
+    fn call(&self, hook: &str, args: impl FuncArgs) -> Option<Result<Dynamic, String>> {
+        if !self.has_hook(hook) {
+            return None;
+        }
+        let options = CallFnOptions::new().eval_ast(false);
+        Some(
+            self.engine
+                .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, hook, args)
+                .map_err(|error| format!("{} in {}: {}", hook, self.name, error)),
+        )
+    }
+
+    pub fn intent(&self, me: &ScriptPaddle, balls: &[ScriptBall], paddles: &[ScriptPaddle], scores: [u32; 4]) -> Option<Result<f32, String>> {
+        let balls: Array = balls.iter().map(ball_map).collect();
+        let paddles: Array = paddles.iter().map(paddle_map).collect();
+        self.call("intent", (paddle_map(me), balls, paddles, scores_array(scores)))
+            .map(|result| result.and_then(|value| number(&value).ok_or_else(|| format!("intent in {} should return a number", self.name))))
+    }
+
+    /// The new velocity for a ball coming off a paddle.
+    pub fn bounce(&self, ball: &ScriptBall, paddle: &ScriptPaddle) -> Option<Result<[f32; 2], String>> {
+        self.call("bounce", (ball_map(ball), paddle_map(paddle))).map(|result| result.and_then(|value| self.velocity("bounce", value)))
+    }
+
+    /// The velocity for the ball'th ball of a serve.
+    pub fn serve(&self, server: usize, ball: usize, velocity: [f32; 2], scores: [u32; 4]) -> Option<Result<[f32; 2], String>> {
+        let mut serve = Map::new();
+        serve.insert("server".into(), (server as INT).into());
+        serve.insert("ball".into(), (ball as INT).into());
+        serve.insert("vx".into(), velocity[0].into());
+        serve.insert("vy".into(), velocity[1].into());
+        serve.insert("scores".into(), scores_array(scores).into());
+        self.call("serve", (serve,)).map(|result| result.and_then(|value| self.velocity("serve", value)))
+    }
+
+    pub fn points(&self, point: &ScriptPoint) -> Option<Result<u32, String>> {
+        self.call("points", (point_map(point),)).map(|result| {
+            result.and_then(|value| {
+                let points = value.as_int().ok().and_then(|points| u32::try_from(points).ok());
+                points.ok_or_else(|| format!("points in {} should return a whole number from 0 to {}", self.name, u32::MAX))
+            })
+        })
+    }
+
+    pub fn next_server(&self, point: &ScriptPoint) -> Option<Result<usize, String>> {
+        self.call("next_server", (point_map(point),)).map(|result| {
+            result.and_then(|value| match value.as_int() {
+                Ok(player) if (0..4).contains(&player) => Ok(player as usize),
+                _ => Err(format!("next_server in {} should return a player from 0 to 3", self.name)),
+            })
+        })
+    }
+
+    // Hooks that change a velocity hand back the map they were given, with vx and vy changed.
+    fn velocity(&self, hook: &str, value: Dynamic) -> Result<[f32; 2], String> {
+        let map = value.try_cast::<Map>().ok_or_else(|| format!("{} in {} should return the map it was given", hook, self.name))?;
+        match (map.get("vx").and_then(number), map.get("vy").and_then(number)) {
+            (Some(vx), Some(vy)) if vx.is_finite() && vy.is_finite() => Ok([vx, vy]),
+            _ => Err(format!("{} in {} should return a map with numbers for vx and vy", hook, self.name)),
+        }
+    }
+}
+
+// Scripts can write 1 or 1.0 and mean the same thing.
+fn number(value: &Dynamic) -> Option<f32> {
+    value.as_float().ok().or_else(|| value.as_int().ok().map(|value| value as f32))
+}
+
+fn player_value(player: Option<usize>) -> Dynamic {
+    player.map_or(-1, |player| player as INT).into()
+}
+
+fn scores_array(scores: [u32; 4]) -> Array {
+    scores.iter().map(|score| (*score as INT).into()).collect()
+}
+
+fn ball_map(ball: &ScriptBall) -> Dynamic {
+    let mut map = Map::new();
+    map.insert("x".into(), ball.position[0].into());
+    map.insert("y".into(), ball.position[1].into());
+    map.insert("vx".into(), ball.velocity[0].into());
+    map.insert("vy".into(), ball.velocity[1].into());
+    map.insert("last_hit".into(), player_value(ball.last_hit));
+    map.into()
+}
+
+fn paddle_map(paddle: &ScriptPaddle) -> Dynamic {
+    let mut map = Map::new();
+    map.insert("player".into(), (paddle.player as INT).into());
+    map.insert("x".into(), paddle.position[0].into());
+    map.insert("y".into(), paddle.position[1].into());
+    map.insert("width".into(), paddle.size[0].into());
+    map.insert("height".into(), paddle.size[1].into());
+    map.into()
+}
+
+fn point_map(point: &ScriptPoint) -> Map {
+    let mut map = Map::new();
+    map.insert("scorer".into(), player_value(point.scorer));
+    map.insert("conceded".into(), (point.conceded as INT).into());
+    map.insert("rally".into(), (point.rally as INT).into());
+    map.insert("scores".into(), scores_array(point.scores).into());
+    map
+}
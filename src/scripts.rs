@@ -0,0 +1,87 @@
+use std::{fs, path::Path, sync::Arc};
+
+use bevy::prelude::*;
+use pong50::scripting::{sandboxed_engine, Script, ScriptBall, ScriptPaddle};
+
+use crate::{Player, rules::GameRules};
+
+const SCRIPT_DIRECTORY: &str = "assets/scripts";
+
+pub struct ScriptsPlugin;
+
+/// Every script that can be picked from the menu, from the .rhai files in assets/scripts sorted by file name.
+/// The one picked for the match is in GameRules::script.
+pub struct Scripts(pub Vec<Arc<Script>>);
+
+impl Scripts {
+    pub fn active(&self, rules: &GameRules) -> Option<&Arc<Script>> {
+        rules.script.and_then(|index| self.0.get(index))
+    }
+}
+
+impl Plugin for ScriptsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(load_scripts(Path::new(SCRIPT_DIRECTORY)));
+    }
+}
+
+// All the scripts share one engine. A script that doesn't compile is left out, the same way a broken
+// arena is.
+fn load_scripts(directory: &Path) -> Scripts {
+    let engine = Arc::new(sandboxed_engine());
+
+    let mut paths: Vec<_> = match fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "rhai"))
+            .collect(),
+        Err(error) => {
+            warn!("Could not read scripts from {:?}: {}", directory, error);
+            Vec::new()
+        }
+    };
+    paths.sort();
+
+    let mut scripts = Vec::new();
+    for path in paths {
+        let name = path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        let script = fs::read_to_string(&path)
+            .map_err(|error| error.to_string())
+            .and_then(|source| Script::compile(&engine, &name, &source));
+
+        match script {
+            Ok(script) => scripts.push(Arc::new(script)),
+            Err(error) => warn!("Skipping script {:?}: {}", path, error),
+        }
+    }
+
+    Scripts(scripts)
+}
+
+/// What a hook said, or `default` when there is no script, it doesn't have that hook, or the hook failed.
+pub fn hook_or<T>(result: Option<Result<T, String>>, default: T) -> T {
+    match result {
+        Some(Ok(value)) => value,
+        Some(Err(error)) => {
+            warn!("Script failed, {}", error);
+            default
+        }
+        None => default,
+    }
+}
+
+pub fn script_ball(position: Vec2, velocity: Vec2, last_hit: Option<Player>) -> ScriptBall {
+    ScriptBall {
+        position: position.into(),
+        velocity: velocity.into(),
+        last_hit: last_hit.map(|player| player.index()),
+    }
+}
+
+pub fn script_paddle(player: Player, position: Vec2, size: Vec2) -> ScriptPaddle {
+    ScriptPaddle {
+        player: player.index(),
+        position: position.into(),
+        size: size.into(),
+    }
+}
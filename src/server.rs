@@ -0,0 +1,397 @@
+use std::{io::{self, Write}, net::{SocketAddr, TcpListener, TcpStream, UdpSocket}, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use bevy::log::{info, warn};
+
+use crate::{discovery::{DiscoveryResponder, GameAnnouncement}, protocol::{ControlMessage, InputPacket, LineReader, LobbyState, MatchPhase, Snapshot}, simulation::{PaddleInput, Phase, SimulationState, TICK_SECONDS}};
+
+// A dedicated server owns the match. Clients only send their paddle input and draw whatever the
+// server tells them, so nobody has to trust anybody elses simulation.
+//
+// Everyone who connects lands in the lobby first. Two of them take a side, agree on the rules and
+// say they are ready, then the match starts for everybody at once. When it is over we go back to
+// the lobby. Whatever goes wrong with one client only costs that client its connection, the server
+// keeps going for everybody else.
+
+pub const DEFAULT_PORT: u16 = 7100;
+
+/// How the server was started, read from the command line.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// The TCP and UDP sockets both use this address.
+    pub bind: SocketAddr,
+    pub seed: u64,
+    /// What the game shows up as when people look for games on the network.
+    pub name: String,
+}
+
+impl ServerConfig {
+    /// `pong50-server [--bind 0.0.0.0:7100] [--seed 0] [--name Pong50]`
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<ServerConfig, String> {
+        let mut config = ServerConfig {
+            bind: SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT)),
+            seed: 0,
+            name: "Pong50".to_string(),
+        };
+
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+            match arg.as_str() {
+                "--bind" => config.bind = parse(&value("--bind")?)?,
+                "--seed" => config.seed = parse(&value("--seed")?)?,
+                "--name" => config.name = value("--name")?,
+                other => return Err(format!("unknown argument {}", other)),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("could not understand {:?}", value))
+}
+
+struct Client {
+    token: u32,
+    stream: TcpStream,
+    reader: LineReader,
+    /// Whether the client has asked to join yet. Until it has we don't send it anything.
+    joined: bool,
+    name: String,
+    /// We learn this from the first input packet the client sends.
+    udp_addr: Option<SocketAddr>,
+    input: PaddleInput,
+    sequence: u32,
+}
+
+pub struct DedicatedServer {
+    listener: TcpListener,
+    socket: UdpSocket,
+    /// None when some other server on this machine already answers discovery.
+    discovery: Option<DiscoveryResponder>,
+    name: String,
+    clients: Vec<Client>,
+    lobby: LobbyState,
+    in_match: bool,
+    matches_played: u64,
+    state: SimulationState,
+    seed: u64,
+    tick: u32,
+    next_token: u32,
+    phase: MatchPhase,
+}
+
+impl DedicatedServer {
+    pub fn bind(config: &ServerConfig) -> io::Result<Self> {
+        let listener = TcpListener::bind(config.bind)?;
+        // Binding to port 0 picks a free port, so make sure UDP ends up on the same one as TCP.
+        let socket = UdpSocket::bind(listener.local_addr()?)?;
+        listener.set_nonblocking(true)?;
+        socket.set_nonblocking(true)?;
+
+        // Tokens only need to differ between runs, so a stale client can't talk to a new server.
+        let next_token = SystemTime::now().duration_since(UNIX_EPOCH).map_or(1, |time| time.subsec_nanos());
+
+        let discovery = match DiscoveryResponder::bind() {
+            Ok(discovery) => Some(discovery),
+            Err(error) => {
+                warn!("Not answering LAN discovery: {}", error);
+                None
+            }
+        };
+
+        Ok(DedicatedServer {
+            listener,
+            socket,
+            discovery,
+            name: config.name.clone(),
+            clients: Vec::new(),
+            lobby: LobbyState::default(),
+            in_match: false,
+            matches_played: 0,
+            state: SimulationState::new(config.seed),
+            seed: config.seed,
+            tick: 0,
+            next_token,
+            phase: MatchPhase::Waiting,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Ticks at the simulation rate for as long as the program runs.
+    pub fn run(&mut self) -> ! {
+        let tick_length = Duration::from_secs_f32(TICK_SECONDS);
+        let mut next_tick = Instant::now();
+        loop {
+            self.tick();
+
+            next_tick += tick_length;
+            let now = Instant::now();
+            if next_tick > now {
+                thread::sleep(next_tick - now);
+            } else {
+                // We fell behind, don't try to catch up all at once.
+                next_tick = now;
+            }
+        }
+    }
+
+    /// Handles connections and input, advances the match by one tick and sends everyone a snapshot.
+    pub fn tick(&mut self) {
+        self.accept_clients();
+        self.read_control_messages();
+        self.receive_inputs();
+        self.answer_discovery();
+
+        let phase = if self.in_match {
+            let was_done = self.state.phase == Phase::Done;
+            let inputs = [self.input_for(0), self.input_for(1)];
+            self.state.step(inputs);
+            // The simulation starts another match on its own, but we would rather go back to the lobby.
+            if was_done && self.state.phase != Phase::Done {
+                self.end_match();
+                MatchPhase::Waiting
+            } else {
+                MatchPhase::from(self.state.phase)
+            }
+        } else {
+            MatchPhase::Waiting
+        };
+        self.set_phase(phase);
+        self.tick += 1;
+
+        self.broadcast();
+    }
+
+    fn accept_clients(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    if let Err(error) = stream.set_nonblocking(true).and_then(|_| stream.set_nodelay(true)) {
+                        warn!("Dropped {}: {}", address, error);
+                        continue;
+                    }
+                    info!("{} connected", address);
+                    self.next_token = self.next_token.wrapping_add(1);
+                    self.clients.push(Client {
+                        token: self.next_token,
+                        stream,
+                        reader: LineReader::default(),
+                        joined: false,
+                        name: String::new(),
+                        udp_addr: None,
+                        input: 0,
+                        sequence: 0,
+                    });
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return,
+                // Someone giving up on connecting halfway is their problem, we try again next tick.
+                Err(error) => {
+                    warn!("Could not accept a connection: {}", error);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn read_control_messages(&mut self) {
+        let mut index = 0;
+        while index < self.clients.len() {
+            let client = &mut self.clients[index];
+            match client.reader.read_lines(&mut client.stream) {
+                Ok(lines) => {
+                    let token = client.token;
+                    for message in lines.iter().filter_map(|line| ControlMessage::parse(line)) {
+                        self.handle_message(index, token, message);
+                    }
+                    index += 1;
+                }
+                Err(_) => self.disconnect(index),
+            }
+        }
+
+        if !self.in_match && self.lobby.all_ready() {
+            self.start_match();
+        }
+    }
+
+    fn handle_message(&mut self, index: usize, token: u32, message: ControlMessage) {
+        let seat = self.lobby.seat_of(token);
+        match message {
+            ControlMessage::Join { name } if !self.clients[index].joined => {
+                let client = &mut self.clients[index];
+                client.joined = true;
+                client.name = if name.is_empty() { "Player".to_string() } else { name };
+                info!("{} joined", client.name);
+
+                let mut lines = ControlMessage::Welcome { token }.to_line();
+                lines.push_str(&ControlMessage::Lobby(self.lobby.clone()).to_line());
+                // Anyone turning up during a match gets to watch it.
+                if self.in_match {
+                    lines.push_str(&ControlMessage::Start.to_line());
+                }
+                // If this fails the read on the next tick notices the connection is gone.
+                let _ = client.stream.write_all(lines.as_bytes());
+                return;
+            }
+            // Nobody gets to shuffle the lobby around while a match is on.
+            _ if self.in_match || !self.clients[index].joined => return,
+            ControlMessage::Side(Some(side)) if self.lobby.seats[side].is_none() => {
+                if let Some(seat) = seat {
+                    self.lobby.leave(seat);
+                }
+                let name = self.clients[index].name.clone();
+                info!("{} sat down as Player {}", name, side + 1);
+                self.lobby.sit(side, token, &name);
+            }
+            ControlMessage::Side(None) => match seat {
+                Some(seat) => self.lobby.leave(seat),
+                None => return,
+            },
+            // Everyone has to agree to the new rules again.
+            ControlMessage::Rules(rules) if seat.is_some() && rules.is_offered() && rules != self.lobby.rules => {
+                self.lobby.rules = rules;
+                self.lobby.ready = [false, false];
+            }
+            ControlMessage::Ready(ready) => match seat {
+                Some(seat) => self.lobby.ready[seat] = ready,
+                None => return,
+            },
+            _ => return,
+        }
+        self.send_all(&ControlMessage::Lobby(self.lobby.clone()));
+    }
+
+    fn start_match(&mut self) {
+        self.matches_played += 1;
+        // A different seed every match, so the serves aren't the same every time.
+        self.state = SimulationState::with_rules(self.seed.wrapping_add(self.matches_played), self.lobby.rules);
+        self.in_match = true;
+        info!(
+            "{} and {} are playing to {} points",
+            self.lobby.names[0],
+            self.lobby.names[1],
+            self.lobby.rules.victory_score
+        );
+        self.send_all(&ControlMessage::Start);
+    }
+
+    fn end_match(&mut self) {
+        self.in_match = false;
+        self.lobby.ready = [false, false];
+        self.send_all(&ControlMessage::Lobby(self.lobby.clone()));
+    }
+
+    fn disconnect(&mut self, index: usize) {
+        let client = self.clients.remove(index);
+        if let Some(seat) = self.lobby.seat_of(client.token) {
+            info!("{} left", client.name);
+            self.lobby.leave(seat);
+            if self.in_match {
+                self.end_match();
+            } else {
+                self.send_all(&ControlMessage::Lobby(self.lobby.clone()));
+            }
+        }
+    }
+
+    fn send_all(&mut self, message: &ControlMessage) {
+        let line = message.to_line();
+        for client in self.clients.iter_mut().filter(|client| client.joined) {
+            // Anyone this fails for is noticed as gone on the next read.
+            let _ = client.stream.write_all(line.as_bytes());
+        }
+    }
+
+    fn answer_discovery(&self) {
+        if let Some(discovery) = &self.discovery {
+            let announcement = GameAnnouncement {
+                name: self.name.clone(),
+                port: self.socket.local_addr().map_or(0, |address| address.port()),
+                players: self.lobby.seats.iter().filter(|seat| seat.is_some()).count(),
+            };
+            if let Err(error) = discovery.answer(&announcement) {
+                warn!("LAN discovery failed: {}", error);
+            }
+        }
+    }
+
+    fn receive_inputs(&mut self) {
+        let mut buffer = [0u8; 64];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((length, address)) => {
+                    let packet = match InputPacket::decode(&buffer[..length]) {
+                        Some(packet) => packet,
+                        None => continue,
+                    };
+                    let client = match self.clients.iter_mut().find(|client| client.token == packet.token) {
+                        Some(client) => client,
+                        None => continue,
+                    };
+                    // The first packet is always taken, after that anything older than what we have is dropped.
+                    if client.udp_addr.is_some() && packet.sequence <= client.sequence {
+                        continue;
+                    }
+                    client.udp_addr = Some(address);
+                    client.sequence = packet.sequence;
+                    client.input = packet.input;
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return,
+                // On some platforms a client that went away shows up as an error here, which isn't our problem.
+                Err(error) if error.kind() == io::ErrorKind::ConnectionReset => continue,
+                // Whatever is left gets another go on the next tick.
+                Err(error) => {
+                    warn!("Could not read inputs: {}", error);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn input_for(&self, player: usize) -> PaddleInput {
+        let token = self.lobby.seats[player];
+        self.clients.iter().find(|client| Some(client.token) == token).map_or(0, |client| client.input)
+    }
+
+    fn set_phase(&mut self, phase: MatchPhase) {
+        if phase == self.phase {
+            return;
+        }
+        self.phase = phase;
+        match (phase, self.state.winner) {
+            (MatchPhase::Done, Some(winner)) => info!(
+                "{} wins {} to {}",
+                self.lobby.names[winner],
+                self.state.scores[winner],
+                self.state.scores[1 - winner]
+            ),
+            (MatchPhase::Serve, _) if self.state.phase == Phase::Serve => {
+                info!("Serving, the score is {} to {}", self.state.scores[0], self.state.scores[1])
+            }
+            _ => (),
+        }
+    }
+
+    fn broadcast(&mut self) {
+        let bytes = Snapshot::from_state(self.tick, self.phase, &self.state).encode();
+        let mut index = 0;
+        while index < self.clients.len() {
+            let sent = match self.clients[index].udp_addr {
+                Some(address) => self.socket.send_to(&bytes, address).map(|_| ()),
+                None => Ok(()),
+            };
+            match sent {
+                Err(error) if error.kind() != io::ErrorKind::WouldBlock && error.kind() != io::ErrorKind::ConnectionRefused => {
+                    warn!("Dropped {}: {}", self.clients[index].name, error);
+                    self.disconnect(index);
+                }
+                _ => index += 1,
+            }
+        }
+    }
+}
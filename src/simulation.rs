@@ -0,0 +1,319 @@
+// A fixed step version of a two player Classic match that doesn't touch Bevy at all. Everything in here
+// only depends on the inputs it is given and its own random number generator, so two machines that feed
+// it the same inputs end up with exactly the same state. That is what online play builds on: the state
+// can be copied, rolled back and checked with a checksum.
+//
+// The sizes and speeds of the ball, the paddles and the window live here, and the local game uses the
+// same ones, so a Classic match plays the same everywhere.
+
+pub const TICKS_PER_SECOND: u32 = 60;
+pub const TICK_SECONDS: f32 = 1.0 / TICKS_PER_SECOND as f32;
+
+pub const ARENA_WIDTH: f32 = 1080.0;
+pub const ARENA_HEIGHT: f32 = 720.0;
+pub const PADDLE_X: f32 = ARENA_WIDTH / 2.0 - 20.0;
+
+pub const PADDLE_SPEED: f32 = 500.0;
+pub const PADDLE_WIDTH: f32 = 20.0;
+pub const PADDLE_HEIGHT: f32 = 100.0;
+pub const BALL_SIZE: f32 = 15.0;
+pub const BALL_INITIAL_X_MIN: f32 = 140.0;
+pub const BALL_INITIAL_X_MAX: f32 = 200.0;
+pub const BALL_INITIAL_Y_MIN: f32 = -50.0;
+pub const BALL_INITIAL_Y_MAX: f32 = 50.0;
+const BOUNCE_VELOCITY_INCREASE: f32 = 1.1;
+pub const VICTORY_SCORE: u32 = 2;
+
+// How long we wait before the ball is served, and before a finished match starts over.
+const SERVE_TICKS: u32 = TICKS_PER_SECOND;
+const DONE_TICKS: u32 = 3 * TICKS_PER_SECOND;
+
+/// The parts of a match the players agree on in the lobby before it starts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatchRules {
+    pub victory_score: u32,
+    /// How much faster the ball gets every time a paddle hits it.
+    pub speed_up: f32,
+}
+
+/// The local match options the simulation doesn't have, for the screens that set up a networked match.
+pub const LOCAL_ONLY_RULES: &str = "Handicaps, arenas, spin, speed curves and momentum are local only";
+
+pub const VICTORY_SCORE_OPTIONS: [u32; 5] = [2, 3, 5, 7, 11];
+pub const SPEED_UP_OPTIONS: [f32; 4] = [1.0, 1.05, 1.1, 1.2];
+
+impl MatchRules {
+    /// Whether these are rules the lobby offers. Anything else, like a victory score of 0 or a speed up
+    /// that isn't a number, would leave a match that can't be played.
+    pub fn is_offered(&self) -> bool {
+        VICTORY_SCORE_OPTIONS.contains(&self.victory_score) && SPEED_UP_OPTIONS.contains(&self.speed_up)
+    }
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        MatchRules {
+            victory_score: VICTORY_SCORE,
+            speed_up: BOUNCE_VELOCITY_INCREASE,
+        }
+    }
+}
+
+/// What a player wants their paddle to do this tick: 1 is up, -1 is down and 0 is standing still.
+pub type PaddleInput = i8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Serve,
+    Play,
+    Done,
+}
+
+/// Things that happened during a tick, so whoever is running the simulation can play sounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulationEvent {
+    PaddleHit,
+    WallHit,
+    /// The index of the player that scored.
+    Scored(usize),
+    /// The index of the player that won.
+    MatchOver(usize),
+}
+
+/// The whole match. Players are indexed with 0 for Player1 on the left and 1 for Player2 on the right.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationState {
+    pub frame: u32,
+    pub phase: Phase,
+    pub phase_ticks: u32,
+    pub ball_position: [f32; 2],
+    pub ball_velocity: [f32; 2],
+    /// The y position of each paddle.
+    pub paddles: [f32; 2],
+    pub scores: [u32; 2],
+    /// Paddle hits since the last serve.
+    pub rally: u32,
+    pub server: usize,
+    pub winner: Option<usize>,
+    pub rules: MatchRules,
+    rng: u64,
+}
+
+impl SimulationState {
+    pub fn new(seed: u64) -> Self {
+        SimulationState::with_rules(seed, MatchRules::default())
+    }
+
+    pub fn with_rules(seed: u64, rules: MatchRules) -> Self {
+        let mut state = SimulationState {
+            frame: 0,
+            phase: Phase::Serve,
+            phase_ticks: 0,
+            ball_position: [0.0, 0.0],
+            ball_velocity: [0.0, 0.0],
+            paddles: [0.0, 0.0],
+            scores: [0, 0],
+            rally: 0,
+            server: 0,
+            winner: None,
+            rules,
+            // xorshift gets stuck on 0, so make sure we never start there.
+            rng: seed ^ 0x9E37_79B9_7F4A_7C15 | 1,
+        };
+        state.serve();
+        state
+    }
+
+    /// Advances the match by one tick.
+    pub fn step(&mut self, inputs: [PaddleInput; 2]) -> Vec<SimulationEvent> {
+        let mut events = Vec::new();
+        self.frame += 1;
+        self.phase_ticks += 1;
+
+        // Paddles can always move, even while we wait for the serve.
+        let max_height = ARENA_HEIGHT / 2.0 - PADDLE_HEIGHT / 2.0;
+        for (paddle, input) in self.paddles.iter_mut().zip(inputs.iter()) {
+            let direction = (*input).clamp(-1, 1) as f32;
+            *paddle = (*paddle + direction * PADDLE_SPEED * TICK_SECONDS).max(-max_height).min(max_height);
+        }
+
+        match self.phase {
+            Phase::Serve => {
+                if self.phase_ticks >= SERVE_TICKS {
+                    self.set_phase(Phase::Play);
+                }
+            }
+            Phase::Play => self.play(&mut events),
+            Phase::Done => {
+                if self.phase_ticks >= DONE_TICKS {
+                    self.scores = [0, 0];
+                    self.winner = None;
+                    self.serve();
+                }
+            }
+        }
+
+        events
+    }
+
+    fn play(&mut self, events: &mut Vec<SimulationEvent>) {
+        self.ball_position[0] += self.ball_velocity[0] * TICK_SECONDS;
+        self.ball_position[1] += self.ball_velocity[1] * TICK_SECONDS;
+
+        // Walls. We only bounce when the ball is still heading into the wall.
+        let wall = ARENA_HEIGHT / 2.0 - BALL_SIZE / 2.0;
+        if (self.ball_position[1] > wall && self.ball_velocity[1] > 0.0)
+            || (self.ball_position[1] < -wall && self.ball_velocity[1] < 0.0)
+        {
+            self.ball_velocity[1] = -self.ball_velocity[1];
+            events.push(SimulationEvent::WallHit);
+        }
+
+        // Paddles
+        for player in 0..2 {
+            let paddle_x = if player == 0 { -PADDLE_X } else { PADDLE_X };
+            let heading_in = if player == 0 { self.ball_velocity[0] < 0.0 } else { self.ball_velocity[0] > 0.0 };
+            let overlaps = (self.ball_position[0] - paddle_x).abs() < (BALL_SIZE + PADDLE_WIDTH) / 2.0
+                && (self.ball_position[1] - self.paddles[player]).abs() < (BALL_SIZE + PADDLE_HEIGHT) / 2.0;
+
+            if heading_in && overlaps {
+                self.ball_velocity[0] = self.rules.speed_up * -self.ball_velocity[0];
+                let ball_y = self.random_range(BALL_INITIAL_Y_MIN, BALL_INITIAL_Y_MAX);
+                self.ball_velocity[1] = if self.ball_velocity[1] > 0.0 { ball_y } else { -ball_y };
+                self.rally += 1;
+                events.push(SimulationEvent::PaddleHit);
+            }
+        }
+
+        // Goals
+        let scorer = if self.ball_position[0] > ARENA_WIDTH / 2.0 {
+            0
+        } else if self.ball_position[0] < -ARENA_WIDTH / 2.0 {
+            1
+        } else {
+            return;
+        };
+
+        self.scores[scorer] += 1;
+        self.server = 1 - scorer;
+        events.push(SimulationEvent::Scored(scorer));
+
+        if self.scores[scorer] >= self.rules.victory_score {
+            self.winner = Some(scorer);
+            self.set_phase(Phase::Done);
+            events.push(SimulationEvent::MatchOver(scorer));
+        } else {
+            self.serve();
+        }
+    }
+
+    fn serve(&mut self) {
+        let serve = if self.server == 0 { 1.0 } else { -1.0 };
+        self.ball_position = [0.0, 0.0];
+        self.rally = 0;
+        self.ball_velocity = [
+            serve * self.random_range(BALL_INITIAL_X_MIN, BALL_INITIAL_X_MAX),
+            self.random_range(BALL_INITIAL_Y_MIN, BALL_INITIAL_Y_MAX),
+        ];
+        self.set_phase(Phase::Serve);
+    }
+
+    fn set_phase(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.phase_ticks = 0;
+    }
+
+    // xorshift64*, which is tiny and gives the same numbers on every machine.
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn random_range(&mut self, min: f32, max: f32) -> f32 {
+        let unit = (self.next_random() >> 40) as f32 / (1u64 << 24) as f32;
+        min + unit * (max - min)
+    }
+
+    /// A hash of everything in the state. Two peers with the same checksum for a frame agree on the match.
+    pub fn checksum(&self) -> u64 {
+        // FNV-1a, so the result doesn't depend on the standard library's hasher.
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut write = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u64;
+                hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+            }
+        };
+
+        write(&self.frame.to_le_bytes());
+        write(&[self.phase as u8, self.server as u8, self.winner.map_or(u8::MAX, |winner| winner as u8)]);
+        write(&self.phase_ticks.to_le_bytes());
+        for value in self.ball_position.iter().chain(self.ball_velocity.iter()).chain(self.paddles.iter()) {
+            write(&value.to_bits().to_le_bytes());
+        }
+        for value in self.scores.iter().chain(std::iter::once(&self.rally)) {
+            write(&value.to_le_bytes());
+        }
+        write(&self.rules.victory_score.to_le_bytes());
+        write(&self.rules.speed_up.to_bits().to_le_bytes());
+        write(&self.rng.to_le_bytes());
+
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Something that moves both paddles around and changes often enough to hit and miss the ball.
+    fn input(frame: u32, player: usize) -> PaddleInput {
+        ((frame / (13 + 7 * player as u32)) % 3) as PaddleInput - 1
+    }
+
+    fn play(seed: u64, frames: u32) -> SimulationState {
+        let mut state = SimulationState::new(seed);
+        for frame in 0..frames {
+            state.step([input(frame, 0), input(frame, 1)]);
+        }
+        state
+    }
+
+    #[test]
+    fn same_seed_and_inputs_give_the_same_match() {
+        let first = play(7, 2000);
+        let second = play(7, 2000);
+
+        assert_eq!(first, second);
+        assert_eq!(first.checksum(), second.checksum());
+        assert_eq!(first.frame, 2000);
+    }
+
+    #[test]
+    fn different_seeds_serve_differently() {
+        assert_ne!(play(1, 0).ball_velocity, play(2, 0).ball_velocity);
+    }
+
+    #[test]
+    fn checksum_only_depends_on_the_state() {
+        let state = play(3, 500);
+        assert_eq!(state.checksum(), state.clone().checksum());
+        assert_eq!(state.checksum(), state.checksum());
+
+        let mut stepped = state.clone();
+        stepped.step([0, 0]);
+        assert_ne!(state.checksum(), stepped.checksum());
+
+        let mut moved = state.clone();
+        moved.paddles[0] += 1.0;
+        assert_ne!(state.checksum(), moved.checksum());
+    }
+
+    #[test]
+    fn checksum_is_the_same_on_every_machine() {
+        // Peers on different platforms compare these, so it must not change between builds.
+        assert_eq!(SimulationState::new(0).checksum(), 2031343477112637038);
+    }
+}
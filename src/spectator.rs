@@ -0,0 +1,122 @@
+use bevy::prelude::*;
+
+use crate::{ball::{Ball, Rally}, game_state::AppState, menu::player_label, rules::GameRules, score::Scoreboard};
+
+pub struct SpectatorPlugin;
+
+/// What the spectator HUD shows, filled in by whoever is running the match. None hides the HUD.
+#[derive(Default)]
+pub struct SpectatorView(pub Option<SpectatorInfo>);
+
+pub struct SpectatorInfo {
+    /// Each player's name and score.
+    pub players: Vec<(String, u32)>,
+    pub rally: u32,
+    pub ball_speed: f32,
+}
+
+struct SpectatorHudText;
+
+impl Plugin for SpectatorPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<SpectatorView>()
+            .add_startup_system(setup.system())
+            .add_system(local_spectator_view.system())
+            .add_system(update_spectator_hud.system());
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    asset_server: Res<AssetServer>
+) {
+    let window = windows.get_primary().unwrap();
+
+    commands
+    .spawn_bundle(TextBundle {
+        style: Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                bottom: Val::Px(10.0),
+                left: Val::Px(window.width() / 2.0 - 200.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::with_section(
+            "",
+            TextStyle {
+                font: asset_server.load("fonts/font.ttf"),
+                font_size: 16.0,
+                color: Color::CYAN,
+            },
+            TextAlignment {
+                horizontal: HorizontalAlign::Center,
+                ..Default::default()
+            },
+        ),
+        ..Default::default()
+    })
+    .insert(SpectatorHudText);
+}
+
+// Nobody is holding a paddle when the computer plays every side of a local match, so whoever is
+// looking at the screen is a spectator. Networked matches fill the view in themselves.
+fn local_spectator_view(
+    app_state: Res<State<AppState>>,
+    rules: Res<GameRules>,
+    scoreboard: Res<Scoreboard>,
+    rally: Res<Rally>,
+    balls: Query<&Ball>,
+    mut view: ResMut<SpectatorView>,
+) {
+    let in_match = match app_state.current() {
+        AppState::Serve | AppState::Play | AppState::Done => true,
+        AppState::Start | AppState::History | AppState::Leaderboard | AppState::Achievements => false,
+        AppState::Online | AppState::Lobby => return,
+    };
+    let players = rules.mode.players();
+    let all_ai = players.iter().all(|player| !rules.controller(*player).is_human());
+
+    if !in_match || !all_ai {
+        view.0 = None;
+        return;
+    }
+
+    view.0 = Some(SpectatorInfo {
+        players: players
+            .iter()
+            .map(|player| (player_label(*player).to_string(), scoreboard.score(*player)))
+            .collect(),
+        rally: rally.0,
+        ball_speed: balls.iter().map(|ball| ball.velocity.length()).fold(0.0, f32::max),
+    });
+}
+
+fn update_spectator_hud(
+    view: Res<SpectatorView>,
+    mut query: Query<&mut Text, With<SpectatorHudText>>,
+) {
+    if let Ok(mut text) = query.single_mut() {
+        text.sections[0].value = match &view.0 {
+            Some(info) => {
+                // Two players read as a scoreline, more than that as a list.
+                let scores = match info.players.as_slice() {
+                    [(left, left_score), (right, right_score)] => {
+                        format!("{} {} - {} {}", left, left_score, right_score, right)
+                    }
+                    players => players
+                        .iter()
+                        .map(|(name, score)| format!("{} {}", name, score))
+                        .collect::<Vec<_>>()
+                        .join("   "),
+                };
+                format!("Spectating\n{}\nRally: {}   Ball Speed: {:.0}", scores, info.rally, info.ball_speed)
+            }
+            None => String::new(),
+        };
+    }
+}
@@ -0,0 +1,143 @@
+use bevy::prelude::*;
+
+use crate::{ball::{Ball, PaddleHit}, game_state::AppState, profile::Profiles, rules::{GameMode, GameRules}, score::{PointScored, Scoreboard}, LoadedAudio, Player};
+
+pub struct SquashPlugin;
+
+// Whether the run that just ended beat the profile's personal best.
+#[derive(Default)]
+struct SquashRun {
+    new_best: bool,
+}
+
+struct SquashText;
+
+impl Plugin for SquashPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<SquashRun>()
+            .add_startup_system(setup.system())
+            .add_system(update_squash_text.system())
+            .add_system_set(SystemSet::on_exit(AppState::Start).with_system(start_run.system()))
+            .add_system_set(SystemSet::on_update(AppState::Play).with_system(count_returns.system()))
+            .add_system_set(SystemSet::on_update(AppState::Play).with_system(score_squash.system()));
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    windows: Res<Windows>,
+) {
+    let window = windows.get_primary().unwrap();
+
+    commands
+    .spawn_bundle(TextBundle {
+        style: Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(40.0),
+                left: Val::Px(window.width() / 2.0 - 80.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::default(),
+        ..Default::default()
+    })
+    .insert(SquashText);
+}
+
+fn start_run(mut run: ResMut<SquashRun>) {
+    run.new_best = false;
+}
+
+// Every time the ball comes off the paddle is one more return. The paddle speeds the ball up on every
+// hit, so each return is harder than the last.
+fn count_returns(
+    mut paddle_hits: EventReader<PaddleHit>,
+    rules: Res<GameRules>,
+    mut scoreboard: ResMut<Scoreboard>,
+) {
+    for hit in paddle_hits.iter() {
+        if rules.mode == GameMode::Squash && hit.player == Player::Player1 {
+            scoreboard.player1 += 1;
+        }
+    }
+}
+
+// There's only the one ball, so the run is over as soon as it gets past the paddle. Only runs played
+// by hand count towards the personal best.
+fn score_squash(
+    mut commands: Commands,
+    scoreboard: Res<Scoreboard>,
+    mut profiles: ResMut<Profiles>,
+    mut run: ResMut<SquashRun>,
+    mut app_state: ResMut<State<AppState>>,
+    query: Query<(Entity, &Transform), With<Ball>>,
+    windows: Res<Windows>,
+    audio: Res<Audio>,
+    loaded_audio: Res<LoadedAudio>,
+    rules: Res<GameRules>,
+    mut points: EventWriter<PointScored>,
+) {
+    if rules.mode != GameMode::Squash {
+        return;
+    }
+    let window = windows.get_primary().unwrap();
+
+    let missed: Vec<Entity> = query
+        .iter()
+        .filter(|(_, transform)| transform.translation.x < -window.width() / 2.0)
+        .map(|(entity, _)| entity)
+        .collect();
+    if missed.is_empty() {
+        return;
+    }
+
+    for entity in missed {
+        commands.entity(entity).despawn();
+    }
+    audio.play(loaded_audio.score.clone());
+    points.send(PointScored { scorer: None });
+
+    // Profiles is only touched when the best was beaten, since any change to it writes the file out.
+    let returns = scoreboard.player1;
+    if rules.controller(Player::Player1).is_human() && returns > profiles.active().squash_best {
+        profiles.active_mut().squash_best = returns;
+        run.new_best = true;
+    }
+    app_state.set(AppState::Done).unwrap();
+}
+
+fn update_squash_text(
+    app_state: Res<State<AppState>>,
+    profiles: Res<Profiles>,
+    run: Res<SquashRun>,
+    rules: Res<GameRules>,
+    asset_server: Res<AssetServer>,
+    mut query: Query<&mut Text, With<SquashText>>,
+) {
+    if let Ok(mut text) = query.single_mut() {
+        let best = profiles.active().squash_best;
+        let value = match app_state.current() {
+            _ if rules.mode != GameMode::Squash => None,
+            AppState::Serve | AppState::Play => Some(format!("Personal best: {}", best)),
+            AppState::Done if run.new_best => Some("New personal best!".to_string()),
+            AppState::Done => Some(format!("Personal best: {}", best)),
+            _ => None,
+        };
+
+        text.sections = match value {
+            Some(value) => vec![TextSection {
+                value,
+                style: TextStyle {
+                    font: asset_server.load("fonts/font.ttf"),
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                },
+            }],
+            None => Vec::new(),
+        };
+    }
+}
@@ -0,0 +1,233 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{ball::PaddleHit, controller::PaddleControl, game_state::AppState, paddle::Paddle, menu::player_label, rules::{GameMode, GameRules}, score::{MatchResult, PointScored}, storage, Player, Server};
+
+pub struct StatsPlugin;
+
+/// Everything we keep track of during a single match. The per player arrays are indexed by Player::index.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MatchStats {
+    pub longest_rally: u32,
+    /// Paddle hits per point.
+    pub average_rally: f32,
+    /// In pixels per second, as the ball left a paddle.
+    pub max_ball_speed: f32,
+    pub hits: [u32; 4],
+    pub edge_hits: [u32; 4],
+    pub points_won_on_serve: [u32; 4],
+    /// Every change in difficulty of the AIs that adjust themselves during the match.
+    #[serde(default)]
+    pub ai_difficulty: Vec<DifficultySample>,
+    #[serde(skip)]
+    current_rally: u32,
+    #[serde(skip)]
+    rallies: u32,
+    #[serde(skip)]
+    rally_hits: u32,
+}
+
+impl MatchStats {
+    fn end_rally(&mut self) {
+        self.longest_rally = self.longest_rally.max(self.current_rally);
+        self.rallies += 1;
+        self.rally_hits += self.current_rally;
+        self.average_rally = self.rally_hits as f32 / self.rallies as f32;
+        self.current_rally = 0;
+    }
+}
+
+/// How hard an adaptive AI was playing, from 0.0 to 1.0, from the given point of the match on.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DifficultySample {
+    pub player: Player,
+    /// Points played before the change.
+    pub point: u32,
+    pub difficulty: f32,
+}
+
+// Only changes bigger than this are logged, so the log doesn't fill up with tiny steps.
+const DIFFICULTY_LOG_STEP: f32 = 0.01;
+
+// Who served the point being played, since Server already points at the next server by the time the point is scored.
+struct Serving(Player);
+
+// What gets written out at the end of every match.
+#[derive(Serialize)]
+struct StatsExport<'a> {
+    finished_at: u64,
+    mode: GameMode,
+    winner: &'static str,
+    /// Final scores, in the same order as the per player stats.
+    scores: [u32; 4],
+    stats: &'a MatchStats,
+}
+
+struct StatsText;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<MatchStats>()
+            .insert_resource(Serving(Player::Player1))
+            .add_startup_system(setup.system())
+            .add_system(track_stats.system())
+            .add_system_set(SystemSet::on_update(AppState::Serve).with_system(log_ai_difficulty.system()))
+            .add_system_set(SystemSet::on_update(AppState::Play).with_system(log_ai_difficulty.system()))
+            .add_system(update_stats_text.system())
+            .add_system_set(SystemSet::on_exit(AppState::Start).with_system(reset_stats.system()))
+            .add_system_set(SystemSet::on_enter(AppState::Play).with_system(remember_server.system()));
+    }
+}
+
+fn setup(
+    mut commands: Commands,
+    windows: Res<Windows>,
+) {
+    let window = windows.get_primary().unwrap();
+
+    commands
+    .spawn_bundle(TextBundle {
+        style: Style {
+            align_self: AlignSelf::FlexEnd,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(70.0),
+                left: Val::Px(window.width() / 2.0 - 180.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::default(),
+        ..Default::default()
+    })
+    .insert(StatsText);
+}
+
+fn reset_stats(mut stats: ResMut<MatchStats>) {
+    *stats = MatchStats::default();
+}
+
+fn remember_server(
+    server: Res<Server>,
+    mut serving: ResMut<Serving>,
+) {
+    serving.0 = server.0;
+}
+
+// Hits, points and the result all come in as events, so the last point and the result of a match
+// are always counted together, in that order, before the stats are exported.
+fn track_stats(
+    mut paddle_hits: EventReader<PaddleHit>,
+    mut points: EventReader<PointScored>,
+    mut match_results: EventReader<MatchResult>,
+    serving: Res<Serving>,
+    mut stats: ResMut<MatchStats>,
+) {
+    for hit in paddle_hits.iter() {
+        let index = hit.player.index();
+        stats.hits[index] += 1;
+        if hit.edge {
+            stats.edge_hits[index] += 1;
+        }
+        stats.max_ball_speed = stats.max_ball_speed.max(hit.speed);
+        stats.current_rally += 1;
+    }
+
+    for point in points.iter() {
+        if point.scorer == Some(serving.0) {
+            stats.points_won_on_serve[serving.0.index()] += 1;
+        }
+        stats.end_rally();
+    }
+
+    for result in match_results.iter() {
+        let finished_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        storage::export_json(
+            &format!("stats/match-{}.json", finished_at),
+            &StatsExport {
+                finished_at,
+                mode: result.mode,
+                winner: player_label(result.winner),
+                scores: [result.player1, result.player2, result.player3, result.player4],
+                stats: &stats,
+            },
+        );
+    }
+}
+
+// Writes down the difficulty of every adaptive AI at the start of the match and whenever it changes.
+fn log_ai_difficulty(
+    query: Query<(&Paddle, &PaddleControl)>,
+    mut stats: ResMut<MatchStats>,
+) {
+    for (paddle, control) in query.iter() {
+        let difficulty = match control.0.difficulty() {
+            Some(difficulty) => difficulty,
+            None => continue,
+        };
+        let last = stats.ai_difficulty.iter().rev().find(|sample| sample.player == paddle.player);
+        if last.is_none_or(|last| (last.difficulty - difficulty).abs() >= DIFFICULTY_LOG_STEP) {
+            let point = stats.rallies;
+            stats.ai_difficulty.push(DifficultySample { player: paddle.player, point, difficulty });
+        }
+    }
+}
+
+fn update_stats_text(
+    app_state: Res<State<AppState>>,
+    stats: Res<MatchStats>,
+    rules: Res<GameRules>,
+    asset_server: Res<AssetServer>,
+    mut query: Query<&mut Text, With<StatsText>>,
+) {
+    if let Ok(mut text) = query.single_mut() {
+        // The stats are only shown once the match is over.
+        if *app_state.current() != AppState::Done {
+            text.sections.clear();
+            return;
+        }
+
+        let mut lines = vec![
+            format!("Longest rally: {}", stats.longest_rally),
+            format!("Average rally: {:.1}", stats.average_rally),
+            format!("Fastest ball: {:.0} px/s", stats.max_ball_speed),
+        ];
+        lines.extend(rules.mode.players().iter().map(|player| {
+            let index = player.index();
+            format!(
+                "{}: {} hits, {} off the edge, {} won on serve",
+                player_label(*player),
+                stats.hits[index],
+                stats.edge_hits[index],
+                stats.points_won_on_serve[index],
+            )
+        }));
+        lines.extend(rules.mode.players().iter().filter_map(|player| {
+            let mut samples = stats.ai_difficulty.iter().filter(|sample| sample.player == *player);
+            let first = samples.next()?;
+            let last = samples.next_back().unwrap_or(first);
+            Some(format!(
+                "{} adaptive AI: started at {:.0}%, ended at {:.0}%",
+                player_label(*player),
+                first.difficulty * 100.0,
+                last.difficulty * 100.0,
+            ))
+        }));
+
+        let font = asset_server.load("fonts/font.ttf");
+        text.sections = lines
+            .into_iter()
+            .map(|line| TextSection {
+                value: format!("{}\n", line),
+                style: TextStyle {
+                    font: font.clone(),
+                    font_size: 16.0,
+                    color: Color::WHITE,
+                },
+            })
+            .collect();
+    }
+}
@@ -0,0 +1,68 @@
+use std::{fs, path::PathBuf};
+
+use bevy::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+
+// Everything we keep between runs lives in one folder: PONG50_DATA if it is set, otherwise
+// .pong50 in the home directory, otherwise next to wherever the game was started from.
+pub fn data_dir() -> PathBuf {
+    if let Some(directory) = std::env::var_os("PONG50_DATA") {
+        return PathBuf::from(directory);
+    }
+    match std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+        Some(home) => PathBuf::from(home).join(".pong50"),
+        None => PathBuf::from("pong50-data"),
+    }
+}
+
+/// Reads a file from the data folder. A missing file is normal on the first run, so that quietly
+/// gives the default. Anything else that goes wrong is logged and also gives the default.
+pub fn load<T: DeserializeOwned + Default>(file_name: &str) -> T {
+    let path = data_dir().join(file_name);
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return T::default(),
+        Err(error) => {
+            warn!("Could not read {:?}: {}", path, error);
+            return T::default();
+        }
+    };
+
+    ron::de::from_str(&contents).unwrap_or_else(|error| {
+        warn!("Could not understand {:?}, starting over: {}", path, error);
+        T::default()
+    })
+}
+
+/// Writes a file to the data folder, logging rather than failing, since losing a save is no reason to stop playing.
+pub fn save<T: Serialize>(file_name: &str, value: &T) {
+    write(file_name, ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default()).map_err(|error| error.to_string()));
+}
+
+/// Like save, but as JSON so other programs can read it. The file name can include sub folders.
+pub fn export_json<T: Serialize>(file_name: &str, value: &T) {
+    write(file_name, serde_json::to_string_pretty(value).map_err(|error| error.to_string()));
+}
+
+fn write(file_name: &str, contents: Result<String, String>) {
+    let path = data_dir().join(file_name);
+    let result = contents.and_then(|contents| {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(|error| error.to_string())?;
+        }
+        fs::write(&path, contents).map_err(|error| error.to_string())
+    });
+
+    if let Err(error) = result {
+        warn!("Could not save {:?}: {}", path, error);
+    }
+}
+
+/// Deletes a file from the data folder. It already being gone is fine.
+pub fn remove(file_name: &str) {
+    let path = data_dir().join(file_name);
+    match fs::remove_file(&path) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => warn!("Could not delete {:?}: {}", path, error),
+        _ => (),
+    }
+}
@@ -0,0 +1,667 @@
+// Pits AIs against each other on the headless simulation, many seeded matches at a time and as fast as
+// the machine allows. Entrants are named on the command line: the built in AIs, a Rhai script with an
+// intent hook, or an outside bot speaking the bot protocol.
+//
+// Every match gets its own seed, counting up from the tournament seed, so running the same tournament
+// twice gives the same results as long as the entrants themselves don't involve chance.
+
+use std::{fmt::Write as _, fs, path::PathBuf, sync::Arc, time::Duration};
+
+use serde::Serialize;
+
+use crate::{
+    bot::{BotBall, BotEndpoint, BotLink, BotObservation, BotPaddle, DEFAULT_BOT_TIMEOUT},
+    gym::chase_input,
+    scripting::{sandboxed_engine, Script, ScriptBall, ScriptPaddle},
+    simulation::{MatchRules, PaddleInput, SimulationEvent, SimulationState, ARENA_HEIGHT, ARENA_WIDTH, BALL_SIZE, PADDLE_HEIGHT, PADDLE_WIDTH, PADDLE_X},
+};
+
+/// The AIs that can be named without a prefix. `chase` can also be given a slowdown, like `chase:0.3`.
+pub const BUILTIN_AIS: [&str; 3] = ["idle", "chase", "predictive"];
+
+const DEFAULT_MATCHES: u32 = 10;
+// A match between two AIs that never miss could go on forever.
+const DEFAULT_MAX_TICKS: u32 = 60 * 60 * 10;
+// For 95% confidence intervals.
+const Z_95: f64 = 1.96;
+
+/// Plays one paddle of the simulation. Side 0 is the left paddle and 1 the right one.
+pub trait TournamentAi {
+    fn input(&mut self, state: &SimulationState, side: usize) -> PaddleInput;
+}
+
+struct IdleAi;
+
+impl TournamentAi for IdleAi {
+    fn input(&mut self, _state: &SimulationState, _side: usize) -> PaddleInput {
+        0
+    }
+}
+
+// The game's chasing AI. The slowdown works like the AI slowdown handicap, see gym::OpponentPolicy.
+struct ChaseAi {
+    slowdown: f32,
+    progress: f32,
+}
+
+impl TournamentAi for ChaseAi {
+    fn input(&mut self, state: &SimulationState, side: usize) -> PaddleInput {
+        self.progress += 1.0 - self.slowdown;
+        if self.progress < 1.0 {
+            return 0;
+        }
+        self.progress -= 1.0;
+        chase_input(state.paddles[side], state.ball_position[1])
+    }
+}
+
+// Works out where the ball will reach its paddle, bounces off the walls included, and waits there.
+// It goes back to the middle while the ball is heading away.
+struct PredictiveAi;
+
+impl TournamentAi for PredictiveAi {
+    fn input(&mut self, state: &SimulationState, side: usize) -> PaddleInput {
+        let paddle_x = paddle_x(side);
+        let [x, y] = state.ball_position;
+        let [vx, vy] = state.ball_velocity;
+        let time = if vx != 0.0 { (paddle_x - x) / vx } else { -1.0 };
+        let target = if time > 0.0 { reflect(y + vy * time, ARENA_HEIGHT / 2.0 - BALL_SIZE / 2.0) } else { 0.0 };
+
+        let paddle = state.paddles[side];
+        if paddle + PADDLE_HEIGHT / 8.0 < target {
+            1
+        } else if paddle - PADDLE_HEIGHT / 8.0 > target {
+            -1
+        } else {
+            0
+        }
+    }
+}
+
+// Folds a position that went past the walls at -limit and limit back inside, the way bouncing would.
+fn reflect(position: f32, limit: f32) -> f32 {
+    let span = 2.0 * limit;
+    let folded = (position + limit).rem_euclid(2.0 * span);
+    let inside = if folded > span { 2.0 * span - folded } else { folded };
+    inside - limit
+}
+
+fn paddle_x(side: usize) -> f32 {
+    if side == 0 { -PADDLE_X } else { PADDLE_X }
+}
+
+// Scripts and bots answer with an intent from -1.0 to 1.0, the simulation only knows full speed or nothing.
+fn input_from_intent(intent: f32) -> PaddleInput {
+    if intent >= 0.5 {
+        1
+    } else if intent <= -0.5 {
+        -1
+    } else {
+        0
+    }
+}
+
+// The intent hook of a script. A script that fails is given up on and stands still from then on.
+struct ScriptAi {
+    script: Option<Script>,
+}
+
+impl TournamentAi for ScriptAi {
+    fn input(&mut self, state: &SimulationState, side: usize) -> PaddleInput {
+        let script = match &self.script {
+            Some(script) => script,
+            None => return 0,
+        };
+
+        let paddle = |player: usize| ScriptPaddle { player, position: [paddle_x(player), state.paddles[player]], size: [PADDLE_WIDTH, PADDLE_HEIGHT] };
+        let ball = ScriptBall { position: state.ball_position, velocity: state.ball_velocity, last_hit: None };
+        let scores = [state.scores[0], state.scores[1], 0, 0];
+
+        match script.intent(&paddle(side), &[ball], &[paddle(0), paddle(1)], scores) {
+            Some(Ok(intent)) => input_from_intent(intent),
+            Some(Err(error)) => {
+                eprintln!("Script {} stopped, {}", script.name, error);
+                self.script = None;
+                0
+            }
+            None => 0,
+        }
+    }
+}
+
+struct BotAi {
+    link: BotLink,
+}
+
+impl TournamentAi for BotAi {
+    fn input(&mut self, state: &SimulationState, side: usize) -> PaddleInput {
+        let paddle = |player: usize| BotPaddle { player, position: [paddle_x(player), state.paddles[player]], size: [PADDLE_WIDTH, PADDLE_HEIGHT] };
+        input_from_intent(self.link.exchange(BotObservation {
+            tick: 0,
+            you: side,
+            arena: [ARENA_WIDTH, ARENA_HEIGHT],
+            balls: vec![BotBall { position: state.ball_position, velocity: state.ball_velocity }],
+            paddles: vec![paddle(0), paddle(1)],
+            scores: [state.scores[0], state.scores[1], 0, 0],
+        }))
+    }
+}
+
+/// Starts the AI an entrant name stands for: `idle`, `chase`, `chase:0.3`, `predictive`, `script:path/to/ai.rhai`,
+/// `bot:./my-bot --flag` or `bot:tcp:127.0.0.1:7200`.
+pub fn registered_ai(name: &str, bot_timeout: Duration) -> Result<Box<dyn TournamentAi>, String> {
+    let (kind, detail) = name.split_once(':').unwrap_or((name, ""));
+    Ok(match kind {
+        "idle" => Box::new(IdleAi),
+        "chase" => {
+            let slowdown = if detail.is_empty() { 0.0 } else { parse(detail)? };
+            if !(0.0..1.0).contains(&slowdown) {
+                return Err(format!("the chase slowdown goes from 0.0 up to 1.0, not {}", slowdown));
+            }
+            Box::new(ChaseAi { slowdown, progress: 0.0 })
+        }
+        "predictive" => Box::new(PredictiveAi),
+        "script" => {
+            let source = fs::read_to_string(detail).map_err(|error| format!("could not read {}: {}", detail, error))?;
+            let script = Script::compile(&Arc::new(sandboxed_engine()), detail, &source)?;
+            if !script.has_hook("intent") {
+                return Err(format!("{} has no intent hook", detail));
+            }
+            Box::new(ScriptAi { script: Some(script) })
+        }
+        "bot" => {
+            let endpoint = match detail.strip_prefix("tcp:") {
+                Some(address) => BotEndpoint::Tcp(address.parse().map_err(|_| format!("could not understand {:?}", address))?),
+                None if !detail.trim().is_empty() => BotEndpoint::Process(detail.split_whitespace().map(str::to_string).collect()),
+                None => return Err("bot: needs a program to run".to_string()),
+            };
+            let link = BotLink::open(&endpoint, bot_timeout).map_err(|error| format!("could not start {}: {}", name, error))?;
+            Box::new(BotAi { link })
+        }
+        _ => return Err(format!("unknown AI {:?}, try one of {}, script:file or bot:program", name, BUILTIN_AIS.join(", "))),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// Every entrant plays every other one.
+    RoundRobin,
+    /// Single elimination, seeded in the order the entrants were given. The winner of each pairing goes through.
+    Bracket,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TournamentConfig {
+    pub format: Format,
+    /// The AI names, see registered_ai.
+    pub entrants: Vec<String>,
+    /// Matches every pairing plays. Entrants swap sides after every match.
+    pub matches: u32,
+    pub seed: u64,
+    pub rules: MatchRules,
+    /// A match still going after this many ticks is left unfinished.
+    pub max_ticks: u32,
+    pub bot_timeout: Duration,
+    pub csv: Option<PathBuf>,
+    pub json: Option<PathBuf>,
+}
+
+impl TournamentConfig {
+    /// `pong50-tournament [--format round-robin|bracket] [--matches 10] [--seed 0] [--victory 2] [--max-ticks 36000]
+    /// [--bot-timeout 10] [--csv standings.csv] [--json results.json] entrant entrant...`
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<TournamentConfig, String> {
+        let mut config = TournamentConfig::default();
+
+        let mut args = args.skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+            match arg.as_str() {
+                "--format" => {
+                    config.format = match value("--format")?.as_str() {
+                        "round-robin" => Format::RoundRobin,
+                        "bracket" => Format::Bracket,
+                        other => return Err(format!("--format is round-robin or bracket, not {}", other)),
+                    }
+                }
+                "--matches" => config.matches = parse(&value("--matches")?)?,
+                "--seed" => config.seed = parse(&value("--seed")?)?,
+                "--victory" => config.rules.victory_score = parse(&value("--victory")?)?,
+                "--max-ticks" => config.max_ticks = parse(&value("--max-ticks")?)?,
+                "--bot-timeout" => config.bot_timeout = Duration::from_millis(parse(&value("--bot-timeout")?)?),
+                "--csv" => config.csv = Some(PathBuf::from(value("--csv")?)),
+                "--json" => config.json = Some(PathBuf::from(value("--json")?)),
+                other if other.starts_with("--") => return Err(format!("unknown argument {}", other)),
+                entrant => config.entrants.push(entrant.to_string()),
+            }
+        }
+
+        if config.entrants.len() < 2 {
+            return Err("a tournament needs at least two entrants".to_string());
+        }
+        if config.matches == 0 {
+            return Err("--matches needs to be at least 1".to_string());
+        }
+        Ok(config)
+    }
+}
+
+impl Default for TournamentConfig {
+    fn default() -> Self {
+        TournamentConfig {
+            format: Format::RoundRobin,
+            entrants: Vec::new(),
+            matches: DEFAULT_MATCHES,
+            seed: 0,
+            rules: MatchRules::default(),
+            max_ticks: DEFAULT_MAX_TICKS,
+            bot_timeout: DEFAULT_BOT_TIMEOUT,
+            csv: None,
+            json: None,
+        }
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("could not understand {:?}", value))
+}
+
+/// A single match. Entrants are given by name.
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchRecord {
+    pub left: String,
+    pub right: String,
+    pub seed: u64,
+    /// Left first.
+    pub scores: [u32; 2],
+    /// None when the match ran out of ticks.
+    pub winner: Option<String>,
+    pub ticks: u32,
+}
+
+/// Two entrants meeting in a bracket. Without an opponent the entrant had a bye.
+#[derive(Debug, Clone, Serialize)]
+pub struct Pairing {
+    pub first: String,
+    pub second: Option<String>,
+    pub wins: [u32; 2],
+    pub winner: String,
+}
+
+/// How an entrant did over the whole tournament.
+#[derive(Debug, Clone, Serialize)]
+pub struct Standing {
+    pub name: String,
+    pub played: u32,
+    pub wins: u32,
+    pub losses: u32,
+    pub unfinished: u32,
+    pub win_rate: f64,
+    /// The 95% Wilson interval around the win rate.
+    pub win_rate_low: f64,
+    pub win_rate_high: f64,
+    /// Points won minus points lost, on average per match.
+    pub point_differential: f64,
+    /// Half the width of the 95% interval around the point differential. 0 with fewer than two matches.
+    pub point_differential_margin: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TournamentReport {
+    pub format: Format,
+    pub seed: u64,
+    pub matches_per_pairing: u32,
+    pub victory_score: u32,
+    /// Best first.
+    pub standings: Vec<Standing>,
+    /// Only for brackets, one list of pairings per round.
+    pub rounds: Vec<Vec<Pairing>>,
+    pub champion: Option<String>,
+    pub matches: Vec<MatchRecord>,
+}
+
+struct Entrant {
+    name: String,
+    ai: Box<dyn TournamentAi>,
+}
+
+/// Starts every entrant and plays the whole tournament.
+pub fn run(config: &TournamentConfig) -> Result<TournamentReport, String> {
+    let mut entrants = Vec::new();
+    for (index, name) in config.entrants.iter().enumerate() {
+        let ai = registered_ai(name, config.bot_timeout)?;
+        // The same AI can be entered more than once, but every entrant needs a name of its own.
+        let earlier = config.entrants[..index].iter().filter(|other| *other == name).count();
+        let name = if earlier == 0 { name.clone() } else { format!("{} #{}", name, earlier + 1) };
+        entrants.push(Entrant { name, ai });
+    }
+
+    let mut tournament = Tournament { config, entrants, matches: Vec::new(), results: Vec::new() };
+    let (rounds, champion) = match config.format {
+        Format::RoundRobin => {
+            for first in 0..tournament.entrants.len() {
+                for second in first + 1..tournament.entrants.len() {
+                    tournament.play_pairing(first, second);
+                }
+            }
+            (Vec::new(), None)
+        }
+        Format::Bracket => {
+            let (rounds, champion) = tournament.play_bracket();
+            (rounds, Some(champion))
+        }
+    };
+
+    Ok(TournamentReport {
+        format: config.format,
+        seed: config.seed,
+        matches_per_pairing: config.matches,
+        victory_score: config.rules.victory_score,
+        standings: tournament.standings(),
+        rounds,
+        champion,
+        matches: tournament.matches,
+    })
+}
+
+// The winner's index, or None, and the score from each entrant's point of view.
+struct MatchResult {
+    entrants: [usize; 2],
+    winner: Option<usize>,
+    points: [u32; 2],
+}
+
+struct Tournament<'a> {
+    config: &'a TournamentConfig,
+    entrants: Vec<Entrant>,
+    matches: Vec<MatchRecord>,
+    results: Vec<MatchResult>,
+}
+
+impl Tournament<'_> {
+    // Runs one match to the end, or until it runs out of ticks.
+    fn play_match(&mut self, left: usize, right: usize) -> MatchResult {
+        let seed = self.config.seed.wrapping_add(self.matches.len() as u64);
+        let mut state = SimulationState::with_rules(seed, self.config.rules);
+        let mut winner = None;
+        let mut ticks = 0;
+
+        while ticks < self.config.max_ticks && winner.is_none() {
+            let inputs = [self.entrants[left].ai.input(&state, 0), self.entrants[right].ai.input(&state, 1)];
+            for event in state.step(inputs) {
+                if let SimulationEvent::MatchOver(side) = event {
+                    winner = Some(if side == 0 { left } else { right });
+                }
+            }
+            ticks += 1;
+        }
+
+        self.matches.push(MatchRecord {
+            left: self.entrants[left].name.clone(),
+            right: self.entrants[right].name.clone(),
+            seed,
+            scores: state.scores,
+            winner: winner.map(|winner| self.entrants[winner].name.clone()),
+            ticks,
+        });
+        MatchResult { entrants: [left, right], winner, points: state.scores }
+    }
+
+    // Every match of a pairing, swapping sides each time. Returns the wins and points of each entrant.
+    fn play_pairing(&mut self, first: usize, second: usize) -> ([u32; 2], [u32; 2]) {
+        let mut wins = [0, 0];
+        let mut points = [0, 0];
+        for index in 0..self.config.matches {
+            let result = if index % 2 == 0 { self.play_match(first, second) } else { self.play_match(second, first) };
+            for (slot, entrant) in [first, second].iter().enumerate() {
+                let side = if result.entrants[0] == *entrant { 0 } else { 1 };
+                points[slot] += result.points[side];
+                if result.winner == Some(*entrant) {
+                    wins[slot] += 1;
+                }
+            }
+            self.results.push(result);
+        }
+        (wins, points)
+    }
+
+    // Seeds are paired first against last, with byes for the top seeds when the field isn't a power of
+    // two. A drawn pairing goes to whoever won more points, and then to the better seed.
+    fn play_bracket(&mut self) -> (Vec<Vec<Pairing>>, String) {
+        let mut field: Vec<usize> = (0..self.entrants.len()).collect();
+        let mut rounds = Vec::new();
+
+        while field.len() > 1 {
+            let size = field.len().next_power_of_two();
+            let mut slots: Vec<Option<usize>> = field.iter().copied().map(Some).collect();
+            slots.resize(size, None);
+
+            let mut round = Vec::new();
+            let mut next = Vec::new();
+            for index in 0..size / 2 {
+                let first = slots[index].expect("the top half of the bracket is never a bye");
+                let (winner, second, wins) = match slots[size - 1 - index] {
+                    Some(second) => {
+                        let (wins, points) = self.play_pairing(first, second);
+                        let first_through = (wins[0], points[0]) >= (wins[1], points[1]);
+                        (if first_through { first } else { second }, Some(second), wins)
+                    }
+                    None => (first, None, [0, 0]),
+                };
+                round.push(Pairing {
+                    first: self.entrants[first].name.clone(),
+                    second: second.map(|second| self.entrants[second].name.clone()),
+                    wins,
+                    winner: self.entrants[winner].name.clone(),
+                });
+                next.push(winner);
+            }
+
+            // Keep the seeding order for the next round.
+            next.sort_unstable();
+            field = next;
+            rounds.push(round);
+        }
+
+        (rounds, self.entrants[field[0]].name.clone())
+    }
+
+    fn standings(&self) -> Vec<Standing> {
+        let mut standings: Vec<Standing> = (0..self.entrants.len())
+            .map(|entrant| {
+                let mut wins = 0;
+                let mut losses = 0;
+                let mut unfinished = 0;
+                let mut differentials = Vec::new();
+                for result in self.results.iter().filter(|result| result.entrants.contains(&entrant)) {
+                    let side = if result.entrants[0] == entrant { 0 } else { 1 };
+                    match result.winner {
+                        Some(winner) if winner == entrant => wins += 1,
+                        Some(_) => losses += 1,
+                        None => unfinished += 1,
+                    }
+                    differentials.push(result.points[side] as f64 - result.points[1 - side] as f64);
+                }
+
+                let played = wins + losses + unfinished;
+                let win_rate = if played > 0 { wins as f64 / played as f64 } else { 0.0 };
+                let (win_rate_low, win_rate_high) = wilson_interval(wins, played);
+                let (point_differential, point_differential_margin) = mean_and_margin(&differentials);
+                Standing {
+                    name: self.entrants[entrant].name.clone(),
+                    played,
+                    wins,
+                    losses,
+                    unfinished,
+                    win_rate,
+                    win_rate_low,
+                    win_rate_high,
+                    point_differential,
+                    point_differential_margin,
+                }
+            })
+            .collect();
+
+        standings.sort_by(|a, b| {
+            b.win_rate
+                .total_cmp(&a.win_rate)
+                .then(b.point_differential.total_cmp(&a.point_differential))
+        });
+        standings
+    }
+}
+
+// The Wilson score interval, which behaves itself near 0% and 100% and with only a few matches.
+fn wilson_interval(wins: u32, played: u32) -> (f64, f64) {
+    if played == 0 {
+        return (0.0, 1.0);
+    }
+    let n = played as f64;
+    let p = wins as f64 / n;
+    let z2 = Z_95 * Z_95;
+    let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+    let half = Z_95 * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / (1.0 + z2 / n);
+    ((center - half).max(0.0), (center + half).min(1.0))
+}
+
+fn mean_and_margin(values: &[f64]) -> (f64, f64) {
+    if values.is_empty() {
+        return (0.0, 0.0);
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    if values.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, Z_95 * (variance / n).sqrt())
+}
+
+impl TournamentReport {
+    /// The standings as a table for the terminal.
+    pub fn table(&self) -> String {
+        let width = self.standings.iter().map(|standing| standing.name.len()).max().unwrap_or(0).max("Entrant".len());
+        let mut table = format!(
+            "{:>3}  {:<width$}  {:>6}  {:>4}  {:>4}  {:>4}  {:>6}  {:>15}  {:>14}\n",
+            "#", "Entrant", "Played", "Won", "Lost", "Left", "Win %", "95% CI", "Point diff",
+            width = width
+        );
+        for (place, standing) in self.standings.iter().enumerate() {
+            let _ = writeln!(
+                table,
+                "{:>3}  {:<width$}  {:>6}  {:>4}  {:>4}  {:>4}  {:>5.1}%  {:>6.1}% - {:>5.1}%  {:>+7.2} ± {:<4.2}",
+                place + 1,
+                standing.name,
+                standing.played,
+                standing.wins,
+                standing.losses,
+                standing.unfinished,
+                standing.win_rate * 100.0,
+                standing.win_rate_low * 100.0,
+                standing.win_rate_high * 100.0,
+                standing.point_differential,
+                standing.point_differential_margin,
+                width = width
+            );
+        }
+        table
+    }
+
+    /// The standings, one row per entrant.
+    pub fn csv(&self) -> String {
+        let mut csv = "place,name,played,wins,losses,unfinished,win_rate,win_rate_low,win_rate_high,point_differential,point_differential_margin\n".to_string();
+        for (place, standing) in self.standings.iter().enumerate() {
+            let _ = writeln!(
+                csv,
+                "{},\"{}\",{},{},{},{},{:.4},{:.4},{:.4},{:.4},{:.4}",
+                place + 1,
+                standing.name.replace('"', "\"\""),
+                standing.played,
+                standing.wins,
+                standing.losses,
+                standing.unfinished,
+                standing.win_rate,
+                standing.win_rate_low,
+                standing.win_rate_high,
+                standing.point_differential,
+                standing.point_differential_margin
+            );
+        }
+        csv
+    }
+
+    /// Everything, every match included.
+    pub fn json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|error| error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn wilson_interval_at_the_edges() {
+        let z2 = Z_95 * Z_95;
+        assert_eq!(wilson_interval(0, 0), (0.0, 1.0));
+
+        let (low, high) = wilson_interval(0, 10);
+        assert!(close(low, 0.0));
+        assert!(close(high, z2 / (10.0 + z2)));
+
+        let (low, high) = wilson_interval(10, 10);
+        assert!(close(low, 10.0 / (10.0 + z2)));
+        assert!(close(high, 1.0));
+    }
+
+    #[test]
+    fn mean_and_margin_of_a_few_values() {
+        assert_eq!(mean_and_margin(&[]), (0.0, 0.0));
+        assert_eq!(mean_and_margin(&[3.0]), (3.0, 0.0));
+
+        // A sample variance of 2 over two values.
+        let (mean, margin) = mean_and_margin(&[1.0, 3.0]);
+        assert!(close(mean, 2.0));
+        assert!(close(margin, Z_95));
+    }
+
+    #[test]
+    fn bracket_of_five_gives_the_top_seeds_byes() {
+        let config = TournamentConfig {
+            format: Format::Bracket,
+            entrants: ["predictive", "chase", "idle", "idle", "chase:0.5"].iter().map(|name| name.to_string()).collect(),
+            matches: 2,
+            max_ticks: 60 * 60,
+            ..TournamentConfig::default()
+        };
+        let report = run(&config).unwrap();
+        let rounds = &report.rounds;
+        assert_eq!(rounds.iter().map(Vec::len).collect::<Vec<_>>(), [4, 2, 1]);
+
+        // Eight slots for five entrants: seeds 1 to 3 sit out the first round and 4 plays 5.
+        for (pairing, name) in rounds[0].iter().zip(["predictive", "chase", "idle"]) {
+            assert_eq!(pairing.first, name);
+            assert_eq!(pairing.second, None);
+            assert_eq!(pairing.winner, name);
+        }
+        assert_eq!(rounds[0][3].first, "idle #2");
+        assert_eq!(rounds[0][3].second.as_deref(), Some("chase:0.5"));
+
+        // The first seed meets whoever came through from the bottom, 2 meets 3.
+        assert_eq!(rounds[1][0].first, "predictive");
+        assert_eq!(rounds[1][0].second.as_ref(), Some(&rounds[0][3].winner));
+        assert_eq!((rounds[1][1].first.as_str(), rounds[1][1].second.as_deref()), ("chase", Some("idle")));
+
+        let mut finalists = vec![rounds[2][0].first.clone(), rounds[2][0].second.clone().unwrap()];
+        finalists.sort();
+        let mut semi_winners = vec![rounds[1][0].winner.clone(), rounds[1][1].winner.clone()];
+        semi_winners.sort();
+        assert_eq!(finalists, semi_winners);
+        assert_eq!(report.champion.as_ref(), Some(&rounds[2][0].winner));
+        assert_eq!(report.matches.len(), 2 * 4);
+    }
+}
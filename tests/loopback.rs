@@ -0,0 +1,55 @@
+// Two peers playing a rollback match against each other over localhost, each on its own thread like
+// they would be in their own game.
+
+use std::{net::{SocketAddr, UdpSocket}, thread, time::Duration};
+
+use pong50::{net::{NetConfig, RollbackSession, SessionStatus}, simulation::PaddleInput};
+
+const FRAMES: u32 = 600;
+const SEED: u64 = 11;
+
+fn free_address() -> SocketAddr {
+    UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+fn input(tick: u32, player: usize) -> PaddleInput {
+    ((tick / (17 + 4 * player as u32)) % 3) as PaddleInput - 1
+}
+
+// Plays a little past FRAMES, so the inputs the other peer needs to get there have all been sent.
+fn play(config: NetConfig) {
+    let player = config.local_player;
+    let mut session = RollbackSession::new(config).unwrap();
+
+    for tick in 0..FRAMES * 10 {
+        if session.state().frame >= FRAMES + 60 {
+            break;
+        }
+        session.advance(input(tick, player)).unwrap();
+        assert!(!matches!(session.status(), SessionStatus::Desynced { .. }), "player {} desynced", player + 1);
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    assert!(session.state().frame >= FRAMES, "player {} only got to frame {}", player + 1, session.state().frame);
+}
+
+#[test]
+fn peers_play_a_match_without_desyncing() {
+    let addresses = [free_address(), free_address()];
+    let peers: Vec<_> = (0..2)
+        .map(|player| {
+            let config = NetConfig {
+                local_addr: addresses[player],
+                remote_addr: addresses[1 - player],
+                local_player: player,
+                input_delay: 2,
+                seed: SEED,
+            };
+            thread::spawn(move || play(config))
+        })
+        .collect();
+
+    for peer in peers {
+        peer.join().unwrap();
+    }
+}